@@ -1,10 +1,12 @@
 use heck::ToUpperCamelCase;
 use proc_macro::TokenStream;
 use proc_macro2::{Span, TokenStream as TokenStream2};
-use quote::{quote, ToTokens};
+use quote::{format_ident, quote, ToTokens};
 use std::collections::HashSet;
 use syn::{
-    parse_macro_input, Error, Expr, FnArg, Ident, ImplItem, ItemImpl, Lit, Meta, MetaNameValue, Pat,
+    parse_macro_input, parse_quote, Attribute, Error, Expr, ExprLit, ExprPath, FnArg,
+    GenericArgument, Ident, ImplItem, ItemImpl, Lit, LitStr, Meta, MetaNameValue, Pat,
+    PathArguments, ReturnType, Type,
 };
 
 /// # Macro for Generating `ToolBox` Implementations
@@ -73,7 +75,14 @@ use syn::{
 /// #### 2.2. Requirements and Limitations
 ///
 /// - **Method Receiver**: Exposed tools must be methods that take `&self` as the first argument. Static methods are not supported.
-/// - **Return Type**: The return type must be `ToolResult` which is `Result<String, ToolError>`.
+/// - **Return Type**: The return type must be `ToolResult` which is `Result<String, ToolError>`, or
+///   `Result<ToolOutput, ToolError>` for tools suggesting follow-up actions.
+///   Both are reported by `ToolBox::call_tool_output`, `ToolBox::call_tool` returns only the content.
+///   Tools can also return `Result<T, ToolError>` with any other `T` implementing `serde::Serialize`,
+///   the value is then passed to the model as a JSON string. `String` results are passed unchanged.
+///   The error type can be any type implementing `Into<ToolError>`, e.g. a custom error enum or
+///   `anyhow::Error`. Errors are returned as converted, so the conversion decides whether they
+///   are fed back to the model (`ToolError::LLMError`) or treated as failures.
 /// - **Serializable Parameters**: All method parameters must be (de)serializable by `serde`.
 ///
 /// ### 3. Advanced Configuration
@@ -81,12 +90,28 @@ use syn::{
 /// The `#[tool(...)]` attribute gives you broad control over the configuration of declared tools.
 /// You can change any of the options using `name=value` pairs. The following options are supported:
 /// - `name`: Overrides the default tool name. This name must be unique within the toolbox.
+/// - `readonly`: Marks the tool as free of side effects, it is reported by `ToolBox::has_side_effects`.
+///   Tools without this flag are assumed to modify external state.
+/// - `terminal`: Marks the tool as the final action of a run, e.g. sending an email, it is reported
+///   by `ToolBox::is_terminal`. After it succeeds, the agent doesn't allow the model to call more tools.
+/// - `non_idempotent`: Marks the tool as unsafe to call again with the same arguments, e.g. making
+///   a payment, it is reported by `ToolBox::is_idempotent`. Failed calls of such tools are not retried.
+/// - `schema`: Replaces the generated parameter schema with a hand-written one, e.g. when a provider
+///   rejects schema generated by `schemars`. The value is either a JSON object written inline, or
+///   a path to a JSON file relative to the crate root. The schema is checked to be a valid JSON
+///   object during compilation.
+/// - `schema_with`: Path to a function returning parameter schema as `serde_json::Value`,
+///   e.g. `schema_with = "Self::search_schema"`. Use it when the schema is built at runtime.
+///
+/// Custom schema changes only what is sent to the model, arguments are still deserialized into
+/// the method parameters, so the schema must describe them.
 ///
 /// ### 4. Tool Arguments
 /// The tool's schema is generated based on the method's arguments, which is why they must be serializable.
 /// This is primarily syntactic sugar, as all arguments are copied into a new helper structure as serializable fields.
 /// This struct derives `serde::Serialize`, `serde::Deserialize`, and `schemars::JsonSchema` to handle argument
-/// serialization, deserialization, and schema generation.
+/// serialization, deserialization, and schema generation. Arguments which can't be deserialized
+/// are reported as `ToolError::InvalidArguments`, with the path of the invalid value.
 ///
 /// All attributes for the arguments will be moved from the method implementation to the newly generated arguments structure.
 /// This allows you to not only provide documentation for the purpose of an argument but also to modify its behavior using
@@ -94,6 +119,25 @@ use syn::{
 /// - [serde](https://serde.rs/field-attrs.html)
 /// - [schemars](https://graham.cool/schemars/examples/3-schemars_attrs/)
 ///
+/// Parameters of type `Option<T>` are optional, the model may omit them and the method receives
+/// `None`. Other parameters get a default value with `#[tool_param(default = ...)]`, the value is
+/// any expression of the parameter type, string literals are converted with `From`. Parameters
+/// with defaults are optional as well, and the default is included in the schema:
+///
+/// ```ignore
+/// /// Lists orders of the customer
+/// #[tool]
+/// fn list_orders(
+///     &self,
+///     customer: String,
+///     #[tool_param(default = 10)] limit: u32,
+///     #[tool_param(default = "newest")] sort: String,
+///     status: Option<String>,
+/// ) -> ToolResult {
+///     // ...
+/// }
+/// ```
+///
 /// # Examples
 ///
 /// ```ignore
@@ -131,8 +175,8 @@ use syn::{
 ///         Ok(format!("Result from tool three with special name and value: {}", value))
 ///     }
 ///
-///     /// This is a sync tool method example.
-///     #[tool]
+///     /// This is a sync tool method example, it is marked as free of side effects.
+///     #[tool(readonly)]
 ///     fn tool_sync(&self) -> ToolResult {
 ///          Ok("This is a synchronous tool result".to_string())
 ///     }
@@ -156,12 +200,68 @@ use syn::{
 ///     -   **`tools_definitions`**: This method returns a `Vec<Tool>`, providing the metadata for each exposed tool.
 ///     -   **`call_tool`**: This method acts as a dispatcher. It matches the `tool_name`,
 ///         deserializes the JSON `parameters` into the corresponding parameter struct,
-///         and invokes the actual method.
+///         and invokes the actual method. Missing (`null`) parameters are treated as an empty
+///         object, and tools without parameters ignore whatever the model provided.
+///
+/// ## Multiple `impl` Blocks
+///
+/// Tools of one struct can be split across multiple `impl` blocks, e.g. one per feature area.
+/// Additional blocks are marked with `#[toolbox(part = "...")]`, and the block generating the
+/// `ToolBox` implementation lists them with `#[toolbox(parts = [...])]`. Tool names have to be
+/// unique across all blocks, duplicates are reported during compilation.
+///
+/// ```ignore
+/// #[toolbox(part = "files")]
+/// impl MyToolBox {
+///     /// Reads a file
+///     #[tool(readonly)]
+///     fn read_file(&self, path: String) -> ToolResult {
+///         // ...
+///     }
+/// }
+///
+/// #[toolbox(parts = ["files"])]
+/// impl MyToolBox {
+///     /// Searches the web
+///     #[tool(readonly)]
+///     async fn search(&self, query: String) -> ToolResult {
+///         // ...
+///     }
+/// }
+/// ```
 #[proc_macro_attribute]
-pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn toolbox(attr: TokenStream, item: TokenStream) -> TokenStream {
     // Parse the original impl block
     let mut item_impl = parse_macro_input!(item as ItemImpl);
 
+    let mut part = None;
+    let mut parts = Vec::new();
+    let attr_parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("part") {
+            part = Some(part_ident(&meta.value()?.parse()?)?);
+            Ok(())
+        } else if meta.path.is_ident("parts") {
+            let Expr::Array(array) = meta.value()?.parse()? else {
+                return Err(meta.error("Expected parts = [\"...\", ...]"));
+            };
+            for name in &array.elems {
+                parts.push(part_ident(&lit_str(name)?)?);
+            }
+            Ok(())
+        } else {
+            Err(meta.error("Expected part = \"...\" or parts = [...] in toolbox attribute"))
+        }
+    });
+    parse_macro_input!(attr with attr_parser);
+    if part.is_some() && !parts.is_empty() {
+        return Error::new(
+            Span::call_site(),
+            "toolbox attribute accepts either part = \"...\" or parts = [...]",
+        )
+        .to_compile_error()
+        .into();
+    }
+
     let struct_name = &item_impl.self_ty;
     let struct_ident = match &**struct_name {
         syn::Type::Path(type_path) => type_path
@@ -181,6 +281,9 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let mut generated_code = TokenStream2::new();
     let mut tool_definitions = TokenStream2::new();
     let mut match_arms = TokenStream2::new();
+    let mut readonly_tools = Vec::new();
+    let mut terminal_tools = Vec::new();
+    let mut non_idempotent_tools = Vec::new();
 
     // TODO: Maybe we should use BTreeHash to preserve order of tools?
     let mut found_tools = HashSet::new();
@@ -206,6 +309,10 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
                 // Parse the #[tool] attribute for name = "..." using parse_args_with with Meta
                 let mut name_arg_found = false;
+                let mut readonly = false;
+                let mut terminal = false;
+                let mut non_idempotent = false;
+                let mut schema_override = None;
                 let parser = syn::punctuated::Punctuated::<Meta, syn::Token![,]>::parse_terminated;
                 if let Ok(args) = tool_attr.parse_args_with(parser) {
                     // Iterate over the parsed Meta items to find 'name'. #[tool(name = "...")]
@@ -242,11 +349,43 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
                                 tool_name = lit_str.value();
                                 name_arg_found = true;
                             }
+                            Meta::Path(path) if path.is_ident("readonly") => {
+                                readonly = true;
+                            }
+                            Meta::Path(path) if path.is_ident("terminal") => {
+                                terminal = true;
+                            }
+                            Meta::Path(path) if path.is_ident("non_idempotent") => {
+                                non_idempotent = true;
+                            }
+                            Meta::NameValue(name_value)
+                                if name_value.path.is_ident("schema")
+                                    || name_value.path.is_ident("schema_with") =>
+                            {
+                                if schema_override.is_some() {
+                                    return Error::new_spanned(
+                                        name_value.to_token_stream(),
+                                        "Duplicate schema argument in tool attribute",
+                                    )
+                                    .to_compile_error()
+                                    .into();
+                                }
+                                let schema = if name_value.path.is_ident("schema") {
+                                    schema_from_json(&name_value.value)
+                                } else {
+                                    schema_from_fn(&name_value.value)
+                                };
+                                match schema {
+                                    Ok(schema) => schema_override = Some(schema),
+                                    Err(err) => return err.to_compile_error().into(),
+                                }
+                            }
                             _ => {
-                                // Error: If arguments are present, they must be 'name = "..."'
+                                // Error: If arguments are present, they must be 'name = "..."', 'readonly',
+                                // 'terminal', 'non_idempotent', 'schema = "..."' or 'schema_with = "..."'
                                 return Error::new_spanned(
                                     arg_meta.to_token_stream(),
-                                    "Expected name = \"...\" in tool attribute",
+                                    "Expected name = \"...\", readonly, terminal, non_idempotent, schema = \"...\" or schema_with = \"...\" in tool attribute",
                                 )
                                 .to_compile_error()
                                 .into();
@@ -264,6 +403,15 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
                     .to_compile_error()
                     .into();
                 }
+                if readonly {
+                    readonly_tools.push(tool_name.clone());
+                }
+                if terminal {
+                    terminal_tools.push(tool_name.clone());
+                }
+                if non_idempotent {
+                    non_idempotent_tools.push(tool_name.clone());
+                }
 
                 // Extract doc comments for description from #[doc = "..."] attributes (handles /// and /* */) from method
                 let description = method
@@ -310,6 +458,7 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 );
                 let mut param_fields = TokenStream2::new();
                 let mut param_assignments = TokenStream2::new();
+                let mut param_defaults = TokenStream2::new();
 
                 for arg in method.sig.inputs.iter_mut() {
                     // self attribute are type FnArg::Receiver()
@@ -319,12 +468,6 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
                         // ...
                         let ty = pat_type.ty.clone();
 
-                        // Clone all attributes that will be moved to new structure
-                        let attrs = pat_type.attrs.clone();
-
-                        // Clean attributes for tool definition
-                        pat_type.attrs.clear();
-
                         let Pat::Ident(ref pat_ident) = *pat_type.pat else {
                             // Handle other patterns if necessary, or return an error
                             return Error::new_spanned(
@@ -336,6 +479,30 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
                         };
 
                         let arg_name = &pat_ident.ident;
+
+                        // Move all attributes except #[tool_param] to new structure
+                        let (tool_params, mut attrs): (Vec<Attribute>, Vec<Attribute>) = pat_type
+                            .attrs
+                            .drain(..)
+                            .partition(|attr| attr.path().is_ident("tool_param"));
+
+                        let default = match param_default(&tool_params) {
+                            Ok(default) => default,
+                            Err(err) => return err.to_compile_error().into(),
+                        };
+                        if let Some(default) = default {
+                            let default_fn = format_ident!("default_{}", arg_name);
+                            let default_path = format!("{params_struct_name}::{default_fn}");
+                            param_defaults.extend(quote! {
+                                fn #default_fn() -> #ty {
+                                    #default
+                                }
+                            });
+                            attrs.push(parse_quote! { #[serde(default = #default_path)] });
+                        } else if is_option(&ty) && !has_serde_default(&attrs) {
+                            // Missing optional parameters are `None`, the schema doesn't require them
+                            attrs.push(parse_quote! { #[serde(default)] });
+                        }
                         // TODO: Change pub to pub(crate), this structures will be used only inside generated code
                         param_fields.extend(quote! {
                             #(#attrs)* pub #arg_name: #ty,
@@ -358,9 +525,19 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
                        }
                     });
                 }
+                if !param_defaults.is_empty() {
+                    generated_code.extend(quote! {
+                        #[allow(clippy::all)]
+                        impl #params_struct_name {
+                            #param_defaults
+                        }
+                    });
+                }
 
                 // Add to tool definitions
-                let schema_token = if param_fields.is_empty() {
+                let schema_token = if let Some(schema) = schema_override {
+                    quote! { Some(#schema) }
+                } else if param_fields.is_empty() {
                     quote! { None }
                 } else {
                     // Use the generated parameter struct name for schemars::schema_for!
@@ -386,13 +563,11 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 // Add to match arms for call_tool
                 let mut method_call = TokenStream2::new();
 
+                // Tools without parameters ignore arguments, providers send `{}`, `null` or nothing
                 if !param_fields.is_empty() {
                     method_call.extend(quote! {
-                        let params: #params_struct_name = serde_json::from_value(parameters)
-                            .map_err(|e| {
-                                eprintln!("Tool parameter deserialization error for '{}': {:?}", #tool_name, e);
-                                ToolError::ExecutionError
-                            })?;
+                        let params: #params_struct_name =
+                            ::agentai::tool::parse_arguments(#tool_name, parameters)?;
                     });
                 }
 
@@ -402,9 +577,19 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 }
 
                 method_call.extend(quote! { .map_err(|e| {
-                    eprintln!("Tool execution error for '{}': {:?}", #tool_name, e);
-                    ToolError::ExecutionError
+                    let err: ToolError = ::core::convert::Into::into(e);
+                    eprintln!("Tool execution error for '{}': {:?}", #tool_name, err);
+                    err
                 }) });
+                if returns_serialized(&method.sig.output) {
+                    method_call.extend(quote! { .and_then(|value| {
+                        ::serde_json::to_string(&value)
+                            .map(::agentai::tool::ToolOutput::new)
+                            .map_err(|e| ToolError::Other(e.into()))
+                    }) });
+                } else {
+                    method_call.extend(quote! { .map(::core::convert::Into::into) });
+                }
 
                 match_arms.extend(quote! {
                     #tool_name => {
@@ -415,32 +600,132 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     }
 
-    if found_tools.is_empty() {
+    if found_tools.is_empty() && parts.is_empty() {
         return Error::new(Span::call_site(), "No #[tool] definition in impl block")
             .to_compile_error()
             .into();
     }
 
+    // Additional block generates tools of the part, they are used by the ToolBox implementation
+    if let Some(part) = part {
+        let (part_const, definitions_fn, call_fn) = part_items(&part);
+        let tool_names = found_tools.iter();
+        let part_impl = quote! {
+            #[allow(clippy::all)]
+            impl #struct_ident {
+                #[doc(hidden)]
+                pub(crate) const #part_const: ::agentai::tool::ToolBoxPart = ::agentai::tool::ToolBoxPart {
+                    tools: &[#(#tool_names),*],
+                    readonly: &[#(#readonly_tools),*],
+                    terminal: &[#(#terminal_tools),*],
+                    non_idempotent: &[#(#non_idempotent_tools),*],
+                };
+
+                #[doc(hidden)]
+                pub(crate) fn #definitions_fn(&self) -> Vec<Tool> {
+                    vec![
+                        #tool_definitions
+                    ]
+                }
+
+                #[doc(hidden)]
+                pub(crate) async fn #call_fn(
+                    &self,
+                    tool_name: String,
+                    parameters: serde_json::Value,
+                ) -> Result<::agentai::tool::ToolOutput, ToolError> {
+                    match tool_name.as_str() {
+                        #match_arms
+                        _ => Err(ToolError::NoToolFound(tool_name)),
+                    }
+                }
+            }
+        };
+        return quote! {
+            #item_impl
+
+            #part_impl
+
+            #generated_code
+        }
+        .into();
+    }
+
+    let (part_consts, definitions_fns, call_fns): (Vec<_>, Vec<_>, Vec<_>) =
+        parts.iter().map(part_items).collect();
+    // Tool names are checked during compilation, names of other blocks are known only to compiler
+    let duplicates_check = if parts.is_empty() {
+        TokenStream2::new()
+    } else {
+        let tool_names = found_tools.iter();
+        let message = format!("Duplicate tool name found in #[toolbox] blocks of {struct_ident}");
+        quote! {
+            const _: () = assert!(
+                !::agentai::tool::has_duplicate_tools(&[
+                    &[#(#tool_names),*],
+                    #(#struct_ident::#part_consts.tools),*
+                ]),
+                #message
+            );
+        }
+    };
+
     // Generate the ToolBox implementation
     let toolbox_impl = quote! {
         #[::async_trait::async_trait]
         impl ToolBox for #struct_ident {
 
             fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
-                Ok(vec![
+                #[allow(unused_mut)]
+                let mut tools = vec![
                     #tool_definitions
-                ])
+                ];
+                #(tools.extend(self.#definitions_fns());)*
+                Ok(tools)
             }
 
             async fn call_tool(&self, tool_name: String, parameters: serde_json::Value) -> ToolResult {
+                self.call_tool_output(tool_name, parameters).await.map(::core::convert::Into::into)
+            }
+
+            async fn call_tool_output(
+                &self,
+                tool_name: String,
+                parameters: serde_json::Value,
+            ) -> Result<::agentai::tool::ToolOutput, ToolError> {
                  match tool_name.as_str() {
                      #match_arms
+                     #(
+                         name if #struct_ident::#part_consts.tools.contains(&name) => {
+                             self.#call_fns(tool_name, parameters).await
+                         }
+                     )*
                      _ => {
                          Err(ToolError::NoToolFound(tool_name))
                      }
                  }
             }
+
+            fn has_side_effects(&self, tool_name: &str) -> bool {
+                const READONLY_TOOLS: &[&str] = &[#(#readonly_tools),*];
+                !READONLY_TOOLS.contains(&tool_name)
+                    #(&& !#struct_ident::#part_consts.readonly.contains(&tool_name))*
+            }
+
+            fn is_terminal(&self, tool_name: &str) -> bool {
+                const TERMINAL_TOOLS: &[&str] = &[#(#terminal_tools),*];
+                TERMINAL_TOOLS.contains(&tool_name)
+                    #(|| #struct_ident::#part_consts.terminal.contains(&tool_name))*
+            }
+
+            fn is_idempotent(&self, tool_name: &str) -> bool {
+                const NON_IDEMPOTENT_TOOLS: &[&str] = &[#(#non_idempotent_tools),*];
+                !NON_IDEMPOTENT_TOOLS.contains(&tool_name)
+                    #(&& !#struct_ident::#part_consts.non_idempotent.contains(&tool_name))*
+            }
         }
+
+        #duplicates_check
     };
 
     // Combine generated code, the ToolBox impl, and the modified original impl block
@@ -454,3 +739,166 @@ pub fn toolbox(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
     final_code.into()
 }
+
+/// Returns default value of the parameter from `#[tool_param(default = ...)]` attributes. String
+/// literals are converted into the parameter type, other expressions are used as written.
+fn param_default(attrs: &[Attribute]) -> Result<Option<TokenStream2>, Error> {
+    let mut default = None;
+    for attr in attrs {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("default") {
+                return Err(meta.error("Expected default = ... in tool_param attribute"));
+            }
+            if default.is_some() {
+                return Err(meta.error("Duplicate default in tool_param attribute"));
+            }
+            let value: Expr = meta.value()?.parse()?;
+            default = Some(match value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(_), ..
+                }) => quote! { ::core::convert::From::from(#value) },
+                _ => quote! { #value },
+            });
+            Ok(())
+        })?;
+    }
+    Ok(default)
+}
+
+/// Checks if the parameter type is `Option<T>`
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+/// Checks if the parameter already has `#[serde(default)]` or `#[serde(default = "...")]`
+fn has_serde_default(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        let mut default = false;
+        if attr.path().is_ident("serde") {
+            let _ = attr.parse_nested_meta(|meta| {
+                default |= meta.path.is_ident("default");
+                // Skip value of other arguments, e.g. rename = "..."
+                if meta.input.peek(syn::Token![=]) {
+                    meta.value()?.parse::<Expr>()?;
+                }
+                Ok(())
+            });
+        }
+        default
+    })
+}
+
+/// Checks if the tool returns `Result<T, _>` where `T` is serialized to JSON, results which are
+/// already text or `ToolOutput` are converted with `Into`
+fn returns_serialized(output: &ReturnType) -> bool {
+    let ReturnType::Type(_, ty) = output else {
+        return false;
+    };
+    let Type::Path(type_path) = &**ty else {
+        return false;
+    };
+    let Some(segment) = type_path.path.segments.last() else {
+        return false;
+    };
+    if segment.ident != "Result" {
+        // `ToolResult` or other alias, its value type is unknown
+        return false;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return false;
+    };
+    match args.args.first() {
+        Some(GenericArgument::Type(Type::Path(value))) => {
+            value.path.segments.last().is_none_or(|value| {
+                !["String", "ToolOutput", "ToolContent"].contains(&value.ident.to_string().as_str())
+            })
+        }
+        Some(GenericArgument::Type(_)) => true,
+        _ => false,
+    }
+}
+
+/// Checks that name of a toolbox part is an identifier
+fn part_ident(name: &LitStr) -> Result<Ident, Error> {
+    name.parse()
+        .map_err(|_| Error::new_spanned(name, "Toolbox part name must be an identifier"))
+}
+
+/// Returns names of items generated for a toolbox part: metadata constant, definitions and call
+/// functions
+fn part_items(part: &Ident) -> (Ident, Ident, Ident) {
+    let name = part.to_string();
+    (
+        format_ident!("__TOOLBOX_PART_{}", name.to_uppercase()),
+        format_ident!("__toolbox_part_{}_definitions", name.to_lowercase()),
+        format_ident!("__toolbox_part_{}_call", name.to_lowercase()),
+    )
+}
+
+/// Returns string literal value of the tool attribute argument
+fn lit_str(value: &Expr) -> Result<LitStr, Error> {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit_str),
+            ..
+        }) => Ok(lit_str.clone()),
+        _ => Err(Error::new_spanned(
+            value.to_token_stream(),
+            "Expected string literal in tool attribute",
+        )),
+    }
+}
+
+/// Generates schema from `schema = "..."` argument, JSON object written inline or path to a JSON
+/// file relative to the crate root. JSON is validated during compilation.
+fn schema_from_json(value: &Expr) -> Result<TokenStream2, Error> {
+    let lit_str = lit_str(value)?;
+    let value = lit_str.value();
+    let (json, tokens) = if value.trim_start().starts_with('{') {
+        (value, quote! { #lit_str })
+    } else {
+        let root = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+        let path = std::path::Path::new(&root).join(&value);
+        let json = std::fs::read_to_string(&path).map_err(|err| {
+            Error::new_spanned(
+                &lit_str,
+                format!("Failed to read schema file {}: {err}", path.display()),
+            )
+        })?;
+        // `include_str!` makes the crate rebuild when schema file changes
+        let path = path.to_string_lossy().into_owned();
+        (json, quote! { include_str!(#path) })
+    };
+    match serde_json::from_str::<serde_json::Value>(&json) {
+        Ok(serde_json::Value::Object(_)) => {}
+        Ok(_) => {
+            return Err(Error::new_spanned(
+                &lit_str,
+                "Tool schema must be a JSON object",
+            ))
+        }
+        Err(err) => {
+            return Err(Error::new_spanned(
+                &lit_str,
+                format!("Invalid tool schema: {err}"),
+            ))
+        }
+    }
+    Ok(quote! {
+        serde_json::from_str(#tokens).expect("tool schema is validated during compilation")
+    })
+}
+
+/// Generates schema from `schema_with = "..."` argument, path to a function returning the schema
+/// as `serde_json::Value`
+fn schema_from_fn(value: &Expr) -> Result<TokenStream2, Error> {
+    let path: ExprPath = lit_str(value)?.parse()?;
+    Ok(quote! { #path() })
+}
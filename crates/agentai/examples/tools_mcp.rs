@@ -1,9 +1,8 @@
+use agentai::output::Thinking;
 use agentai::tool::mcp::McpToolBox;
 use agentai::Agent;
 use anyhow::Result;
 use log::{info, LevelFilter};
-use schemars::JsonSchema;
-use serde::Deserialize;
 use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
 
 const SYSTEM: &str = "You are helpful assistant.";
@@ -31,21 +30,9 @@ async fn main() -> Result<()> {
     let mcp_tools =
         McpToolBox::new("uvx", ["mcp-server-time", "--local-timezone", "UTC"], None).await?;
 
-    let answer: Answer = agent.run(&model, question, Some(&mcp_tools)).await?;
+    let answer: Thinking<String> = agent.run(&model, question, Some(&mcp_tools)).await?;
 
     info!("{:#?}", answer);
 
     Ok(())
 }
-
-#[allow(dead_code)]
-#[derive(Deserialize, JsonSchema, Debug)]
-struct Answer {
-    // It is always good idea to include thinking field for LLM's debugging
-    /// In this field provide your thinking steps
-    #[serde(rename = "_thinking")]
-    thinking: String,
-
-    /// In this field provide answer
-    answer: String,
-}
@@ -1,3 +1,4 @@
+use agentai::output::Thinking;
 use agentai::tool::{
     web::{WebFetchToolBox, WebSearchToolBox},
     ToolBoxSet,
@@ -5,8 +6,6 @@ use agentai::tool::{
 use agentai::Agent;
 use anyhow::Result;
 use log::{info, LevelFilter};
-use schemars::JsonSchema;
-use serde::Deserialize;
 use simplelog::{ColorChoice, Config, TermLogger, TerminalMode};
 use std::env;
 
@@ -41,21 +40,9 @@ async fn main() -> Result<()> {
 
     let mut agent = Agent::new_with_url(&base_url, &api_key, SYSTEM);
 
-    let answer: Answer = agent.run(&model, question, Some(&toolbox)).await?;
+    let answer: Thinking<String> = agent.run(&model, question, Some(&toolbox)).await?;
 
     info!("{:#?}", answer);
 
     Ok(())
 }
-
-#[allow(dead_code)]
-#[derive(Deserialize, JsonSchema, Debug)]
-struct Answer {
-    // It is always good idea to include thinking field for LLM's debugging
-    /// In this field provide your thinking steps
-    #[serde(rename = "_thinking")]
-    thinking: String,
-
-    /// In this field provide answer
-    answer: String,
-}
@@ -0,0 +1,587 @@
+//! # Context Window Management
+//!
+//! Long conversations, or tools returning large outputs, may exceed the context window of the
+//! model. Providers reject such requests with an error. When a [`ContextOverflowStrategy`] is
+//! configured using [`Agent::with_context_overflow_strategy`](crate::agent::Agent::with_context_overflow_strategy),
+//! the agent detects this error, reduces the history and retries the request once. With
+//! [`Agent::with_max_prompt_tokens`](crate::agent::Agent::with_max_prompt_tokens) oversized
+//! requests are detected using token estimates, before they are sent.
+//!
+//! Large tool outputs can be shrunk before they are added to the history using
+//! [`LargeToolOutputStrategy`], see
+//! [`Agent::with_large_tool_output_strategy`](crate::agent::Agent::with_large_tool_output_strategy).
+//!
+//! Independently of token limits, number of messages kept in memory can be capped using
+//! [`Agent::with_max_history_messages`](crate::agent::Agent::with_max_history_messages).
+//! To keep requests of long-running agents within a budget, [`HistoryLimit`] set with
+//! [`Agent::with_history_limit`](crate::agent::Agent::with_history_limit) trims the history
+//! before every request.
+//!
+//! Size of the next request can be checked up front with
+//! [`Agent::estimated_request_tokens`](crate::agent::Agent::estimated_request_tokens), which
+//! counts all messages, including the system prompt, and tool definitions. Estimates are
+//! rough, based on text length, providers count tokens using their own tokenizers.
+
+use crate::tool::Tool;
+use anyhow::Result;
+use genai::chat::{ChatMessage, ChatRequest, ChatRole, ContentPart, MessageContent};
+use genai::webc;
+use genai::Client;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+/// Fragments of error messages returned by providers when the context window is exceeded
+const CONTEXT_OVERFLOW_MESSAGES: &[&str] = &[
+    "context_length_exceeded",
+    "maximum context length",
+    "context window",
+    "prompt is too long",
+    "input is too long",
+    "too many tokens",
+    "exceeds the maximum number of tokens",
+    "input token count",
+];
+
+/// Average number of characters per token, typical for English text and code
+pub(crate) const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens added by providers to every message and tool definition, e.g. role markers
+const OVERHEAD_TOKENS: usize = 4;
+
+/// Tokens counted for every image, the cost of a low detail image in OpenAI models
+const IMAGE_TOKENS: usize = 85;
+
+/// Strategy used to recover when a request exceeds model context window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContextOverflowStrategy {
+    /// Drops oldest messages, keeping system prompt and at least `keep_last` most recent messages
+    DropOldest { keep_last: usize },
+    /// Replaces oldest messages with a summary generated by `model`, keeping system prompt and
+    /// at least `keep_last` most recent messages
+    Summarize { model: String, keep_last: usize },
+}
+
+/// Strategy used to shrink tool outputs exceeding the size limit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LargeToolOutputStrategy {
+    /// Cuts output to `max_bytes`, marking that it was truncated
+    Truncate { max_bytes: usize },
+    /// Replaces output longer than `max_bytes` with a summary generated by `model`.
+    /// If summarization fails, output is truncated.
+    Summarize { model: String, max_bytes: usize },
+}
+
+/// Limit of the history size, applied before every request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HistoryLimit {
+    /// Keeps at most given number of non-system messages
+    MaxMessages(usize),
+    /// Keeps messages which fit in given number of tokens, estimated with
+    /// [`estimate_message_tokens`], system messages included
+    MaxTokens(usize),
+}
+
+/// Result of history reduction, used to keep track of messages added during current run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Reduction {
+    /// Index of the first removed message
+    pub start: usize,
+    /// Number of removed messages
+    pub removed: usize,
+    /// Number of messages inserted in place of removed ones
+    pub inserted: usize,
+}
+
+impl Reduction {
+    /// Returns new position of message that was at `index` before reduction
+    pub fn adjust_index(&self, index: usize) -> usize {
+        if index <= self.start {
+            index
+        } else if index >= self.start + self.removed {
+            index - self.removed + self.inserted
+        } else {
+            self.start + self.inserted
+        }
+    }
+}
+
+/// Checks if error returned by GenAI indicates that request exceeded model context window.
+///
+/// Detection is based on the response body of failed requests, with fallback to the error
+/// message, because providers do not use a common error code.
+pub fn is_context_overflow(err: &genai::Error) -> bool {
+    let message = match err {
+        genai::Error::WebModelCall {
+            webc_error: webc::Error::ResponseFailedStatus { body, .. },
+            ..
+        }
+        | genai::Error::WebAdapterCall {
+            webc_error: webc::Error::ResponseFailedStatus { body, .. },
+            ..
+        } => body.to_lowercase(),
+        err => err.to_string().to_lowercase(),
+    };
+    CONTEXT_OVERFLOW_MESSAGES
+        .iter()
+        .any(|fragment| message.contains(fragment))
+}
+
+/// Estimates number of tokens in text, assuming about 4 characters per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Estimates number of tokens used by a message, including tool calls and tool results.
+pub fn estimate_message_tokens(message: &ChatMessage) -> usize {
+    let content = match &message.content {
+        MessageContent::Text(text) => estimate_tokens(text),
+        MessageContent::Parts(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text(text) => estimate_tokens(text),
+                ContentPart::Image { .. } => IMAGE_TOKENS,
+            })
+            .sum(),
+        MessageContent::ToolCalls(calls) => calls
+            .iter()
+            .map(|call| {
+                estimate_tokens(&call.fn_name)
+                    + estimate_tokens(&call.fn_arguments.to_string())
+                    + OVERHEAD_TOKENS
+            })
+            .sum(),
+        MessageContent::ToolResponses(responses) => responses
+            .iter()
+            .map(|response| estimate_tokens(&response.content) + OVERHEAD_TOKENS)
+            .sum(),
+    };
+    content + OVERHEAD_TOKENS
+}
+
+/// Estimates number of tokens used by a tool definition, its name, description and schema.
+///
+/// Tool definitions are sent with every request, with many tools, e.g. provided by MCP servers,
+/// they may take a large part of the context window.
+pub fn estimate_tool_tokens(tool: &Tool) -> usize {
+    estimate_tokens(&tool.name)
+        + tool.description.as_deref().map_or(0, estimate_tokens)
+        + tool
+            .schema
+            .as_ref()
+            .map_or(0, |schema| estimate_tokens(&schema.to_string()))
+        + OVERHEAD_TOKENS
+}
+
+/// Reduces history according to the strategy.
+///
+/// Returns `None` if history can't be reduced any further.
+pub(crate) async fn reduce_history(
+    client: &Client,
+    strategy: &ContextOverflowStrategy,
+    history: &mut Vec<ChatMessage>,
+) -> Result<Option<Reduction>> {
+    let keep_last = match strategy {
+        ContextOverflowStrategy::DropOldest { keep_last } => *keep_last,
+        ContextOverflowStrategy::Summarize { keep_last, .. } => *keep_last,
+    };
+    let Some((start, end)) = reducible_range(history, keep_last) else {
+        return Ok(None);
+    };
+    let removed: Vec<ChatMessage> = history.drain(start..end).collect();
+    debug!("Context overflow, removing {} messages", removed.len());
+
+    let mut inserted = 0;
+    if let ContextOverflowStrategy::Summarize { model, .. } = strategy {
+        let summary = summarize(client, model, &removed).await?;
+        history.insert(
+            start,
+            ChatMessage::system(format!("Summary of earlier conversation:\n{summary}")),
+        );
+        inserted = 1;
+    }
+
+    Ok(Some(Reduction {
+        start,
+        removed: removed.len(),
+        inserted,
+    }))
+}
+
+/// Removes oldest non-system messages, so at most `max_messages` of them are left.
+///
+/// Tool responses whose tool call was removed are removed too, so history never starts with
+/// dangling tool responses. Returns which messages of the original history were kept, so data
+/// kept alongside the history can be adjusted with [`retain_mask`].
+pub(crate) fn limit_history(history: &mut Vec<ChatMessage>, max_messages: usize) -> Vec<bool> {
+    let mut keep = vec![true; history.len()];
+    let Some(start) = history
+        .iter()
+        .position(|msg| !matches!(msg.role, ChatRole::System))
+    else {
+        return keep;
+    };
+    let non_system = history[start..]
+        .iter()
+        .filter(|msg| !matches!(msg.role, ChatRole::System))
+        .count();
+    if non_system <= max_messages {
+        return keep;
+    }
+    let mut end = start;
+    let mut removed = 0;
+    while removed < non_system - max_messages
+        || history
+            .get(end)
+            .is_some_and(|msg| matches!(msg.content, MessageContent::ToolResponses(_)))
+    {
+        if !matches!(history[end].role, ChatRole::System) {
+            removed += 1;
+        }
+        end += 1;
+    }
+    // System messages placed later in history, e.g. summaries, are kept
+    for (index, msg) in history.iter().enumerate().take(end).skip(start) {
+        keep[index] = matches!(msg.role, ChatRole::System);
+    }
+    retain_mask(history, &keep);
+    debug!("History limit exceeded, removed {removed} messages");
+    keep
+}
+
+/// Removes oldest non-system messages exceeding the limit, like [`limit_history`].
+///
+/// The most recent message is always kept, together with the tool call, if it is a tool response.
+pub(crate) fn apply_history_limit(
+    history: &mut Vec<ChatMessage>,
+    limit: HistoryLimit,
+) -> Vec<bool> {
+    let (system, messages): (Vec<&ChatMessage>, Vec<&ChatMessage>) = history
+        .iter()
+        .partition(|msg| matches!(msg.role, ChatRole::System));
+    let mut max_messages = match limit {
+        HistoryLimit::MaxMessages(max_messages) => max_messages,
+        HistoryLimit::MaxTokens(max_tokens) => {
+            let system_tokens: usize = system.iter().map(|msg| estimate_message_tokens(msg)).sum();
+            let mut budget = max_tokens.saturating_sub(system_tokens);
+            messages
+                .iter()
+                .rev()
+                .take_while(|msg| {
+                    let tokens = estimate_message_tokens(msg);
+                    let fits = tokens <= budget;
+                    budget = budget.saturating_sub(tokens);
+                    fits
+                })
+                .count()
+        }
+    }
+    .max(1);
+    while max_messages < messages.len()
+        && matches!(
+            messages[messages.len() - max_messages].content,
+            MessageContent::ToolResponses(_)
+        )
+    {
+        max_messages += 1;
+    }
+    limit_history(history, max_messages)
+}
+
+/// Marks messages forming complete tool turns, so tool calls without responses and tool
+/// responses without calls can be removed with [`retain_mask`]. Other messages are kept.
+pub(crate) fn complete_tool_turns(history: &[ChatMessage]) -> Vec<bool> {
+    let responded: Vec<&str> = history
+        .iter()
+        .filter_map(|msg| match &msg.content {
+            MessageContent::ToolResponses(responses) => Some(responses),
+            _ => None,
+        })
+        .flatten()
+        .map(|response| response.call_id.as_str())
+        .collect();
+    let mut called = vec![];
+    history
+        .iter()
+        .map(|msg| match &msg.content {
+            MessageContent::ToolCalls(calls) => {
+                let complete = calls
+                    .iter()
+                    .all(|call| responded.contains(&call.call_id.as_str()));
+                if complete {
+                    called.extend(calls.iter().map(|call| call.call_id.as_str()));
+                }
+                complete
+            }
+            MessageContent::ToolResponses(responses) => responses
+                .iter()
+                .all(|response| called.contains(&response.call_id.as_str())),
+            _ => true,
+        })
+        .collect()
+}
+
+/// Keeps only items marked in `keep`, which has an entry for every item
+pub(crate) fn retain_mask<T>(items: &mut Vec<T>, keep: &[bool]) {
+    let mut index = 0;
+    items.retain(|_| {
+        let kept = keep.get(index).copied().unwrap_or(true);
+        index += 1;
+        kept
+    });
+}
+
+/// Shrinks tool output according to the strategy, outputs within the limit are not changed.
+pub(crate) async fn shrink_tool_output(
+    client: &Client,
+    strategy: &LargeToolOutputStrategy,
+    tool_name: &str,
+    output: String,
+) -> String {
+    let max_bytes = match strategy {
+        LargeToolOutputStrategy::Truncate { max_bytes } => *max_bytes,
+        LargeToolOutputStrategy::Summarize { max_bytes, .. } => *max_bytes,
+    };
+    if output.len() <= max_bytes {
+        return output;
+    }
+    if let LargeToolOutputStrategy::Summarize { model, .. } = strategy {
+        debug!(
+            "Tool '{tool_name}' output has {} bytes, summarizing",
+            output.len()
+        );
+        match summarize_tool_output(client, model, tool_name, &output).await {
+            Ok(summary) => return format!("Summary of tool output:\n{summary}"),
+            Err(err) => debug!("Tool output summarization failed: {err}"),
+        }
+    }
+    truncate_output(output, max_bytes)
+}
+
+/// Cuts text to at most `max_bytes`, respecting character boundaries
+fn truncate_output(mut output: String, max_bytes: usize) -> String {
+    let mut end = max_bytes.min(output.len());
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    let omitted = output.len() - end;
+    output.truncate(end);
+    output.push_str(&format!("\n[output truncated, {omitted} bytes omitted]"));
+    output
+}
+
+async fn summarize_tool_output(
+    client: &Client,
+    model: &str,
+    tool_name: &str,
+    output: &str,
+) -> Result<String> {
+    let chat_req = ChatRequest::new(vec![
+        ChatMessage::system(format!(
+            "Summarize the output of tool '{tool_name}' provided by the user. Keep all facts, \
+            numbers, names and identifiers that may be needed to answer questions about it. \
+            Respond only with the summary."
+        )),
+        ChatMessage::user(output),
+    ]);
+    let chat_resp = client.exec_chat(model, chat_req, None).await?;
+    chat_resp
+        .content_text_into_string()
+        .ok_or_else(|| anyhow::anyhow!("Summary response has no text"))
+}
+
+/// Finds range of messages that can be removed.
+///
+/// Leading system messages are always kept. The range ends at a user message, so the remaining
+/// history never starts with tool responses detached from their tool calls.
+fn reducible_range(history: &[ChatMessage], keep_last: usize) -> Option<(usize, usize)> {
+    let start = history
+        .iter()
+        .position(|msg| !matches!(msg.role, ChatRole::System))?;
+    let end = (start..=history.len().saturating_sub(keep_last.max(1)))
+        .rev()
+        .find(|&index| {
+            matches!(history[index].role, ChatRole::User)
+                && matches!(history[index].content, MessageContent::Text(_))
+        })?;
+    (start < end).then_some((start, end))
+}
+
+/// Summarizes messages using `model`
+pub(crate) async fn summarize(
+    client: &Client,
+    model: &str,
+    messages: &[ChatMessage],
+) -> Result<String> {
+    let conversation = messages
+        .iter()
+        .map(|msg| {
+            let msg = crate::transcript::Message::from(msg);
+            let mut text = format!("{:?}: {}", msg.role, msg.content);
+            for call in msg.tool_calls {
+                text.push_str(&format!("\n[called {}({})]", call.name, call.arguments));
+            }
+            for result in msg.tool_results {
+                text.push_str(&format!("\n[tool result: {}]", result.content));
+            }
+            text
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let chat_req = ChatRequest::new(vec![
+        ChatMessage::system(
+            "Summarize the following conversation. Keep all facts, decisions and results \
+            that may be needed to continue it. Respond only with the summary.",
+        ),
+        ChatMessage::user(conversation),
+    ]);
+    let chat_resp = client.exec_chat(model, chat_req, None).await?;
+    Ok(chat_resp.content_text_into_string().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genai::chat::{ToolCall, ToolResponse};
+    use serde_json::json;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("Hello world"), 3);
+        assert_eq!(
+            estimate_message_tokens(&ChatMessage::user("Hello world")),
+            7
+        );
+        let call = ChatMessage::from(vec![ToolCall {
+            call_id: "call_1".to_string(),
+            fn_name: "search".to_string(),
+            fn_arguments: json!({"query": "rust"}),
+        }]);
+        // Name, arguments and overhead of the call and the message
+        assert_eq!(estimate_message_tokens(&call), 2 + 4 + 4 + 4);
+        let tool = Tool::new("search").with_schema(json!({"type": "object"}));
+        assert_eq!(estimate_tool_tokens(&tool), 2 + 5 + 4);
+    }
+
+    fn history() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::system("system"),
+            ChatMessage::user("first question"),
+            ChatMessage::from(vec![ToolCall {
+                call_id: "call_1".to_string(),
+                fn_name: "echo".to_string(),
+                fn_arguments: json!({}),
+            }]),
+            ChatMessage::from(ToolResponse::new("call_1", "result")),
+            ChatMessage::assistant("first answer"),
+            ChatMessage::user("second question"),
+            ChatMessage::assistant("second answer"),
+            ChatMessage::user("third question"),
+        ]
+    }
+
+    #[test]
+    fn test_reducible_range_ends_at_user_message() {
+        assert_eq!(reducible_range(&history(), 1), Some((1, 7)));
+        assert_eq!(reducible_range(&history(), 3), Some((1, 5)));
+        // Cut can't be placed between tool call and its response
+        assert_eq!(reducible_range(&history(), 5), None);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let mut history = history();
+        let strategy = ContextOverflowStrategy::DropOldest { keep_last: 3 };
+        let reduction = reduce_history(&Client::default(), &strategy, &mut history)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[1].content.text_as_str(), Some("second question"));
+        assert_eq!(reduction.adjust_index(7), 3);
+        assert_eq!(reduction.adjust_index(2), 1);
+    }
+
+    #[test]
+    fn test_limit_history() {
+        let removed = |keep: Vec<bool>| keep.iter().filter(|kept| !**kept).count();
+        let mut messages = history();
+        assert_eq!(removed(limit_history(&mut messages, 7)), 0);
+        assert_eq!(removed(limit_history(&mut messages, 6)), 1);
+        assert_eq!(messages.len(), 7);
+        assert!(matches!(messages[1].content, MessageContent::ToolCalls(_)));
+
+        // Tool response can't be left without its tool call
+        let mut messages = history();
+        assert_eq!(removed(limit_history(&mut messages, 5)), 3);
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[0].content.text_as_str(), Some("system"));
+        assert_eq!(messages[1].content.text_as_str(), Some("first answer"));
+    }
+
+    #[test]
+    fn test_apply_history_limit() {
+        let mut messages = history();
+        apply_history_limit(&mut messages, HistoryLimit::MaxMessages(3));
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[1].content.text_as_str(), Some("second question"));
+
+        // System prompt (6 tokens) and the last two messages (8 tokens each) fit
+        let mut messages = history();
+        apply_history_limit(&mut messages, HistoryLimit::MaxTokens(22));
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].content.text_as_str(), Some("second answer"));
+
+        // The last message is kept even if it doesn't fit, with its tool call
+        let mut messages = history();
+        messages.truncate(4);
+        apply_history_limit(&mut messages, HistoryLimit::MaxTokens(0));
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(messages[1].content, MessageContent::ToolCalls(_)));
+    }
+
+    #[test]
+    fn test_complete_tool_turns() {
+        let mut messages = history();
+        assert!(complete_tool_turns(&messages).iter().all(|kept| *kept));
+
+        // Tool call without response and response without call
+        messages.remove(3);
+        messages.push(ChatMessage::from(ToolResponse::new("call_2", "result")));
+        let keep = complete_tool_turns(&messages);
+        retain_mask(&mut messages, &keep);
+        assert_eq!(messages.len(), 6);
+        assert!(messages
+            .iter()
+            .all(|msg| matches!(msg.content, MessageContent::Text(_))));
+    }
+
+    #[tokio::test]
+    async fn test_truncate_tool_output() {
+        let strategy = LargeToolOutputStrategy::Truncate { max_bytes: 5 };
+        let client = Client::default();
+
+        let output = shrink_tool_output(&client, &strategy, "echo", "short".to_string()).await;
+        assert_eq!(output, "short");
+
+        // Cut is moved to character boundary
+        let output = shrink_tool_output(&client, &strategy, "echo", "abcdéfgh".to_string()).await;
+        assert_eq!(output, "abcd\n[output truncated, 5 bytes omitted]");
+    }
+
+    #[test]
+    fn test_is_context_overflow() {
+        let err = genai::Error::WebModelCall {
+            model_iden: genai::ModelIden::new(genai::adapter::AdapterKind::OpenAI, "gpt-4o"),
+            webc_error: webc::Error::ResponseFailedStatus {
+                status: reqwest::StatusCode::BAD_REQUEST,
+                body: r#"{"error": {"code": "context_length_exceeded"}}"#.to_string(),
+            },
+        };
+        assert!(is_context_overflow(&err));
+        assert!(!is_context_overflow(
+            &genai::Error::JsonModeWithoutInstruction
+        ));
+    }
+}
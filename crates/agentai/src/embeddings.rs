@@ -0,0 +1,131 @@
+//! # Embeddings Cache
+//!
+//! Computing embeddings of the same text again wastes money, e.g. in RAG pipelines re-embedding
+//! stable documents on every start. [`EmbeddingCache`] stores embeddings on disk, keyed by model
+//! and hash of the text, so they are computed once and reused across process restarts.
+//!
+//! GenAI doesn't provide embeddings API yet, so the cache wraps any function computing them:
+//!
+//! ```rust,no_run
+//! # use agentai::embeddings::EmbeddingCache;
+//! # async fn embed(model: &str, text: &str) -> anyhow::Result<Vec<f32>> { Ok(vec![]) }
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let cache = EmbeddingCache::new("cache/embeddings");
+//! let text = "Rust is a systems programming language";
+//! let embedding = cache
+//!     .get_or_embed("text-embedding-3-small", text, || {
+//!         embed("text-embedding-3-small", text)
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Read-through disk cache of embeddings.
+///
+/// Every embedding is stored as a JSON file in the cache directory, named after SHA-256 hash of
+/// the model and the text. Files are written atomically, so the cache can be shared between
+/// processes.
+#[derive(Debug, Clone)]
+pub struct EmbeddingCache {
+    dir: PathBuf,
+}
+
+impl EmbeddingCache {
+    /// Creates cache storing embeddings in `dir`, the directory is created when needed
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Returns cached embedding of `text` computed by `model`, if present
+    pub fn get(&self, model: &str, text: &str) -> Result<Option<Vec<f32>>> {
+        let path = self.path(model, text);
+        match fs::read(&path) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data).with_context(|| {
+                format!("Invalid cached embedding {}", path.display())
+            })?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).context("Failed to read cached embedding"),
+        }
+    }
+
+    /// Stores embedding of `text` computed by `model`
+    pub fn insert(&self, model: &str, text: &str, embedding: &[f32]) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Failed to create embeddings cache directory")?;
+        let path = self.path(model, text);
+        // Write to temporary file first, readers never see partially written embedding
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_vec(embedding)?)
+            .context("Failed to write cached embedding")?;
+        fs::rename(&tmp_path, &path).context("Failed to write cached embedding")?;
+        Ok(())
+    }
+
+    /// Returns cached embedding, or computes it using `embed` and stores it in the cache
+    pub async fn get_or_embed<F, Fut>(&self, model: &str, text: &str, embed: F) -> Result<Vec<f32>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<f32>>>,
+    {
+        if let Some(embedding) = self.get(model, text)? {
+            return Ok(embedding);
+        }
+        let embedding = embed().await?;
+        self.insert(model, text, &embedding)?;
+        Ok(embedding)
+    }
+
+    fn path(&self, model: &str, text: &str) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(model.as_bytes());
+        hasher.update([0]);
+        hasher.update(text.as_bytes());
+        let hash: String = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        self.dir.join(format!("{hash}.json"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_get_or_embed() {
+        let dir = std::env::temp_dir().join(format!("agentai-embeddings-{}", std::process::id()));
+        let cache = EmbeddingCache::new(&dir);
+        let calls = AtomicUsize::new(0);
+        let embed = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![0.5, -1.0])
+        };
+
+        assert_eq!(cache.get("model", "text").unwrap(), None);
+        let first = cache.get_or_embed("model", "text", embed).await.unwrap();
+        // New cache instance reads embedding stored on disk
+        let second = EmbeddingCache::new(&dir)
+            .get_or_embed("model", "text", embed)
+            .await
+            .unwrap();
+        assert_eq!(first, [0.5, -1.0]);
+        assert_eq!(second, first);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // Other model has separate entry
+        cache.get_or_embed("other", "text", embed).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
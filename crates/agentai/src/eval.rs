@@ -0,0 +1,68 @@
+//! # Evaluation Helpers
+//!
+//! This module contains helpers for evaluating quality of agent answers.
+//!
+//! [`judge`] implements the "LLM-as-judge" pattern: a model is asked to score an answer
+//! against provided criteria. Response is returned as a typed [`JudgeResult`] using
+//! [structured output](crate::structured_output).
+//!
+//! ```rust,no_run
+//! # use agentai::eval::judge;
+//! # use genai::Client;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let client = Client::default();
+//! let result = judge(
+//!     client,
+//!     "gpt-4o-mini",
+//!     "Answer must name the capital of France and nothing else",
+//!     "Paris",
+//! )
+//! .await?;
+//! println!("Score: {} ({})", result.score, result.rationale);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::Agent;
+use anyhow::Result;
+use genai::Client;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+const JUDGE_SYSTEM: &str = "You are an impartial judge evaluating answers. \
+Assess how well the answer satisfies the criteria. Be strict and objective. \
+Explain your reasoning first, then assign a score from 0 (does not satisfy criteria at all) \
+to 10 (fully satisfies criteria).";
+
+/// Verdict returned by [`judge`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct JudgeResult {
+    /// Explanation of the score, referring to the criteria
+    pub rationale: String,
+    /// Score from 0 (does not satisfy criteria at all) to 10 (fully satisfies criteria)
+    pub score: u8,
+}
+
+/// Asks a model to score an answer against criteria.
+///
+/// # Arguments
+///
+/// * `client` - GenAI client used to access the judging model.
+/// * `model` - The model acting as a judge.
+/// * `criteria` - Description of what a good answer looks like.
+/// * `answer` - Answer to evaluate.
+///
+/// # Returns
+///
+/// Score with rationale provided by the judging model.
+pub async fn judge(
+    client: Client,
+    model: &str,
+    criteria: &str,
+    answer: &str,
+) -> Result<JudgeResult> {
+    let mut agent = Agent::new_with_client(client, JUDGE_SYSTEM);
+    let prompt = format!("<criteria>\n{criteria}\n</criteria>\n\n<answer>\n{answer}\n</answer>");
+    agent.run(model, &prompt, None).await
+}
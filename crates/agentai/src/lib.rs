@@ -77,8 +77,28 @@
 //! cargo run --example simple
 //! ```
 
+// Code generated by `#[toolbox]` refers to items of this crate by `::agentai` path,
+// which also has to resolve when the macro is used inside this crate
+extern crate self as agentai;
+
 pub mod agent;
+pub mod context;
+#[cfg(feature = "embeddings-cache")]
+pub mod embeddings;
+pub mod eval;
+pub mod monitoring;
+pub mod output;
+pub mod pricing;
+pub mod rate_limit;
+pub mod repl;
+pub mod schema;
+pub mod stream;
+pub mod text;
 pub mod tool;
+pub mod transcript;
+
+#[cfg(test)]
+mod test_util;
 
 // These modules will be enabled only when generating documentation.
 #[cfg(doc)]
@@ -89,3 +109,6 @@ pub mod structured_output;
 
 #[allow(unused_imports)]
 pub use agent::*;
+
+/// JSON Patch used by [`Agent::update_context_document`]
+pub use json_patch;
@@ -0,0 +1,402 @@
+//! # Metrics
+//!
+//! When the `metrics` feature is enabled, agent runs are instrumented using the
+//! [`metrics`](https://crates.io/crates/metrics) crate facade. Metrics are sent to the recorder
+//! installed by the application, e.g. Prometheus or StatsD exporter. Without installed recorder
+//! metrics are discarded.
+//!
+//! | Metric                                  | Type      | Labels                  |
+//! |-----------------------------------------|-----------|-------------------------|
+//! | `agentai_runs_total`                    | counter   | `status`                |
+//! | `agentai_run_duration_seconds`          | histogram | `status`                |
+//! | `agentai_requests_total`                | counter   | `model`                 |
+//! | `agentai_request_duration_seconds`      | histogram | `model`                 |
+//! | `agentai_tokens_total`                  | counter   | `model`, `type`         |
+//! | `agentai_tool_calls_total`              | counter   | `tool`, `status`        |
+//! | `agentai_tool_call_duration_seconds`    | histogram | `tool`                  |
+//! | `agentai_errors_total`                  | counter   | `type`                  |
+//!
+//! `status` is `ok` or `error`. Token `type` is `prompt` or `completion`. Error `type` is one of
+//! `request`, `timeout`, `cancelled`, `no_tools_available`, `empty_response`,
+//! `prompt_too_long`, `max_iterations_exceeded`, `structured_output`, `tool` or `other`.
+//!
+//! ```rust,ignore
+//! metrics_exporter_prometheus::PrometheusBuilder::new().install()?;
+//! let answer: String = agent.run("gpt-4o-mini", "Why is the sky blue?", None).await?;
+//! ```
+//!
+//! # Tracing
+//!
+//! When the `tracing` feature is enabled, agent runs are instrumented using the
+//! [`tracing`](https://crates.io/crates/tracing) crate. Spans and events are sent to the
+//! subscriber installed by the application, e.g. exported to OpenTelemetry using
+//! `tracing-opentelemetry`, which allows to follow runs of concurrent agents.
+//!
+//! | Span              | Fields                 |
+//! |-------------------|------------------------|
+//! | `agent.run`       | `model`, `session.id`  |
+//! | `agent.tool_call` | `tool.name`, `call_id` |
+//!
+//! Every run started with [`Agent::run`](crate::agent::Agent::run) or its variants has its own
+//! `agent.run` span, with `agent.tool_call` spans of executed tools nested in it.
+//!
+//! | Event                | Level      | Fields                                                       |
+//! |----------------------|------------|--------------------------------------------------------------|
+//! | `agent.request`      | debug      | `model`, `messages`, `tools`                                 |
+//! | `agent.response`     | info       | `model`, `prompt_tokens`, `completion_tokens`, `duration_ms` |
+//! | `agent.tool_result`  | info       | `tool.name`, `status`, `duration_ms`                         |
+//! | `agent.run_finished` | info, warn | `status`, `duration_ms`, `error.type`, `error`               |
+//!
+//! `error.type` has the same values as the `type` label of `agentai_errors_total` metric.
+//!
+//! Messages of the [`log`](https://crates.io/crates/log) crate are still emitted, so both can be
+//! used together. `tracing-subscriber` captures them as events of the current span, when its
+//! `tracing-log` feature is enabled.
+//!
+//! ```rust,ignore
+//! tracing_subscriber::fmt().with_max_level(tracing::Level::DEBUG).init();
+//! let answer: String = agent.run("gpt-4o-mini", "Why is the sky blue?", None).await?;
+//! ```
+
+use crate::agent::AgentError;
+use crate::tool::ToolError;
+use genai::chat::Usage;
+use std::future::Future;
+use std::time::Duration;
+
+/// Span of an agent run or a tool call
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::Span;
+
+/// Span of an agent run or a tool call, spans are not recorded without the `tracing` feature
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    /// Calls `f`, the same as [`tracing::Span::in_scope`]
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+}
+
+/// Creates span of an agent run
+#[cfg(feature = "tracing")]
+pub(crate) fn run_span(model: &str, session_id: Option<&str>) -> Span {
+    tracing::info_span!("agent.run", model, session.id = session_id)
+}
+
+/// Creates span of an agent run
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn run_span(_model: &str, _session_id: Option<&str>) -> Span {
+    Span
+}
+
+/// Creates span of a tool call
+#[cfg(feature = "tracing")]
+pub(crate) fn tool_call_span(tool: &str, call_id: &str) -> Span {
+    tracing::info_span!("agent.tool_call", tool.name = tool, call_id)
+}
+
+/// Creates span of a tool call
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn tool_call_span(_tool: &str, _call_id: &str) -> Span {
+    Span
+}
+
+/// Runs `future` inside `span`
+#[cfg(feature = "tracing")]
+pub(crate) async fn instrument<F: Future>(span: Span, future: F) -> F::Output {
+    tracing::Instrument::instrument(future, span).await
+}
+
+/// Runs `future` inside `span`
+#[cfg(not(feature = "tracing"))]
+pub(crate) async fn instrument<F: Future>(_span: Span, future: F) -> F::Output {
+    future.await
+}
+
+/// Records request about to be sent to the model
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn record_request_started(model: &str, messages: usize, tools: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(name: "agent.request", model, messages, tools, "Sending request to {model}");
+}
+
+/// Records finished agent run
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "tracing")),
+    allow(unused_variables)
+)]
+pub(crate) fn record_run(error: Option<&anyhow::Error>, duration: Duration) {
+    #[cfg(feature = "tracing")]
+    {
+        let duration_ms = duration.as_millis() as u64;
+        match error {
+            None => tracing::info!(
+                name: "agent.run_finished",
+                status = status(true),
+                duration_ms,
+                "Agent run finished"
+            ),
+            Some(error) => tracing::warn!(
+                name: "agent.run_finished",
+                status = status(false),
+                duration_ms,
+                error.type = error_type(error),
+                error = %error,
+                "Agent run failed"
+            ),
+        }
+    }
+    #[cfg(feature = "metrics")]
+    {
+        let status = status(error.is_none());
+        metrics::counter!("agentai_runs_total", "status" => status).increment(1);
+        metrics::histogram!("agentai_run_duration_seconds", "status" => status)
+            .record(duration.as_secs_f64());
+        if let Some(error) = error {
+            metrics::counter!("agentai_errors_total", "type" => error_type(error)).increment(1);
+        }
+    }
+}
+
+/// Records successful request to the model
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "tracing")),
+    allow(unused_variables)
+)]
+pub(crate) fn record_request(model: &str, usage: &Usage, duration: Duration) {
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        name: "agent.response",
+        model,
+        prompt_tokens = usage.prompt_tokens,
+        completion_tokens = usage.completion_tokens,
+        duration_ms = duration.as_millis() as u64,
+        "Received response from {model}"
+    );
+    #[cfg(feature = "metrics")]
+    {
+        let model = model.to_string();
+        metrics::counter!("agentai_requests_total", "model" => model.clone()).increment(1);
+        metrics::histogram!("agentai_request_duration_seconds", "model" => model.clone())
+            .record(duration.as_secs_f64());
+        for (kind, tokens) in [
+            ("prompt", usage.prompt_tokens),
+            ("completion", usage.completion_tokens),
+        ] {
+            if let Some(tokens) = tokens {
+                metrics::counter!("agentai_tokens_total", "model" => model.clone(), "type" => kind)
+                    .increment(tokens.max(0) as u64);
+            }
+        }
+    }
+}
+
+/// Records tool call executed by the agent
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "tracing")),
+    allow(unused_variables)
+)]
+pub(crate) fn record_tool_call(tool: &str, success: bool, duration: Duration) {
+    #[cfg(feature = "tracing")]
+    tracing::event!(
+        name: "agent.tool_result",
+        tracing::Level::INFO,
+        tool.name = tool,
+        status = status(success),
+        duration_ms = duration.as_millis() as u64,
+        "Tool '{tool}' finished"
+    );
+    #[cfg(feature = "metrics")]
+    {
+        let tool = tool.to_string();
+        let status = status(success);
+        metrics::counter!("agentai_tool_calls_total", "tool" => tool.clone(), "status" => status)
+            .increment(1);
+        metrics::histogram!("agentai_tool_call_duration_seconds", "tool" => tool)
+            .record(duration.as_secs_f64());
+    }
+}
+
+#[cfg(any(feature = "metrics", feature = "tracing"))]
+fn status(success: bool) -> &'static str {
+    if success {
+        "ok"
+    } else {
+        "error"
+    }
+}
+
+/// Returns value of the `type` label of `agentai_errors_total` metric
+#[cfg_attr(not(any(feature = "metrics", feature = "tracing")), allow(dead_code))]
+fn error_type(error: &anyhow::Error) -> &'static str {
+    if error.is::<genai::Error>() {
+        "request"
+    } else if let Some(error) = error.downcast_ref::<AgentError>() {
+        match error {
+            AgentError::Timeout { .. } => "timeout",
+            AgentError::Cancelled { .. } => "cancelled",
+            AgentError::NoToolsAvailable { .. } => "no_tools_available",
+            AgentError::EmptyResponse => "empty_response",
+            AgentError::PromptTooLong { .. } => "prompt_too_long",
+            AgentError::MaxIterationsExceeded { .. } => "max_iterations_exceeded",
+            AgentError::InvalidStructuredOutput { .. } => "structured_output",
+        }
+    } else if error.is::<serde_json::Error>() {
+        "structured_output"
+    } else if error.is::<ToolError>() {
+        "tool"
+    } else {
+        "other"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_error_type() {
+        let timeout = AgentError::Timeout {
+            timeout: Duration::from_secs(1),
+            partial_history: vec![],
+        };
+        assert_eq!(error_type(&timeout.into()), "timeout");
+        assert_eq!(
+            error_type(&ToolError::LLMError("invalid".to_string()).into()),
+            "tool"
+        );
+        let json_error = serde_json::from_str::<u32>("text").unwrap_err();
+        assert_eq!(error_type(&json_error.into()), "structured_output");
+        assert_eq!(error_type(&anyhow!("unknown")), "other");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_record_tracing() {
+        use std::sync::{Arc, Mutex};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Records names of events, prefixed with names of entered spans
+        #[derive(Default)]
+        struct Recorder {
+            spans: Mutex<Vec<&'static str>>,
+            entered: Mutex<Vec<usize>>,
+            events: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut spans = self.spans.lock().unwrap();
+                spans.push(span.metadata().name());
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let spans = self.spans.lock().unwrap();
+                let mut path: Vec<&str> = self
+                    .entered
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|index| spans[*index])
+                    .collect();
+                path.push(event.metadata().name());
+                self.events.lock().unwrap().push(path.join("/"));
+            }
+
+            fn enter(&self, span: &Id) {
+                let index = span.into_u64() as usize - 1;
+                self.entered.lock().unwrap().push(index);
+            }
+
+            fn exit(&self, _span: &Id) {
+                self.entered.lock().unwrap().pop();
+            }
+        }
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let _guard = tracing::subscriber::set_default(Recorder {
+            events: events.clone(),
+            ..Default::default()
+        });
+        let span = run_span("model", Some("session"));
+        instrument(span.clone(), async {
+            record_request_started("model", 2, 1);
+            record_request("model", &Usage::default(), Duration::from_millis(100));
+            let tool_call = async { record_tool_call("echo", true, Duration::from_millis(10)) };
+            instrument(tool_call_span("echo", "call_1"), tool_call).await;
+        })
+        .await;
+        span.in_scope(|| record_run(Some(&anyhow!("failed")), Duration::from_secs(1)));
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "agent.run/agent.request",
+                "agent.run/agent.response",
+                "agent.run/agent.tool_call/agent.tool_result",
+                "agent.run/agent.run_finished",
+            ]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_record_metrics() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let usage = Usage {
+                prompt_tokens: Some(10),
+                completion_tokens: Some(5),
+                ..Default::default()
+            };
+            record_request("model", &usage, Duration::from_millis(100));
+            record_tool_call("echo", true, Duration::from_millis(10));
+            record_run(Some(&anyhow!("failed")), Duration::from_secs(1));
+        });
+
+        let counters: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| match value {
+                DebugValue::Counter(value) => {
+                    let key = key.key();
+                    let labels: Vec<_> = key.labels().map(|label| label.value()).collect();
+                    Some(format!("{}{labels:?} {value}", key.name()))
+                }
+                _ => None,
+            })
+            .collect();
+        for expected in [
+            "agentai_requests_total[\"model\"] 1",
+            "agentai_tokens_total[\"model\", \"prompt\"] 10",
+            "agentai_tokens_total[\"model\", \"completion\"] 5",
+            "agentai_tool_calls_total[\"echo\", \"ok\"] 1",
+            "agentai_runs_total[\"error\"] 1",
+            "agentai_errors_total[\"other\"] 1",
+        ] {
+            assert!(
+                counters.iter().any(|counter| counter == expected),
+                "missing {expected} in {counters:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! # Common Output Types
+//!
+//! This module provides ready to use types for common [structured output](crate::structured_output)
+//! patterns.
+//!
+//! [`Thinking`] asks the model to write down its reasoning before the answer. Field order in
+//! the schema makes the model generate reasoning first, which usually improves answers and helps
+//! debugging:
+//!
+//! ```rust,no_run
+//! # use agentai::Agent;
+//! # use agentai::output::Thinking;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let mut agent = Agent::new("You are a useful assistant");
+//! let answer: Thinking<u32> = agent
+//!     .run("gpt-4o-mini", "How many legs do three spiders have?", None)
+//!     .await?;
+//! println!("Reasoning: {}", answer.thinking);
+//! let legs = answer.into_value();
+//! # Ok(())
+//! # }
+//! ```
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Answer of type `T` preceded by the model reasoning.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Thinking<T> {
+    /// In this field provide your thinking steps
+    #[serde(rename = "_thinking")]
+    pub thinking: String,
+
+    /// In this field provide answer
+    pub value: T,
+}
+
+impl<T> Thinking<T> {
+    /// Discards reasoning and returns the answer
+    pub fn into_value(self) -> T {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schemars::schema_for;
+    use serde_json::json;
+
+    #[test]
+    fn test_thinking_schema_order() {
+        let schema = schema_for!(Thinking<u32>).to_value();
+        let properties: Vec<_> = schema["properties"].as_object().unwrap().keys().collect();
+        assert_eq!(properties, ["_thinking", "value"]);
+
+        let answer: Thinking<u32> =
+            serde_json::from_value(json!({"_thinking": "3 * 8", "value": 24})).unwrap();
+        assert_eq!(answer.into_value(), 24);
+    }
+}
@@ -0,0 +1,150 @@
+//! # Cost Estimation
+//!
+//! This module provides [`PricingTable`], mapping model names to token prices. Together with
+//! token usage reported by providers it allows estimating cost of an agent run, see
+//! [`Agent::last_cost`](crate::agent::Agent::last_cost).
+//!
+//! Default table contains prices of common models in USD. Providers change prices often,
+//! so treat it as a starting point and override prices relevant to you:
+//!
+//! ```rust
+//! use agentai::pricing::PricingTable;
+//!
+//! let pricing = PricingTable::default()
+//!     .with_price("gpt-4o", 0.0025, 0.01)
+//!     .with_price("my-finetuned-model", 0.003, 0.012);
+//! ```
+
+use genai::chat::Usage;
+use std::collections::HashMap;
+
+/// Price of a model, per 1K tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    /// Price of 1K input (prompt) tokens
+    pub input_per_1k: f64,
+    /// Price of 1K output (completion) tokens
+    pub output_per_1k: f64,
+}
+
+impl ModelPrice {
+    /// Calculates cost of tokens reported in usage
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let input = usage.prompt_tokens.unwrap_or(0).max(0) as f64;
+        let output = usage.completion_tokens.unwrap_or(0).max(0) as f64;
+        (input * self.input_per_1k + output * self.output_per_1k) / 1000.0
+    }
+}
+
+/// Prices of common models in USD, per 1K tokens
+const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o", 0.0025, 0.01),
+    ("gpt-4o-mini", 0.00015, 0.0006),
+    ("gpt-4.1", 0.002, 0.008),
+    ("gpt-4.1-mini", 0.0004, 0.0016),
+    ("gpt-4.1-nano", 0.0001, 0.0004),
+    ("o3-mini", 0.0011, 0.0044),
+    ("o4-mini", 0.0011, 0.0044),
+    ("claude-3-5-haiku", 0.0008, 0.004),
+    ("claude-3-7-sonnet", 0.003, 0.015),
+    ("claude-sonnet-4", 0.003, 0.015),
+    ("claude-opus-4", 0.015, 0.075),
+    ("gemini-2.0-flash", 0.0001, 0.0004),
+    ("gemini-2.5-flash", 0.0003, 0.0025),
+    ("gemini-2.5-pro", 0.00125, 0.01),
+];
+
+/// Maps model names to their prices.
+///
+/// Model is matched by exact name first, then by the longest known prefix, so versioned names
+/// like `gpt-4o-2024-08-06` use price of `gpt-4o`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricingTable {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl Default for PricingTable {
+    /// Creates table with prices of common models, see module documentation
+    fn default() -> Self {
+        DEFAULT_PRICES
+            .iter()
+            .fold(Self::new(), |table, (model, input, output)| {
+                table.with_price(model, *input, *output)
+            })
+    }
+}
+
+impl PricingTable {
+    /// Creates an empty table
+    pub fn new() -> Self {
+        Self {
+            prices: HashMap::new(),
+        }
+    }
+
+    /// Sets price of a model, replacing existing one.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - Model name, or its prefix.
+    /// * `input_per_1k` - Price of 1K input tokens.
+    /// * `output_per_1k` - Price of 1K output tokens.
+    pub fn with_price(mut self, model: &str, input_per_1k: f64, output_per_1k: f64) -> Self {
+        self.prices.insert(
+            model.to_string(),
+            ModelPrice {
+                input_per_1k,
+                output_per_1k,
+            },
+        );
+        self
+    }
+
+    /// Returns price of a model, if known
+    pub fn price(&self, model: &str) -> Option<&ModelPrice> {
+        self.prices.get(model).or_else(|| {
+            self.prices
+                .iter()
+                .filter(|(name, _)| model.starts_with(name.as_str()))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, price)| price)
+        })
+    }
+
+    /// Calculates cost of usage reported for a model, `None` if the model is unknown
+    pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        self.price(model).map(|price| price.cost(usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_lookup() {
+        let pricing = PricingTable::default();
+        assert_eq!(pricing.price("gpt-4o").unwrap().input_per_1k, 0.0025);
+        // Longest prefix wins
+        assert_eq!(
+            pricing
+                .price("gpt-4o-mini-2024-07-18")
+                .unwrap()
+                .input_per_1k,
+            0.00015
+        );
+        assert_eq!(pricing.price("unknown-model"), None);
+    }
+
+    #[test]
+    fn test_cost() {
+        let pricing = PricingTable::new().with_price("model", 0.001, 0.002);
+        let usage = Usage {
+            prompt_tokens: Some(2000),
+            completion_tokens: Some(500),
+            ..Default::default()
+        };
+        assert_eq!(pricing.cost("model", &usage), Some(0.003));
+        assert_eq!(pricing.cost("other", &usage), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! # Rate Limiting
+//!
+//! This module provides a token-bucket [`RateLimiter`] used by the [`Agent`](crate::agent::Agent)
+//! to stay under provider limits for requests per minute (RPM) and tokens per minute (TPM).
+//!
+//! Before every model request the agent waits until both buckets have budget available. After
+//! the response arrives, the number of tokens reported by the provider is subtracted from the
+//! token bucket, so the limit reflects actual usage instead of estimates.
+//!
+//! A single limiter can be shared by many agents, for example when running batch jobs:
+//!
+//! ```rust
+//! use agentai::Agent;
+//! use agentai::rate_limit::{RateLimit, RateLimiter};
+//! use std::sync::Arc;
+//!
+//! let limiter = Arc::new(RateLimiter::new(RateLimit {
+//!     requests_per_min: Some(60),
+//!     tokens_per_min: Some(100_000),
+//! }));
+//!
+//! let agents: Vec<Agent> = (0..4)
+//!     .map(|_| Agent::new("You are a useful assistant").with_rate_limiter(limiter.clone()))
+//!     .collect();
+//! ```
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits applied by [`RateLimiter`], `None` means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Maximum number of model requests per minute
+    pub requests_per_min: Option<u32>,
+    /// Maximum number of tokens (prompt and completion) per minute
+    pub tokens_per_min: Option<u32>,
+}
+
+/// Token-bucket rate limiter for model requests.
+///
+/// Use [`RateLimiter::acquire`] before sending a request and [`RateLimiter::record_usage`]
+/// after receiving a response. Limiter is thread safe and is meant to be shared using `Arc`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests: Option<Mutex<Bucket>>,
+    tokens: Option<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a new limiter, both buckets start full.
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            requests: limit.requests_per_min.map(|v| Mutex::new(Bucket::new(v))),
+            tokens: limit.tokens_per_min.map(|v| Mutex::new(Bucket::new(v))),
+        }
+    }
+
+    /// Waits until a request can be sent without exceeding configured limits.
+    pub async fn acquire(&self) {
+        while let Some(wait) = self.try_acquire() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Reports tokens used by a completed request.
+    ///
+    /// Token bucket may go below zero, in that case following requests will wait until
+    /// the budget is restored.
+    pub fn record_usage(&self, tokens: u32) {
+        if let Some(bucket) = &self.tokens {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.refill(Instant::now());
+            bucket.available -= tokens as f64;
+        }
+    }
+
+    /// Takes a request from the budget, or returns how long to wait before trying again.
+    fn try_acquire(&self) -> Option<Duration> {
+        let now = Instant::now();
+        if let Some(bucket) = &self.tokens {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.refill(now);
+            // Token usage is known only after the request, so we just need positive budget
+            if bucket.available <= 0.0 {
+                return Some(bucket.time_until(f64::MIN_POSITIVE));
+            }
+        }
+        if let Some(bucket) = &self.requests {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.refill(now);
+            if bucket.available < 1.0 {
+                return Some(bucket.time_until(1.0));
+            }
+            bucket.available -= 1.0;
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    /// Refill rate in units per second
+    rate: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(per_min: u32) -> Self {
+        Self {
+            capacity: per_min as f64,
+            available: per_min as f64,
+            rate: per_min as f64 / 60.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.available = (self.available + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    fn time_until(&self, amount: f64) -> Duration {
+        if self.rate <= 0.0 {
+            // Zero limit, there is nothing better than waiting for a minute
+            return Duration::from_secs(60);
+        }
+        Duration::from_secs_f64(((amount - self.available) / self.rate).max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_limit() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_min: Some(2),
+            tokens_per_min: None,
+        });
+        assert_eq!(limiter.try_acquire(), None);
+        assert_eq!(limiter.try_acquire(), None);
+        let wait = limiter.try_acquire().unwrap();
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_tokens_limit_uses_recorded_usage() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests_per_min: None,
+            tokens_per_min: Some(600),
+        });
+        assert_eq!(limiter.try_acquire(), None);
+        limiter.record_usage(1200);
+        let wait = limiter.try_acquire().unwrap();
+        assert!(wait > Duration::from_secs(59) && wait <= Duration::from_secs(60));
+    }
+}
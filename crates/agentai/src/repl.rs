@@ -0,0 +1,56 @@
+//! # Interactive REPL
+//!
+//! This module provides a minimal read-eval-print loop that turns any configured
+//! [`Agent`] into an interactive chat in the terminal. It is meant for prototyping and demos.
+//!
+//! ```rust,no_run
+//! # use agentai::Agent;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let mut agent = Agent::new("You are a useful assistant");
+//! agentai::repl::run(&mut agent, "gpt-4o-mini", None).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::tool::ToolBox;
+use crate::Agent;
+use anyhow::Result;
+use tokio::io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// Reads prompts from standard input and prints agent answers until end of input.
+///
+/// Each non-empty line is sent to [`Agent::run`], conversation history is kept by the agent
+/// between prompts. Errors returned by the agent are printed and do not stop the loop.
+///
+/// # Arguments
+///
+/// * `agent` - The agent used to answer prompts.
+/// * `model` - The model to use for the chat.
+/// * `toolbox` - The toolbox available for the agent.
+pub async fn run(agent: &mut Agent, model: &str, toolbox: Option<&dyn ToolBox>) -> Result<()> {
+    let mut lines = BufReader::new(stdin()).lines();
+    let mut stdout = stdout();
+
+    loop {
+        stdout.write_all(b"> ").await?;
+        stdout.flush().await?;
+
+        let Some(line) = lines.next_line().await? else {
+            break;
+        };
+        let prompt = line.trim();
+        if prompt.is_empty() {
+            continue;
+        }
+
+        let output = match agent.run::<String>(model, prompt, toolbox).await {
+            Ok(answer) => format!("{answer}\n"),
+            Err(err) => format!("Error: {err}\n"),
+        };
+        stdout.write_all(output.as_bytes()).await?;
+    }
+
+    stdout.write_all(b"\n").await?;
+    Ok(())
+}
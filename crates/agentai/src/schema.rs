@@ -0,0 +1,313 @@
+//! # JSON Schema utilities
+//!
+//! This module contains helpers for adapting JSON schemas generated by [`schemars`] to the
+//! requirements of different LLM providers.
+//!
+//! Schemas are used in two places: as the response format for structured output and as the
+//! parameters definition of tools. Some providers are strict about what they accept, for example
+//! OpenAI structured outputs in strict mode requires `additionalProperties: false` and all
+//! properties listed as required.
+//!
+//! Every helper is implemented as a [`schemars::transform::Transform`], so it can be used
+//! directly with [`schemars::generate::SchemaSettings::with_transform`] or applied to an
+//! already generated schema.
+//!
+//! [`SchemaSupport::detect`] decides which adaptations are needed for a provider. The agent uses
+//! it automatically, unless strict mode or response format are configured explicitly.
+
+use genai::adapter::AdapterKind;
+use schemars::transform::{transform_subschemas, Transform};
+use schemars::Schema;
+use serde_json::Value;
+
+/// Converts a schema into one accepted by OpenAI strict JSON schema mode.
+///
+/// For every object schema that defines `properties` this transform:
+/// - sets `additionalProperties` to `false`,
+/// - marks all properties as `required`.
+///
+/// Optional fields (`Option<T>`) are still accepted, because `schemars` already generates them
+/// as nullable types. Transform is applied recursively to all subschemas, including `$defs`.
+///
+/// # Example
+///
+/// ```rust
+/// use agentai::schema::StrictSchema;
+/// use schemars::transform::Transform;
+/// use schemars::json_schema;
+///
+/// let mut schema = json_schema!({
+///     "type": "object",
+///     "properties": {
+///         "answer": { "type": "string" }
+///     }
+/// });
+/// StrictSchema.transform(&mut schema);
+///
+/// assert_eq!(schema.get("additionalProperties"), Some(&false.into()));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StrictSchema;
+
+impl Transform for StrictSchema {
+    fn transform(&mut self, schema: &mut Schema) {
+        if let Some(obj) = schema.as_object_mut() {
+            if let Some(Value::Object(properties)) = obj.get("properties") {
+                let required = properties.keys().cloned().map(Value::String).collect();
+                obj.insert("required".to_string(), Value::Array(required));
+                obj.insert("additionalProperties".to_string(), Value::Bool(false));
+            }
+        }
+        transform_subschemas(self, schema);
+    }
+}
+
+/// Inlines local `$ref` references, so the schema doesn't use `$defs`.
+///
+/// `schemars` places schemas of nested types in `$defs` and refers to them with `$ref`, which
+/// some providers (or their strict modes) don't support. This transform replaces every reference
+/// to `#/$defs/...` or `#/definitions/...` with a copy of the referenced schema and removes
+/// definitions, producing a self-contained schema. Keywords placed next to `$ref`, e.g.
+/// `description`, are kept.
+///
+/// Recursive types can't be inlined, references forming a cycle are kept together with
+/// definitions.
+///
+/// # Example
+///
+/// ```rust
+/// use agentai::schema::InlineRefs;
+/// use schemars::transform::Transform;
+/// use schemars::json_schema;
+///
+/// let mut schema = json_schema!({
+///     "type": "object",
+///     "properties": {
+///         "address": { "$ref": "#/$defs/Address" }
+///     },
+///     "$defs": {
+///         "Address": { "type": "string" }
+///     }
+/// });
+/// InlineRefs.transform(&mut schema);
+///
+/// assert_eq!(schema.as_value()["properties"]["address"]["type"], "string");
+/// assert_eq!(schema.get("$defs"), None);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InlineRefs;
+
+/// Keywords holding definitions of subschemas referenced with `$ref`
+const DEFINITIONS_KEYWORDS: [&str; 2] = ["$defs", "definitions"];
+
+impl Transform for InlineRefs {
+    fn transform(&mut self, schema: &mut Schema) {
+        let root = schema.as_value().clone();
+        let Some(obj) = schema.as_object_mut() else {
+            return;
+        };
+        let definitions: Vec<_> = DEFINITIONS_KEYWORDS
+            .iter()
+            .filter_map(|keyword| obj.remove_entry(*keyword))
+            .collect();
+        if definitions.is_empty() {
+            return;
+        }
+        let mut stack = vec![];
+        let mut unresolved = false;
+        for value in obj.values_mut() {
+            unresolved |= inline_refs(value, &root, &mut stack);
+        }
+        if unresolved {
+            obj.extend(definitions);
+        }
+    }
+}
+
+/// Replaces local references in `value` with referenced schemas, returns `true` if some
+/// references could not be inlined. `stack` holds references being inlined, to detect cycles.
+fn inline_refs(value: &mut Value, root: &Value, stack: &mut Vec<String>) -> bool {
+    match value {
+        Value::Object(obj) => {
+            if let Some(Value::String(reference)) = obj.get("$ref") {
+                let target = reference
+                    .strip_prefix('#')
+                    .filter(|_| !stack.contains(reference))
+                    .and_then(|pointer| root.pointer(pointer));
+                let Some(Value::Object(target)) = target else {
+                    return true;
+                };
+                let reference = reference.clone();
+                let mut inlined = target.clone();
+                obj.remove("$ref");
+                // Keywords next to the reference take precedence over the referenced schema
+                inlined.extend(std::mem::take(obj));
+                *obj = inlined;
+                stack.push(reference);
+                let unresolved = inline_refs(value, root, stack);
+                stack.pop();
+                return unresolved;
+            }
+            let mut unresolved = false;
+            for (keyword, value) in obj.iter_mut() {
+                // Values of these keywords are data, not schemas
+                if !matches!(keyword.as_str(), "enum" | "const" | "default" | "examples") {
+                    unresolved |= inline_refs(value, root, stack);
+                }
+            }
+            unresolved
+        }
+        Value::Array(items) => items.iter_mut().fold(false, |unresolved, item| {
+            inline_refs(item, root, stack) | unresolved
+        }),
+        _ => false,
+    }
+}
+
+/// Structured output capabilities of a provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaSupport {
+    /// Provider enforces the response format, otherwise the schema must be provided in the prompt
+    pub response_format: bool,
+    /// Provider enforces the response format in strict mode, schema must be converted
+    /// using [`StrictSchema`]
+    pub strict: bool,
+    /// Provider returns structured output most reliably as arguments of a tool call, so the
+    /// schema is sent as a tool the model calls with the answer
+    pub tool_output: bool,
+}
+
+impl SchemaSupport {
+    /// Detects structured output capabilities of a provider.
+    ///
+    /// - OpenAI and OpenAI-compatible providers use strict mode for the response format,
+    /// - Gemini accepts the response format, but doesn't support strict mode keywords,
+    /// - Anthropic ignores the response format, the answer is returned by calling a tool,
+    /// - Cohere ignores the response format, so the schema goes to the prompt.
+    ///
+    /// ```rust
+    /// use agentai::schema::SchemaSupport;
+    /// use genai::adapter::AdapterKind;
+    ///
+    /// assert!(SchemaSupport::detect(AdapterKind::OpenAI).strict);
+    /// assert!(!SchemaSupport::detect(AdapterKind::Anthropic).response_format);
+    /// assert!(SchemaSupport::detect(AdapterKind::Anthropic).tool_output);
+    /// ```
+    pub fn detect(adapter_kind: AdapterKind) -> Self {
+        match adapter_kind {
+            AdapterKind::OpenAI
+            | AdapterKind::Ollama
+            | AdapterKind::Groq
+            | AdapterKind::Xai
+            | AdapterKind::DeepSeek => Self {
+                response_format: true,
+                strict: true,
+                tool_output: false,
+            },
+            AdapterKind::Gemini => Self {
+                response_format: true,
+                strict: false,
+                tool_output: false,
+            },
+            AdapterKind::Anthropic => Self {
+                response_format: false,
+                strict: false,
+                tool_output: true,
+            },
+            AdapterKind::Cohere => Self {
+                response_format: false,
+                strict: false,
+                tool_output: false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schemars::{json_schema, schema_for, JsonSchema};
+    use serde_json::json;
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Inner {
+        value: i32,
+    }
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Outer {
+        name: String,
+        comment: Option<String>,
+        inner: Inner,
+    }
+
+    #[test]
+    fn test_strict_schema_marks_all_required() {
+        let mut schema = json_schema!({
+            "type": "object",
+            "properties": {
+                "a": { "type": "string" },
+                "b": { "type": ["string", "null"] }
+            },
+            "required": ["a"]
+        });
+        StrictSchema.transform(&mut schema);
+
+        assert_eq!(schema.get("required"), Some(&json!(["a", "b"])));
+        assert_eq!(schema.get("additionalProperties"), Some(&json!(false)));
+    }
+
+    #[test]
+    fn test_inline_refs() {
+        let mut schema = schema_for!(Outer);
+        InlineRefs.transform(&mut schema);
+
+        assert_eq!(schema.get("$defs"), None);
+        let inner = &schema.as_value()["properties"]["inner"];
+        assert_eq!(inner["type"], json!("object"));
+        assert_eq!(inner["properties"]["value"]["type"], json!("integer"));
+        assert!(!schema.as_value().to_string().contains("$ref"));
+    }
+
+    #[test]
+    fn test_inline_refs_keeps_recursive_definitions() {
+        let mut schema = json_schema!({
+            "type": "object",
+            "properties": {
+                "root": { "$ref": "#/$defs/Node", "description": "Root node" }
+            },
+            "$defs": {
+                "Node": {
+                    "type": "object",
+                    "properties": {
+                        "children": { "type": "array", "items": { "$ref": "#/$defs/Node" } }
+                    }
+                }
+            }
+        });
+        InlineRefs.transform(&mut schema);
+
+        let root = &schema.as_value()["properties"]["root"];
+        assert_eq!(root["description"], json!("Root node"));
+        assert_eq!(
+            root["properties"]["children"]["items"],
+            json!({ "$ref": "#/$defs/Node" })
+        );
+        assert!(schema.get("$defs").is_some());
+    }
+
+    #[test]
+    fn test_strict_schema_applies_to_definitions() {
+        let mut schema = schema_for!(Outer);
+        StrictSchema.transform(&mut schema);
+
+        let inner = &schema.as_value()["$defs"]["Inner"];
+        assert_eq!(inner["additionalProperties"], json!(false));
+        assert_eq!(inner["required"], json!(["value"]));
+        let required = schema.get("required").and_then(Value::as_array).unwrap();
+        assert_eq!(required.len(), 3);
+        assert!(required.contains(&json!("comment")));
+    }
+}
@@ -0,0 +1,282 @@
+//! # Streaming
+//!
+//! [`Agent::run_stream`](crate::Agent::run_stream) streams the answer while the model is
+//! generating it. Reasoning of the model, if the provider exposes it, is reported separately
+//! from the answer:
+//!
+//! ```rust,no_run
+//! # use agentai::Agent;
+//! # use agentai::stream::AgentEvent;
+//! # use futures::StreamExt;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let mut agent = Agent::new("You are a useful assistant");
+//! let mut stream = agent.run_stream("deepseek-reasoner", "Why is the sky blue?", None);
+//! while let Some(event) = stream.next().await {
+//!     match event? {
+//!         AgentEvent::ReasoningDelta(text) => eprint!("{text}"),
+//!         AgentEvent::TextDelta(text) => print!("{text}"),
+//!         AgentEvent::ToolCallStarted { name, .. } => eprintln!("Calling {name}"),
+//!         AgentEvent::ToolCallFinished { .. } => {}
+//!         AgentEvent::Done => println!(),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Agent::run_stream_typed`](crate::Agent::run_stream_typed) streams structured output while the
+//! model is generating it. Every [`Partial::Incomplete`] item contains fields completed so far,
+//! which allows to fill in a form in UI as soon as each field is known. The last item is
+//! [`Partial::Complete`] with the answer deserialized into the requested type.
+//!
+//! ```rust,no_run
+//! # use agentai::Agent;
+//! # use agentai::stream::Partial;
+//! # use futures::StreamExt;
+//! # use schemars::JsonSchema;
+//! # use serde::Deserialize;
+//! #[derive(Deserialize, JsonSchema)]
+//! struct Contact {
+//!     name: String,
+//!     email: String,
+//!     phone: String,
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let mut agent = Agent::new("You extract contact details");
+//! let mut stream = agent.run_stream_typed::<Contact>(
+//!     "gpt-4o-mini",
+//!     "John Smith, john@example.com, +48 123 456 789",
+//! );
+//! while let Some(item) = stream.next().await {
+//!     match item? {
+//!         Partial::Incomplete(fields) => println!("Known fields: {fields}"),
+//!         Partial::Complete(contact) => println!("Contact: {}", contact.name),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! By default a field is reported only once its value is complete, so numbers and flags never
+//! flicker while the model is typing them. Long text fields, e.g. an answer displayed to the
+//! user, can be streamed as they grow by marking them with the `x-stream` schema extension.
+//! Only string fields of the top-level object can be streamed:
+//!
+//! ```rust
+//! # use schemars::JsonSchema;
+//! # use serde::Deserialize;
+//! #[derive(Deserialize, JsonSchema)]
+//! struct Answer {
+//!     #[schemars(extend("x-stream" = true))]
+//!     answer: String,
+//!     confidence: f32,
+//! }
+//! ```
+
+use serde_json::Value;
+
+/// JSON schema keyword marking fields streamed by [`Agent::run_stream_typed`](crate::Agent::run_stream_typed)
+pub(crate) const STREAM_KEYWORD: &str = "x-stream";
+
+/// Event of a streamed run, see [`Agent::run_stream`](crate::Agent::run_stream)
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentEvent {
+    /// Next part of the answer
+    TextDelta(String),
+    /// Next part of the model reasoning, reported only by providers exposing it while streaming
+    ReasoningDelta(String),
+    /// Tool requested by the model is being executed
+    ToolCallStarted {
+        /// Name of the tool
+        name: String,
+        /// Arguments provided by the model
+        args: Value,
+    },
+    /// Tool finished, with its result or error passed to the model
+    ToolCallFinished {
+        /// Name of the tool
+        name: String,
+        /// Result of the tool, or description of its error
+        result: String,
+    },
+    /// Answer is complete and added to the history
+    Done,
+}
+
+/// Item of a structured output stream, see [`Agent::run_stream_typed`](crate::Agent::run_stream_typed)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Partial<D> {
+    /// JSON value containing fields completed so far. Values still being generated are omitted,
+    /// except text answers and fields marked with `x-stream`, which are reported as they grow.
+    Incomplete(Value),
+    /// Complete answer, deserialized into the requested type
+    Complete(D),
+}
+
+/// Parses beginning of a JSON document, keeping only values which are complete.
+///
+/// Objects and arrays which are not closed yet are closed, strings, numbers and keys without
+/// values which may still change are dropped. Returns `None` until the first object or array
+/// is opened. Text before the JSON document, e.g. opening of a Markdown code block, is skipped.
+///
+/// ```rust
+/// # use agentai::stream::parse_partial_json;
+/// # use serde_json::json;
+/// let value = parse_partial_json(r#"{"name": "John", "tags": ["a", "b"], "email": "jo"#);
+/// assert_eq!(value, Some(json!({"name": "John", "tags": ["a", "b"]})));
+/// ```
+pub fn parse_partial_json(text: &str) -> Option<Value> {
+    parse_partial_json_streamed(text, &[])
+}
+
+/// Parses beginning of a JSON document like [`parse_partial_json`], but strings of top-level
+/// object fields listed in `streamed` are included while they are still being generated.
+pub(crate) fn parse_partial_json_streamed(text: &str, streamed: &[String]) -> Option<Value> {
+    let start = text.find(['{', '['])?;
+    let text = &text[start..];
+
+    // Open containers, with a flag telling if the object is expecting a key
+    let mut stack: Vec<(u8, bool)> = Vec::new();
+    let mut in_string = false;
+    let mut is_key = false;
+    let mut escaped = false;
+    let mut in_scalar = false;
+    // Start of the last key of the top-level object, and whether its value is streamed
+    let mut key_start = 0;
+    let mut is_streamed = false;
+    // Prefix ending inside a streamed string, with quote and brackets closing it
+    let mut streamed_prefix: Option<(usize, String)> = None;
+    // Longest prefix ending with a complete value, with brackets closing it
+    let mut complete: Option<(usize, String)> = None;
+    let closing = |stack: &[(u8, bool)]| -> String {
+        stack
+            .iter()
+            .rev()
+            .map(|(open, _)| if *open == b'{' { '}' } else { ']' })
+            .collect()
+    };
+
+    for (index, byte) in text.bytes().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => {
+                    in_string = false;
+                    streamed_prefix = None;
+                    if is_key {
+                        is_streamed = stack.len() == 1
+                            && streamed.iter().any(|name| *name == text[key_start..index]);
+                    } else {
+                        complete = Some((index + 1, closing(&stack)));
+                    }
+                }
+                _ if is_streamed && !is_key && stack.len() == 1 => {
+                    streamed_prefix = Some((index + 1, format!("\"{}", closing(&stack))));
+                }
+                _ => {}
+            }
+            continue;
+        }
+        if in_scalar && (byte.is_ascii_whitespace() || matches!(byte, b',' | b'}' | b']')) {
+            in_scalar = false;
+            complete = Some((index, closing(&stack)));
+        }
+        match byte {
+            b'"' => {
+                in_string = true;
+                is_key = matches!(stack.last(), Some((b'{', true)));
+                key_start = index + 1;
+            }
+            b'{' | b'[' => {
+                stack.push((byte, byte == b'{'));
+                complete = Some((index + 1, closing(&stack)));
+            }
+            b'}' | b']' => {
+                stack.pop();
+                complete = Some((index + 1, closing(&stack)));
+                if stack.is_empty() {
+                    break;
+                }
+            }
+            b':' => {
+                if let Some((_, expects_key)) = stack.last_mut() {
+                    *expects_key = false;
+                }
+            }
+            b',' => {
+                if let Some((b'{', expects_key)) = stack.last_mut() {
+                    *expects_key = true;
+                }
+            }
+            _ if byte.is_ascii_whitespace() => {}
+            _ => in_scalar = true,
+        }
+    }
+
+    // Streamed string may end in the middle of an escape sequence, fall back to complete values
+    let parse = |(end, closing): (usize, String)| {
+        serde_json::from_str(&format!("{}{closing}", &text[..end])).ok()
+    };
+    streamed_prefix
+        .and_then(parse)
+        .or_else(|| complete.and_then(parse))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_partial_json() {
+        assert_eq!(parse_partial_json(""), None);
+        assert_eq!(parse_partial_json("```json\n{"), Some(json!({})));
+        assert_eq!(parse_partial_json(r#"{"name": "Jo"#), Some(json!({})));
+        assert_eq!(
+            parse_partial_json(r#"{"name": "John", "age""#),
+            Some(json!({"name": "John"}))
+        );
+        // Number may still have more digits
+        assert_eq!(
+            parse_partial_json(r#"{"name": "John", "age": 4"#),
+            Some(json!({"name": "John"}))
+        );
+        assert_eq!(
+            parse_partial_json(r#"{"name": "John", "age": 42, "tags": [true, "a\"b", nu"#),
+            Some(json!({"name": "John", "age": 42, "tags": [true, "a\"b"]}))
+        );
+        assert_eq!(
+            parse_partial_json(r#"{"items": [{"id": 1}, {"id": 2, "name": "x"}], "done": false}"#),
+            Some(json!({"items": [{"id": 1}, {"id": 2, "name": "x"}], "done": false}))
+        );
+        // Text after the document is ignored
+        assert_eq!(parse_partial_json("[1, 2]\n```"), Some(json!([1, 2])));
+    }
+
+    #[test]
+    fn test_parse_partial_json_streamed() {
+        let streamed = ["answer".to_string()];
+        let parse = |text| parse_partial_json_streamed(text, &streamed);
+        assert_eq!(parse(r#"{"answer": ""#), Some(json!({})));
+        assert_eq!(
+            parse(r#"{"answer": "It is"#),
+            Some(json!({"answer": "It is"}))
+        );
+        // Incomplete escape sequence is not reported
+        assert_eq!(parse(r#"{"answer": "It is \u00"#), Some(json!({})));
+        assert_eq!(
+            parse(r#"{"answer": "Yes", "confidence": 0.9"#),
+            Some(json!({"answer": "Yes"}))
+        );
+        // Only fields of the top-level object are streamed
+        assert_eq!(
+            parse(r#"{"items": [{"answer": "N"#),
+            Some(json!({"items": [{}]}))
+        );
+        assert_eq!(parse(r#"{"title": "Ans"#), Some(json!({})));
+    }
+}
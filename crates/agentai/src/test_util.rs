@@ -0,0 +1,14 @@
+//! Helpers shared by tests of multiple modules
+
+/// Starts a server which accepts connections, but never responds, returns its base URL
+pub(crate) async fn hanging_server() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut connections = vec![];
+        while let Ok((stream, _)) = listener.accept().await {
+            connections.push(stream);
+        }
+    });
+    base_url
+}
@@ -0,0 +1,205 @@
+//! # Text Processing
+//!
+//! Helpers for preparing large documents for agents, e.g. in RAG or summarization pipelines.
+//!
+//! [`chunk`] splits a document into parts small enough to be passed to a model or a tool.
+//! Sizes are estimated with [`estimate_tokens`], the same estimator used by
+//! [context window management](crate::context).
+//!
+//! ```rust
+//! use agentai::text::{chunk, ChunkConfig};
+//!
+//! let document = "First paragraph.\n\nSecond paragraph. It has two sentences.";
+//! let chunks = chunk(document, ChunkConfig { max_tokens: 8, overlap: 0 });
+//! assert_eq!(chunks, ["First paragraph.", "Second paragraph.", "It has two sentences."]);
+//! ```
+
+use crate::context::{estimate_tokens, CHARS_PER_TOKEN};
+
+/// Configuration of [`chunk`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkConfig {
+    /// Maximum estimated number of tokens in a chunk
+    pub max_tokens: usize,
+    /// Estimated number of tokens at the end of a chunk repeated at the start of the next one,
+    /// so content split between chunks is not lost
+    pub overlap: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 512,
+            overlap: 64,
+        }
+    }
+}
+
+/// Boundaries at which text is split, from the most to the least natural
+#[derive(Debug, Clone, Copy)]
+enum Boundary {
+    Paragraph,
+    Sentence,
+    Word,
+}
+
+const BOUNDARIES: [Boundary; 3] = [Boundary::Paragraph, Boundary::Sentence, Boundary::Word];
+
+/// Splits text into chunks of at most `max_tokens` estimated tokens.
+///
+/// Text is split on paragraphs, paragraphs too long for a single chunk are split on sentences,
+/// then on words. Only words longer than a whole chunk are cut in the middle. Consecutive parts
+/// are joined into chunks as long as they fit, and the end of every chunk, up to `overlap`
+/// tokens, is repeated at the start of the next one. Chunks are trimmed, whitespace-only
+/// chunks are skipped.
+pub fn chunk(text: &str, config: ChunkConfig) -> Vec<String> {
+    let max_tokens = config.max_tokens.max(1);
+    let mut segments = vec![];
+    split(text, 0, max_tokens, &mut segments);
+    let tokens: Vec<usize> = segments
+        .iter()
+        .map(|segment| estimate_tokens(segment))
+        .collect();
+
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < segments.len() {
+        let mut end = start;
+        let mut chunk_tokens = 0;
+        while end < segments.len() && (end == start || chunk_tokens + tokens[end] <= max_tokens) {
+            chunk_tokens += tokens[end];
+            end += 1;
+        }
+        let chunk = segments[start..end].concat();
+        if !chunk.trim().is_empty() {
+            chunks.push(chunk.trim().to_string());
+        }
+        if end == segments.len() {
+            break;
+        }
+        // Next chunk starts with the end of this one, but always moves forward
+        let mut next = end;
+        let mut overlap_tokens = 0;
+        while next > start + 1 && overlap_tokens + tokens[next - 1] <= config.overlap {
+            overlap_tokens += tokens[next - 1];
+            next -= 1;
+        }
+        start = next;
+    }
+    chunks
+}
+
+/// Splits text into consecutive segments fitting into `max_tokens`, using boundaries starting
+/// from `level`
+fn split<'a>(text: &'a str, level: usize, max_tokens: usize, segments: &mut Vec<&'a str>) {
+    if estimate_tokens(text) <= max_tokens {
+        segments.push(text);
+        return;
+    }
+    match BOUNDARIES.get(level) {
+        Some(boundary) => {
+            for piece in pieces(text, *boundary) {
+                split(piece, level + 1, max_tokens, segments);
+            }
+        }
+        // Single word longer than a chunk
+        None => {
+            let max_chars = max_tokens * CHARS_PER_TOKEN;
+            let mut start = 0;
+            for (count, (index, _)) in text.char_indices().enumerate() {
+                if count > 0 && count % max_chars == 0 {
+                    segments.push(&text[start..index]);
+                    start = index;
+                }
+            }
+            segments.push(&text[start..]);
+        }
+    }
+}
+
+/// Splits text after every boundary, whitespace following a boundary belongs to the piece
+/// before it, so pieces joined together give the original text
+fn pieces(text: &str, boundary: Boundary) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        let next = chars.peek().map(|(_, next)| *next);
+        let is_boundary = match boundary {
+            Boundary::Paragraph => c == '\n' && next == Some('\n'),
+            Boundary::Sentence => {
+                c == '\n' || matches!(c, '.' | '!' | '?') && next.is_some_and(char::is_whitespace)
+            }
+            Boundary::Word => c.is_whitespace(),
+        };
+        if is_boundary {
+            while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+            let end = chars.peek().map_or(text.len(), |(index, _)| *index);
+            pieces.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_natural_boundaries() {
+        assert!(chunk("", ChunkConfig::default()).is_empty());
+        assert_eq!(
+            chunk("Short text.", ChunkConfig::default()),
+            ["Short text."]
+        );
+
+        let text = "Rust is fast.\n\nRust is safe. Rust has a friendly compiler!\n\nThe end.";
+        let config = ChunkConfig {
+            max_tokens: 8,
+            overlap: 0,
+        };
+        // Sentences of a long paragraph are joined with neighbours as long as they fit
+        assert_eq!(
+            chunk(text, config),
+            [
+                "Rust is fast.\n\nRust is safe.",
+                "Rust has a friendly compiler!",
+                "The end."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunk_overlap() {
+        let text = "one two three four five six seven eight";
+        let config = ChunkConfig {
+            max_tokens: 5,
+            overlap: 2,
+        };
+        let chunks = chunk(text, config);
+        assert_eq!(
+            chunks,
+            [
+                "one two three",
+                "three four",
+                "four five six",
+                "six seven eight"
+            ]
+        );
+        assert!(chunks.iter().all(|chunk| estimate_tokens(chunk) <= 5));
+    }
+
+    #[test]
+    fn test_chunk_long_word() {
+        let text = "a".repeat(10);
+        let config = ChunkConfig {
+            max_tokens: 1,
+            overlap: 0,
+        };
+        assert_eq!(chunk(&text, config), ["aaaa", "aaaa", "aa"]);
+    }
+}
@@ -5,31 +5,97 @@
 //!
 //! ## Included Toolboxes:
 //!
-//! - `CurrentDateAndTimeToolBox`: A set of tools for querying the current date, time, and performing timezone conversions.
+//! - `CurrentDateAndTimeToolBox`: A set of tools for querying the current date, time, performing timezone conversions and date arithmetic.
 //! - `LocationToolBox`: A tool for retrieving geographical information (latitude and longitude) for a given location using the OpenStreetMap Nominatim API.
+//! - `ScratchpadToolBox`: A simple in-memory scratchpad where an agent can store and retrieve intermediate notes.
+//! - `ReasoningToolBox`: A `think` tool recording reasoning steps of ReAct-style agents.
+//! - `UserInputToolBox`: An `ask_user` tool letting the agent ask the user a clarifying question.
+//! - `EncodingToolBox`: Tools for base64 and URL encoding, and for computing hashes.
+//! - `UnitMathToolBox`: Tools for unit conversions, currency conversions and arithmetic with units.
+//! - `CalculatorToolBox`: A tool evaluating arithmetic expressions.
 //!
 //! For a practical demonstration of how to use these tools, please refer to the `examples/tool_buildin.rs` file.
-use crate::tool::{toolbox, Tool, ToolBox, ToolError, ToolResult};
+use crate::tool::{
+    default_http_timeout, http_client, toolbox, Tool, ToolBox, ToolError, ToolResult,
+};
 use anyhow::anyhow;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, Engine, GeneralPurpose, GeneralPurposeConfig};
+use base64::prelude::BASE64_STANDARD;
+use futures::future::BoxFuture;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use ring::digest;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
 use time::format_description::well_known::{Iso8601, Rfc3339};
-use time::{format_description, Date, OffsetDateTime, Time};
+use time::{format_description, util, Date, Duration, Month, OffsetDateTime, Time};
 use time_tz::{timezones, OffsetDateTimeExt};
 
+mod calculator;
+mod expression;
+mod units;
+pub use calculator::CalculatorToolBox;
+pub use units::UnitMathToolBox;
+
 /// # Current Date and Time Toolbox
 ///
 /// This struct provides tools for getting the current date and time.
 /// The `#[toolbox]` macro exposes methods marked with `#[tool]` to an AI model,
 /// enabling it to answer questions about the current date and time.
-pub struct CurrentDateAndTimeToolBox {}
+///
+/// Current time is taken from a clock, by default the system clock in the local timezone
+/// (UTC if the local offset can't be determined). A custom clock can be provided with
+/// [`CurrentDateAndTimeToolBox::with_clock`], e.g. to pin the time in tests:
+///
+/// ```rust
+/// # use agentai::tool::buildin::CurrentDateAndTimeToolBox;
+/// use time::OffsetDateTime;
+///
+/// // 2024-01-15 10:30:00 UTC
+/// let now = OffsetDateTime::from_unix_timestamp(1_705_314_600).unwrap();
+/// let toolbox = CurrentDateAndTimeToolBox::new().with_clock(move || now);
+/// assert_eq!(toolbox.get_today_date().unwrap(), "2024-01-15");
+/// ```
+pub struct CurrentDateAndTimeToolBox {
+    clock: Clock,
+}
+
+/// Source of the current time used by [`CurrentDateAndTimeToolBox`]
+type Clock = Arc<dyn Fn() -> OffsetDateTime + Send + Sync>;
+
+impl Default for CurrentDateAndTimeToolBox {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(|| {
+                OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc())
+            }),
+        }
+    }
+}
 
 #[toolbox]
 impl CurrentDateAndTimeToolBox {
+    /// Creates a new instance of `CurrentDateAndTimeToolBox` using the system clock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses provided clock as the source of the current time.
+    pub fn with_clock(
+        mut self,
+        clock: impl Fn() -> OffsetDateTime + Send + Sync + 'static,
+    ) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
     /// Use this tool to answer questions like: "What is today's date?".
     /// It returns the date in `YYYY-MM-DD` format.
     /// The date is based on the local timezone of the system.
-    #[tool]
+    #[tool(readonly)]
     pub fn get_today_date(&self) -> ToolResult {
-        let today = OffsetDateTime::now_local().map_err(|err| ToolError::Other(anyhow!(err)))?;
+        let today = (self.clock)();
         today
             .date()
             .format(&Iso8601::DATE)
@@ -39,10 +105,10 @@ impl CurrentDateAndTimeToolBox {
     /// Use this tool to answer questions like: "What time is it?".
     /// It returns the time in `HH:MM:SS` format.
     /// The time is based on the local timezone of the system.
-    #[tool]
+    #[tool(readonly)]
     pub fn get_current_time(&self) -> ToolResult {
-        let now = OffsetDateTime::now_local().map_err(|e| ToolError::Other(anyhow!(e)))?;
-        let format = format_description::parse("[hour]:[minute]:[second]")
+        let now = (self.clock)();
+        let format = format_description::parse_borrowed::<2>("[hour]:[minute]:[second]")
             .map_err(|e| ToolError::Other(anyhow!(e)))?;
         now.format(&format)
             .map_err(|e| ToolError::Other(anyhow!(e)))
@@ -51,16 +117,16 @@ impl CurrentDateAndTimeToolBox {
     /// Use this tool to get the complete current date and time for precise and unambiguous time-stamping.
     /// For example, to answer "What is the current timestamp?".
     /// Returns a timestamp in the standard ISO 8601 format (e.g., "2023-10-27T10:30:00+00:00").
-    #[tool]
+    #[tool(readonly)]
     pub fn get_current_datetime(&self) -> ToolResult {
-        let now = OffsetDateTime::now_local().map_err(|e| ToolError::Other(anyhow!(e)))?;
+        let now = (self.clock)();
         now.format(&Rfc3339)
             .map_err(|e| ToolError::Other(anyhow!(e)))
         // Ok(now.to_string())
     }
 
     /// Use this tool to find the day of the week for a given date. For example, to answer "What day of the week was 2024-01-01?".
-    #[tool]
+    #[tool(readonly)]
     pub fn get_day_of_week(
         &self,
         /// Date in `YYYY-MM-DD` format
@@ -74,7 +140,7 @@ impl CurrentDateAndTimeToolBox {
     /// Use this tool to answer questions like: "What time is it in Tokyo?".
     /// You must provide the timezone as a string
     /// It returns the time in `HH:MM:SS` format for that zone.
-    #[tool]
+    #[tool(readonly)]
     pub fn get_time_in_timezone(
         &self,
         /// Timezone provided in IANA timezone names format (e.g., "America/New_York", "Europe/London", "Asia/Tokyo").
@@ -82,9 +148,8 @@ impl CurrentDateAndTimeToolBox {
     ) -> ToolResult {
         let tz = timezones::get_by_name(&timezone)
             .ok_or_else(|| ToolError::Other(anyhow!("Unknown timezone: {}", timezone)))?;
-        let now_utc = OffsetDateTime::now_utc();
-        let now_in_tz = now_utc.to_timezone(tz);
-        let format = format_description::parse("[hour]:[minute]:[second]")
+        let now_in_tz = (self.clock)().to_timezone(tz);
+        let format = format_description::parse_borrowed::<2>("[hour]:[minute]:[second]")
             .map_err(|e| ToolError::Other(anyhow!(e)))?;
         now_in_tz
             .format(&format)
@@ -93,7 +158,7 @@ impl CurrentDateAndTimeToolBox {
 
     /// Use this tool to convert time between different timezones. For example, to answer "What is 14:00 in New York in Tokyo time?".
     /// You must provide the source timezone, the time to convert, and the target timezone.
-    #[tool]
+    #[tool(readonly)]
     pub fn convert_time(
         &self,
         /// Source timezone provided in IANA timezone names format (e.g., "America/New_York", "Asia/Tokyo").
@@ -110,12 +175,12 @@ impl CurrentDateAndTimeToolBox {
             ToolError::Other(anyhow!("Unknown target timezone: {}", target_timezone))
         })?;
 
-        let time_format = format_description::parse("[hour]:[minute]")
+        let time_format = format_description::parse_borrowed::<2>("[hour]:[minute]")
             .map_err(|e| ToolError::Other(anyhow!(e)))?;
         let parsed_time = Time::parse(&time, &time_format)
             .map_err(|e| ToolError::Other(anyhow!("Invalid time format for '{}': {}", time, e)))?;
 
-        let now_in_source_tz = OffsetDateTime::now_utc().to_timezone(source_tz);
+        let now_in_source_tz = (self.clock)().to_timezone(source_tz);
         let source_datetime = now_in_source_tz.replace_time(parsed_time);
 
         let target_datetime = source_datetime.to_timezone(target_tz);
@@ -124,6 +189,69 @@ impl CurrentDateAndTimeToolBox {
             .format(&time_format)
             .map_err(|e| ToolError::Other(anyhow!(e)))
     }
+
+    /// Use this tool to add or subtract a duration from a date. For example, to answer "What date is 3 weeks after 2024-01-15?".
+    /// Use negative amount to subtract. When adding months or years the day is clamped to the last day of the resulting month.
+    /// It returns the date in `YYYY-MM-DD` format.
+    #[tool(readonly)]
+    pub fn date_add(
+        &self,
+        /// Base date in `YYYY-MM-DD` format
+        date: String,
+        /// Number of units to add, negative values subtract
+        amount: i32,
+        /// Unit of the amount
+        unit: DateUnit,
+    ) -> ToolResult {
+        let date =
+            Date::parse(&date, &Iso8601::DEFAULT).map_err(|err| ToolError::Other(anyhow!(err)))?;
+        let result = match unit {
+            DateUnit::Days => date.checked_add(Duration::days(amount.into())),
+            DateUnit::Weeks => date.checked_add(Duration::weeks(amount.into())),
+            DateUnit::Months => add_months(date, amount),
+            DateUnit::Years => add_months(date, amount.saturating_mul(12)),
+        }
+        .ok_or_else(|| ToolError::Other(anyhow!("Resulting date is out of range")))?;
+        result
+            .format(&Iso8601::DATE)
+            .map_err(|err| ToolError::Other(anyhow!(err)))
+    }
+
+    /// Use this tool to calculate the number of days between two dates. For example, to answer "How many days until 2024-12-24?".
+    /// It returns the number of days from start date to end date, negative if end date is before start date.
+    #[tool(readonly)]
+    pub fn date_diff(
+        &self,
+        /// Start date in `YYYY-MM-DD` format
+        start_date: String,
+        /// End date in `YYYY-MM-DD` format
+        end_date: String,
+    ) -> ToolResult {
+        let start = Date::parse(&start_date, &Iso8601::DEFAULT)
+            .map_err(|err| ToolError::Other(anyhow!(err)))?;
+        let end = Date::parse(&end_date, &Iso8601::DEFAULT)
+            .map_err(|err| ToolError::Other(anyhow!(err)))?;
+        Ok(format!("{} days", (end - start).whole_days()))
+    }
+}
+
+/// Unit used by `date_add` tool
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DateUnit {
+    Days,
+    Weeks,
+    Months,
+    Years,
+}
+
+/// Adds months to the date, clamping the day to the length of resulting month
+fn add_months(date: Date, months: i32) -> Option<Date> {
+    let total = (date.year() * 12 + date.month() as i32 - 1).checked_add(months)?;
+    let year = total.div_euclid(12);
+    let month = Month::try_from((total.rem_euclid(12) + 1) as u8).ok()?;
+    let day = date.day().min(util::days_in_month(month, year));
+    Date::from_calendar_date(year, month, day).ok()
 }
 
 #[derive(serde::Deserialize)]
@@ -143,15 +271,36 @@ struct LocationResponse {
 ///
 /// Please remember to follow Nominatim Usage Policy
 /// <https://operations.osmfoundation.org/policies/nominatim/>
-pub struct LocationToolBox;
+pub struct LocationToolBox {
+    client: reqwest::Client,
+}
+
+impl Default for LocationToolBox {
+    fn default() -> Self {
+        Self {
+            client: http_client(default_http_timeout()),
+        }
+    }
+}
 
 #[toolbox]
 impl LocationToolBox {
+    /// Creates a new `LocationToolBox` using default HTTP client.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to share connection pool or to configure proxy.
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
     /// Use this tool to get the geographical location (latitude and longitude) of a place.
     /// For example, to answer "Where is the Eiffel Tower?". You can search using not only a city name
     /// but also more specific details, like a full street address.
     /// It returns the display name, latitude, and longitude.
-    #[tool]
+    #[tool(readonly)]
     pub async fn get_location(
         &self,
         /// The name of the location to search for (e.g., "Eiffel Tower", "New York City").
@@ -159,8 +308,8 @@ impl LocationToolBox {
     ) -> ToolResult {
         let url = format!("https://nominatim.openstreetmap.org/search?q={location}&format=jsonv2");
 
-        let client = reqwest::Client::new();
-        let response = client
+        let response = self
+            .client
             .get(&url)
             // Nominatim API requires a User-Agent header.
             .header("User-Agent", "rust-agentai-client")
@@ -195,22 +344,303 @@ impl LocationToolBox {
     }
 }
 
+/// # Scratchpad Toolbox
+///
+/// This struct provides a simple key-value scratchpad that an AI agent can use as externalized
+/// working memory. It is useful for multi-step reasoning, where the agent needs to store
+/// intermediate results and read them back in later turns.
+///
+/// Notes are kept in memory and are owned by the toolbox instance, they are not persisted
+/// anywhere. Dropping the toolbox drops all notes.
+#[derive(Default)]
+pub struct ScratchpadToolBox {
+    notes: Mutex<HashMap<String, String>>,
+}
+
+#[toolbox]
+impl ScratchpadToolBox {
+    /// Creates a new, empty `ScratchpadToolBox`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use this tool to save a note on the scratchpad for later use.
+    /// If a note with the same key already exists, it will be overwritten.
+    #[tool]
+    pub fn note_write(
+        &self,
+        /// Key under which the note will be stored
+        key: String,
+        /// Content of the note
+        value: String,
+    ) -> ToolResult {
+        let mut notes = self
+            .notes
+            .lock()
+            .map_err(|e| ToolError::Other(anyhow!("Scratchpad lock poisoned: {}", e)))?;
+        notes.insert(key.clone(), value);
+        Ok(format!("Note '{key}' saved"))
+    }
+
+    /// Use this tool to read a note previously saved on the scratchpad.
+    #[tool(readonly)]
+    pub fn note_read(
+        &self,
+        /// Key of the note to read
+        key: String,
+    ) -> ToolResult {
+        let notes = self
+            .notes
+            .lock()
+            .map_err(|e| ToolError::Other(anyhow!("Scratchpad lock poisoned: {}", e)))?;
+        notes.get(&key).cloned().ok_or_else(|| {
+            let mut keys: Vec<&String> = notes.keys().collect();
+            keys.sort();
+            ToolError::LLMError(format!(
+                "No note found for key '{key}'. Available keys: {keys:?}"
+            ))
+        })
+    }
+}
+
+/// # Reasoning Toolbox
+///
+/// This struct provides a `think` tool, which the agent calls to record a reasoning step. The
+/// thought is returned unchanged, so it becomes part of the conversation history, without any
+/// side effects. Forcing explicit reasoning steps improves multi-step reasoning of ReAct-style
+/// agents, especially on weaker models without native reasoning. Combine it with
+/// [`Agent::with_max_iterations`](crate::Agent::with_max_iterations), because every thought
+/// uses one iteration.
+#[derive(Default)]
+pub struct ReasoningToolBox {}
+
+#[toolbox]
+impl ReasoningToolBox {
+    /// Creates a new `ReasoningToolBox`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use this tool to think step by step before acting or answering. Write down your
+    /// reasoning, e.g. what you know, what is missing and what to do next. It doesn't obtain
+    /// new information or change anything, it only records the thought.
+    #[tool(readonly)]
+    pub fn think(
+        &self,
+        /// Your reasoning step
+        thought: String,
+    ) -> ToolResult {
+        Ok(thought)
+    }
+}
+
+/// # User Input Toolbox
+///
+/// This struct provides an `ask_user` tool, which the agent calls to ask the user a question
+/// in the middle of a run, e.g. to clarify an ambiguous request. The answer is returned to the
+/// model as the tool result.
+///
+/// By default the question is printed to the standard output and the answer is read from the
+/// standard input. Applications with their own user interface provide a handler instead:
+///
+/// ```rust
+/// # use agentai::tool::buildin::UserInputToolBox;
+/// let toolbox = UserInputToolBox::with_handler(|question| async move {
+///     // Show the question in the UI and wait for the answer
+///     Ok(format!("No answer to: {question}"))
+/// });
+/// ```
+pub struct UserInputToolBox {
+    ask: AskUser,
+}
+
+/// Function asking the user a question, see [`UserInputToolBox::with_handler`]
+type AskUser = Box<dyn Fn(String) -> BoxFuture<'static, anyhow::Result<String>> + Send + Sync>;
+
+impl Default for UserInputToolBox {
+    fn default() -> Self {
+        Self::with_handler(|question| async move {
+            tokio::task::spawn_blocking(move || {
+                use std::io::Write;
+
+                let mut stdout = std::io::stdout();
+                write!(stdout, "{question}\n> ")?;
+                stdout.flush()?;
+                let mut answer = String::new();
+                if std::io::stdin().read_line(&mut answer)? == 0 {
+                    anyhow::bail!("Standard input is closed, user can't answer");
+                }
+                Ok(answer.trim().to_string())
+            })
+            .await?
+        })
+    }
+}
+
+#[toolbox]
+impl UserInputToolBox {
+    /// Creates a new `UserInputToolBox` asking questions in the terminal.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `UserInputToolBox` asking questions with provided handler.
+    ///
+    /// The handler receives the question and returns the answer of the user. Errors returned by
+    /// the handler are returned by the tool as [`ToolError::Other`].
+    pub fn with_handler<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<String>> + Send + 'static,
+    {
+        Self {
+            ask: Box::new(move |question| Box::pin(handler(question))),
+        }
+    }
+
+    /// Use this tool to ask the user a question when you need information only the user has,
+    /// e.g. to clarify an ambiguous request or to confirm a decision. Ask one short, specific
+    /// question at a time.
+    #[tool(non_idempotent)]
+    pub async fn ask_user(
+        &self,
+        /// Question to the user
+        question: String,
+    ) -> ToolResult {
+        let answer = (self.ask)(question).await?;
+        if answer.trim().is_empty() {
+            return Ok("The user didn't answer".to_string());
+        }
+        Ok(answer)
+    }
+}
+
+/// Characters encoded by `url_encode`, everything except RFC 3986 unreserved characters
+const URL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Base64 decoding config accepting input with and without padding
+const BASE64_DECODE_CONFIG: GeneralPurposeConfig =
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+
+/// # Encoding Toolbox
+///
+/// This struct provides deterministic encoding and hashing tools. Language models perform
+/// these conversions unreliably, so agents processing data should use tools instead.
+///
+/// Text is encoded as UTF-8, hashes are returned as lowercase hex strings.
+#[derive(Default)]
+pub struct EncodingToolBox {}
+
+#[toolbox]
+impl EncodingToolBox {
+    /// Creates a new instance of `EncodingToolBox`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use this tool to encode text using base64.
+    #[tool(readonly)]
+    pub fn base64_encode(
+        &self,
+        /// Text to encode
+        input: String,
+    ) -> ToolResult {
+        Ok(BASE64_STANDARD.encode(input))
+    }
+
+    /// Use this tool to decode base64 encoded text. Both standard and URL-safe alphabets
+    /// are accepted, padding is optional.
+    #[tool(readonly)]
+    pub fn base64_decode(
+        &self,
+        /// Base64 encoded text
+        input: String,
+    ) -> ToolResult {
+        let input = input.trim();
+        let alphabet = if input.contains(['-', '_']) {
+            alphabet::URL_SAFE
+        } else {
+            alphabet::STANDARD
+        };
+        let bytes = GeneralPurpose::new(&alphabet, BASE64_DECODE_CONFIG)
+            .decode(input)
+            .map_err(|err| ToolError::LLMError(format!("Invalid base64 input: {err}")))?;
+        String::from_utf8(bytes)
+            .map_err(|_| ToolError::LLMError("Decoded data is binary, not UTF-8 text".to_string()))
+    }
+
+    /// Use this tool to percent-encode text, e.g. to use it as a URL query parameter.
+    #[tool(readonly)]
+    pub fn url_encode(
+        &self,
+        /// Text to encode
+        input: String,
+    ) -> ToolResult {
+        Ok(utf8_percent_encode(&input, URL_ENCODE_SET).to_string())
+    }
+
+    /// Use this tool to decode percent-encoded text, e.g. a URL query parameter.
+    #[tool(readonly)]
+    pub fn url_decode(
+        &self,
+        /// Percent-encoded text
+        input: String,
+    ) -> ToolResult {
+        percent_decode_str(&input)
+            .decode_utf8()
+            .map(|text| text.into_owned())
+            .map_err(|_| ToolError::LLMError("Decoded data is not valid UTF-8 text".to_string()))
+    }
+
+    /// Use this tool to compute a hash of text. It returns the hash as a lowercase hex string.
+    #[tool(readonly)]
+    pub fn hash(
+        &self,
+        /// Hash algorithm, one of: sha1, sha256, sha384, sha512
+        algorithm: String,
+        /// Text to hash
+        input: String,
+    ) -> ToolResult {
+        let algorithm = match algorithm.to_lowercase().replace('-', "").as_str() {
+            "sha1" => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            "sha256" => &digest::SHA256,
+            "sha384" => &digest::SHA384,
+            "sha512" => &digest::SHA512,
+            _ => {
+                return Err(ToolError::LLMError(format!(
+                    "Unsupported hash algorithm '{algorithm}'. Supported algorithms: sha1, sha256, sha384, sha512"
+                )))
+            }
+        };
+        Ok(digest::digest(algorithm, input.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tool::ToolBoxSet;
     use time::format_description::well_known::Iso8601;
     use time::{Date, OffsetDateTime};
 
     #[test]
     fn test_get_today_date() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox.get_today_date().unwrap();
         assert!(Date::parse(&result, &Iso8601::DATE).is_ok());
     }
 
     #[test]
     fn test_get_current_time() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox.get_current_time().unwrap();
         let parts: Vec<&str> = result.split(':').collect();
         assert_eq!(parts.len(), 3);
@@ -221,28 +651,78 @@ mod tests {
 
     #[test]
     fn test_get_day_of_week() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox.get_day_of_week("2024-01-01".to_string()).unwrap();
         assert_eq!(result, "Monday");
     }
 
     #[test]
     fn test_get_day_of_week_invalid_date() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox.get_day_of_week("invalid-date".to_string());
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_date_add() {
+        let toolbox = CurrentDateAndTimeToolBox::new();
+        let add = |date: &str, amount, unit| toolbox.date_add(date.to_string(), amount, unit);
+        assert_eq!(add("2024-01-15", 3, DateUnit::Weeks).unwrap(), "2024-02-05");
+        assert_eq!(add("2024-03-01", -1, DateUnit::Days).unwrap(), "2024-02-29");
+        assert_eq!(
+            add("2024-01-31", 1, DateUnit::Months).unwrap(),
+            "2024-02-29"
+        );
+        assert_eq!(add("2024-02-29", 1, DateUnit::Years).unwrap(), "2025-02-28");
+        assert_eq!(
+            add("2024-01-10", -2, DateUnit::Months).unwrap(),
+            "2023-11-10"
+        );
+        assert!(add("invalid-date", 1, DateUnit::Days).is_err());
+    }
+
+    #[test]
+    fn test_date_diff() {
+        let toolbox = CurrentDateAndTimeToolBox::new();
+        let result = toolbox
+            .date_diff("2024-01-01".to_string(), "2024-12-24".to_string())
+            .unwrap();
+        assert_eq!(result, "358 days");
+        let result = toolbox
+            .date_diff("2024-01-10".to_string(), "2024-01-01".to_string())
+            .unwrap();
+        assert_eq!(result, "-9 days");
+    }
+
     #[test]
     fn test_get_current_datetime() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox.get_current_datetime().unwrap();
         assert!(OffsetDateTime::parse(&result, &Iso8601::DEFAULT).is_ok());
     }
 
+    #[test]
+    fn test_with_clock() {
+        // 2024-01-15 10:30:00 UTC
+        let now = OffsetDateTime::from_unix_timestamp(1_705_314_600).unwrap();
+        let toolbox = CurrentDateAndTimeToolBox::new().with_clock(move || now);
+        assert_eq!(toolbox.get_today_date().unwrap(), "2024-01-15");
+        assert_eq!(toolbox.get_current_time().unwrap(), "10:30:00");
+        assert_eq!(
+            toolbox.get_current_datetime().unwrap(),
+            "2024-01-15T10:30:00Z"
+        );
+        assert_eq!(
+            toolbox
+                .get_time_in_timezone("Asia/Tokyo".to_string())
+                .unwrap(),
+            "19:30:00"
+        );
+    }
+
     #[test]
     fn test_get_time_in_timezone() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox
             .get_time_in_timezone("Asia/Tokyo".to_string())
             .unwrap();
@@ -255,14 +735,14 @@ mod tests {
 
     #[test]
     fn test_get_time_in_invalid_timezone() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox.get_time_in_timezone("Invalid/Timezone".to_string());
         assert!(result.is_err());
     }
 
     #[test]
     fn test_convert_time() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox
             .convert_time(
                 "America/New_York".to_string(),
@@ -278,7 +758,7 @@ mod tests {
 
     #[test]
     fn test_convert_time_invalid_input() {
-        let toolbox = CurrentDateAndTimeToolBox {};
+        let toolbox = CurrentDateAndTimeToolBox::new();
         let result = toolbox.convert_time(
             "Invalid/Timezone".to_string(),
             "10:00".to_string(),
@@ -301,9 +781,135 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_scratchpad_write_and_read() {
+        let toolbox = ScratchpadToolBox::new();
+        toolbox
+            .note_write("plan".to_string(), "step one".to_string())
+            .unwrap();
+        assert_eq!(toolbox.note_read("plan".to_string()).unwrap(), "step one");
+
+        toolbox
+            .note_write("plan".to_string(), "step two".to_string())
+            .unwrap();
+        assert_eq!(toolbox.note_read("plan".to_string()).unwrap(), "step two");
+    }
+
+    #[test]
+    fn test_scratchpad_read_missing_key() {
+        let toolbox = ScratchpadToolBox::new();
+        let result = toolbox.note_read("missing".to_string());
+        assert!(matches!(result, Err(ToolError::LLMError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_think() {
+        let toolbox = ReasoningToolBox::new();
+        let thought = "The user asks for a sum, I need both numbers first";
+        let result = toolbox
+            .call_tool("think".to_string(), serde_json::json!({"thought": thought}))
+            .await
+            .unwrap();
+        assert_eq!(result, thought);
+        assert!(!toolbox.has_side_effects("think"));
+    }
+
+    #[tokio::test]
+    async fn test_user_input_ask_user() {
+        let questions = Arc::new(Mutex::new(vec![]));
+        let asked = questions.clone();
+        let mut toolbox = ToolBoxSet::new();
+        toolbox.add_tool(UserInputToolBox::with_handler(move |question| {
+            asked.lock().unwrap().push(question);
+            async { Ok("Paris".to_string()) }
+        }));
+
+        let args = serde_json::json!({"question": "Which city?"});
+        let result = toolbox.call_tool("ask_user".to_string(), args).await;
+        assert_eq!(result.unwrap(), "Paris");
+        assert_eq!(*questions.lock().unwrap(), vec!["Which city?".to_string()]);
+        assert!(!toolbox.is_idempotent("ask_user"));
+
+        let toolbox = UserInputToolBox::with_handler(|_| async { Ok(" ".to_string()) });
+        let result = toolbox.ask_user("Which city?".to_string()).await;
+        assert_eq!(result.unwrap(), "The user didn't answer");
+
+        let toolbox = UserInputToolBox::with_handler(|_| async { anyhow::bail!("UI closed") });
+        let err = toolbox.ask_user("Which city?".to_string()).await;
+        assert_eq!(err.unwrap_err().to_string(), "UI closed");
+    }
+
+    #[test]
+    fn test_encoding() {
+        let toolbox = EncodingToolBox::new();
+        assert_eq!(
+            toolbox.base64_encode("hello?".to_string()).unwrap(),
+            "aGVsbG8/"
+        );
+        assert_eq!(
+            toolbox.base64_decode("aGVsbG8/".to_string()).unwrap(),
+            "hello?"
+        );
+        assert_eq!(
+            toolbox.base64_decode("aGVsbG8_".to_string()).unwrap(),
+            "hello?"
+        );
+        assert_eq!(toolbox.base64_decode("aGk".to_string()).unwrap(), "hi");
+        assert!(matches!(
+            toolbox.base64_decode("not base64!".to_string()),
+            Err(ToolError::LLMError(_))
+        ));
+
+        assert_eq!(
+            toolbox.url_encode("a b&c=ł~".to_string()).unwrap(),
+            "a%20b%26c%3D%C5%82~"
+        );
+        assert_eq!(
+            toolbox
+                .url_decode("a%20b%26c%3D%C5%82~".to_string())
+                .unwrap(),
+            "a b&c=ł~"
+        );
+        assert!(matches!(
+            toolbox.url_decode("%FF".to_string()),
+            Err(ToolError::LLMError(_))
+        ));
+    }
+
+    #[test]
+    fn test_hash() {
+        let toolbox = EncodingToolBox::new();
+        assert_eq!(
+            toolbox
+                .hash("SHA-256".to_string(), "abc".to_string())
+                .unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            toolbox.hash("sha1".to_string(), "abc".to_string()).unwrap(),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert!(matches!(
+            toolbox.hash("md4".to_string(), "abc".to_string()),
+            Err(ToolError::LLMError(_))
+        ));
+    }
+
+    #[test]
+    fn test_scratchpad_side_effects() {
+        let toolbox = ScratchpadToolBox::new();
+        assert!(toolbox.has_side_effects("note_write"));
+        assert!(!toolbox.has_side_effects("note_read"));
+
+        let mut set = ToolBoxSet::new();
+        set.add_tool(toolbox);
+        assert!(!set.has_side_effects("note_read"));
+        assert!(set.has_side_effects("unknown_tool"));
+    }
+
     #[tokio::test]
     async fn test_get_location() {
-        let toolbox = LocationToolBox;
+        let toolbox = LocationToolBox::new();
         let result = toolbox.get_location("Wrocław".to_string()).await;
         assert!(result.is_ok());
         let location_info = result.unwrap();
@@ -318,7 +924,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_location_not_found() {
-        let toolbox = LocationToolBox;
+        let toolbox = LocationToolBox::new();
         let result = toolbox
             .get_location("SomeInvalidPlaceThatDoesNotExist".to_string())
             .await;
@@ -0,0 +1,115 @@
+use super::expression::{self, tokenize, Number};
+use crate::tool::{toolbox, Tool, ToolBox, ToolError, ToolResult};
+
+/// Evaluates arithmetic expression
+fn evaluate(expression: &str) -> Result<Number, String> {
+    let tokens = tokenize(expression, false)?;
+    expression::evaluate(&tokens, |number, _| Ok(number))
+}
+
+/// # Calculator Toolbox
+///
+/// This struct provides an `evaluate` tool for arithmetic. Models often make mistakes in
+/// arithmetic, especially multiplying multi-digit numbers, even when they write down every step.
+///
+/// Integers are calculated exactly, as long as they fit in 128 bits, other numbers use double
+/// precision floating point.
+///
+/// ```rust
+/// # use agentai::tool::buildin::CalculatorToolBox;
+/// let toolbox = CalculatorToolBox::new();
+/// assert_eq!(
+///     toolbox.evaluate("123456789 * 987654321".to_string()).unwrap(),
+///     "121932631112635269"
+/// );
+/// ```
+#[derive(Default)]
+pub struct CalculatorToolBox {}
+
+#[toolbox]
+impl CalculatorToolBox {
+    /// Creates a new `CalculatorToolBox`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use this tool to calculate the value of an arithmetic expression, instead of doing the
+    /// math yourself, e.g. "(1250 * 12 - 300) / 7" or "1.05 ^ 10". Supported operators are
+    /// + - * / % (remainder), ^ (exponentiation) and parentheses. It returns the numeric result.
+    #[tool(readonly)]
+    pub fn evaluate(
+        &self,
+        /// Arithmetic expression to evaluate
+        expression: String,
+    ) -> ToolResult {
+        evaluate(&expression)
+            .map(|result| result.to_string())
+            .map_err(ToolError::LLMError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calculate(expression: &str) -> ToolResult {
+        CalculatorToolBox::new().evaluate(expression.to_string())
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(calculate("2 + 3 * 4").unwrap(), "14");
+        assert_eq!(calculate("(2 + 3) * 4").unwrap(), "20");
+        assert_eq!(calculate("7 / 2").unwrap(), "3.5");
+        assert_eq!(calculate("8 / 2").unwrap(), "4");
+        assert_eq!(calculate("17 % 5").unwrap(), "2");
+        assert_eq!(calculate("0.1 + 0.2").unwrap(), "0.3");
+        assert_eq!(calculate("1_000 * 1.5e3").unwrap(), "1500000");
+        assert_eq!(calculate("-(3 - 5)").unwrap(), "2");
+    }
+
+    #[test]
+    fn test_evaluate_exponentiation() {
+        assert_eq!(calculate("2 ^ 10").unwrap(), "1024");
+        assert_eq!(calculate("2 ** 3 ** 2").unwrap(), "512");
+        assert_eq!(calculate("-2 ^ 2").unwrap(), "-4");
+        assert_eq!(calculate("2 ^ -1").unwrap(), "0.5");
+        assert_eq!(
+            calculate("2 ^ 100").unwrap(),
+            "1267650600228229401496703205376"
+        );
+    }
+
+    #[test]
+    fn test_evaluate_large_integers() {
+        assert_eq!(
+            calculate("123456789 * 987654321").unwrap(),
+            "121932631112635269"
+        );
+        // Integer overflow falls back to floating point
+        assert_eq!(calculate("2 ^ 200").unwrap(), "1.6069380442589903e60");
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        let error = |expression: &str| match calculate(expression) {
+            Err(ToolError::LLMError(message)) => message,
+            result => panic!("Unexpected result {result:?}"),
+        };
+        assert_eq!(error("1 / 0"), "Division by zero");
+        assert_eq!(error("5 % (2 - 2)"), "Division by zero");
+        assert_eq!(error("(1 + 2"), "Missing closing parenthesis");
+        assert_eq!(error("1 +"), "Unexpected end of expression");
+        assert_eq!(error("2 * * 3"), "Unexpected operator '*' in expression");
+        assert_eq!(
+            error("2 3"),
+            "Unexpected number 3 in expression, operator is missing"
+        );
+        assert_eq!(error("2 x 3"), "Unexpected character 'x' in expression");
+        assert_eq!(
+            error("(-8) ^ 0.5"),
+            "Result of -8 ^ 0.5 is not a real number"
+        );
+        assert_eq!(error("10 ^ 400.5"), "Result of 10 ^ 400.5 is too large");
+    }
+}
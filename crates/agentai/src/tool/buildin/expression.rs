@@ -0,0 +1,335 @@
+use std::fmt;
+
+/// Number of an expression, integers are exact as long as they fit in `i128`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Number {
+    Integer(i128),
+    Float(f64),
+}
+
+impl Number {
+    pub(super) fn to_f64(self) -> f64 {
+        match self {
+            Number::Integer(value) => value as f64,
+            Number::Float(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Integer(value) => write!(f, "{value}"),
+            Number::Float(value) => write!(f, "{}", format_number(*value)),
+        }
+    }
+}
+
+/// Value of an expression, see [`evaluate`]
+pub(super) trait Operand: Sized {
+    /// Applies binary operator, one of `+ - * / % ^`
+    fn apply(self, op: char, other: Self) -> Result<Self, String>;
+
+    fn negate(self) -> Result<Self, String>;
+}
+
+impl Operand for Number {
+    /// Falls back to floating point when integer result is not exact
+    fn apply(self, op: char, other: Number) -> Result<Number, String> {
+        if let (Number::Integer(a), Number::Integer(b)) = (self, other) {
+            let exact = match op {
+                '+' => a.checked_add(b),
+                '-' => a.checked_sub(b),
+                '*' => a.checked_mul(b),
+                '/' if b != 0 && a % b == 0 => a.checked_div(b),
+                '%' if b != 0 => a.checked_rem(b),
+                '^' => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+                _ => None,
+            };
+            if let Some(value) = exact {
+                return Ok(Number::Integer(value));
+            }
+        }
+        let (a, b) = (self.to_f64(), other.to_f64());
+        let value = match op {
+            '+' => a + b,
+            '-' => a - b,
+            '*' => a * b,
+            '/' | '%' if b == 0.0 => return Err("Division by zero".to_string()),
+            '/' => a / b,
+            '%' => a % b,
+            _ => a.powf(b),
+        };
+        if value.is_nan() {
+            return Err(format!(
+                "Result of {self} {op} {other} is not a real number"
+            ));
+        }
+        if value.is_infinite() {
+            return Err(format!("Result of {self} {op} {other} is too large"));
+        }
+        Ok(Number::Float(value))
+    }
+
+    fn negate(self) -> Result<Number, String> {
+        Number::Integer(0).apply('-', self)
+    }
+}
+
+/// Formats number without floating point noise, e.g. `0.30000000000000004` as `0.3`
+pub(super) fn format_number(value: f64) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return value.to_string();
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    if !(-6..15).contains(&magnitude) {
+        return format!("{value:e}");
+    }
+    let decimals = (9 - magnitude).max(0) as usize;
+    let formatted = format!("{value:.decimals$}");
+    if formatted.contains('.') {
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        formatted
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Token {
+    Number(Number),
+    Unit(String),
+    Operator(char),
+}
+
+/// Splits arithmetic expression into numbers and operators, `**` is read as `^`.
+///
+/// With `units`, a sequence of characters directly following a number is its unit, so `10 km/h`
+/// is a speed, while `10 km / 2 h` is a division.
+pub(super) fn tokenize(expression: &str, units: bool) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                let exponent_sign = matches!(c, '+' | '-') && number.ends_with(['e', 'E']);
+                let exponent = matches!(c, 'e' | 'E')
+                    && !number.contains(['e', 'E'])
+                    && chars
+                        .clone()
+                        .nth(1)
+                        .is_some_and(|n| n.is_ascii_digit() || matches!(n, '+' | '-'));
+                if c.is_ascii_digit() || c == '.' || c == '_' || exponent || exponent_sign {
+                    number.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let digits = number.replace('_', "");
+            let value = match digits.parse() {
+                Ok(value) => Number::Integer(value),
+                Err(_) => Number::Float(
+                    digits
+                        .parse()
+                        .map_err(|_| format!("Invalid number '{number}'"))?,
+                ),
+            };
+            tokens.push(Token::Number(value));
+            if units {
+                while chars.peek().is_some_and(|c| *c == ' ') {
+                    chars.next();
+                }
+                if chars
+                    .peek()
+                    .is_some_and(|c| c.is_alphabetic() || matches!(c, '°' | 'µ'))
+                {
+                    let mut unit = String::new();
+                    while let Some(&c) = chars.peek() {
+                        let allowed = !c.is_whitespace()
+                            && !matches!(c, '(' | ')' | '+' | ',')
+                            && (c != '-' || unit.ends_with('^'));
+                        if !allowed {
+                            break;
+                        }
+                        unit.push(c);
+                        chars.next();
+                    }
+                    tokens.push(Token::Unit(unit));
+                }
+            }
+        } else if c == '*' {
+            chars.next();
+            let op = if chars.next_if_eq(&'*').is_some() {
+                '^'
+            } else {
+                '*'
+            };
+            tokens.push(Token::Operator(op));
+        } else if matches!(c, '+' | '-' | '/' | '%' | '^' | '(' | ')') {
+            tokens.push(Token::Operator(c));
+            chars.next();
+        } else {
+            return Err(format!("Unexpected character '{c}' in expression"));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser evaluating arithmetic expression
+struct Evaluator<'a, F> {
+    tokens: &'a [Token],
+    position: usize,
+    /// Creates operand from a number with optional unit
+    operand: F,
+}
+
+impl<T, F> Evaluator<'_, F>
+where
+    T: Operand,
+    F: Fn(Number, Option<&str>) -> Result<T, String>,
+{
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next_operator(&mut self, operators: &[char]) -> Option<char> {
+        match self.peek() {
+            Some(&Token::Operator(op)) if operators.contains(&op) => {
+                self.position += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expression(&mut self) -> Result<T, String> {
+        let mut left = self.term()?;
+        while let Some(op) = self.next_operator(&['+', '-']) {
+            left = left.apply(op, self.term()?)?;
+        }
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<T, String> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.next_operator(&['*', '/', '%']) {
+            left = left.apply(op, self.unary()?)?;
+        }
+        Ok(left)
+    }
+
+    /// Sign binds weaker than exponentiation, so `-2^2` is `-4`
+    fn unary(&mut self) -> Result<T, String> {
+        match self.next_operator(&['-', '+']) {
+            Some('-') => self.unary()?.negate(),
+            Some(_) => self.unary(),
+            None => self.power(),
+        }
+    }
+
+    /// Exponentiation is right associative, so `2^3^2` is `2^9`
+    fn power(&mut self) -> Result<T, String> {
+        let base = self.primary()?;
+        match self.next_operator(&['^']) {
+            Some(op) => base.apply(op, self.unary()?),
+            None => Ok(base),
+        }
+    }
+
+    fn primary(&mut self) -> Result<T, String> {
+        match self.peek().cloned() {
+            Some(Token::Number(value)) => {
+                self.position += 1;
+                match self.peek().cloned() {
+                    Some(Token::Unit(unit)) => {
+                        self.position += 1;
+                        (self.operand)(value, Some(&unit))
+                    }
+                    _ => (self.operand)(value, None),
+                }
+            }
+            Some(Token::Operator('(')) => {
+                self.position += 1;
+                let value = self.expression()?;
+                if self.next_operator(&[')']).is_none() {
+                    return Err("Missing closing parenthesis".to_string());
+                }
+                Ok(value)
+            }
+            Some(token) => Err(unexpected(&token)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+fn unexpected(token: &Token) -> String {
+    match token {
+        Token::Number(number) => {
+            format!("Unexpected number {number} in expression, operator is missing")
+        }
+        Token::Unit(unit) => format!("Unexpected unit '{unit}' in expression"),
+        Token::Operator(op) => format!("Unexpected operator '{op}' in expression"),
+    }
+}
+
+/// Evaluates tokens of arithmetic expression, `operand` creates operands from numbers with
+/// optional unit
+pub(super) fn evaluate<T: Operand>(
+    tokens: &[Token],
+    operand: impl Fn(Number, Option<&str>) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut evaluator = Evaluator {
+        tokens,
+        position: 0,
+        operand,
+    };
+    let result = evaluator.expression()?;
+    match evaluator.peek() {
+        Some(token) => Err(unexpected(token)),
+        None => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("2 ** 1.5e3 %(1_000)", false).unwrap(),
+            [
+                Token::Number(Number::Integer(2)),
+                Token::Operator('^'),
+                Token::Number(Number::Float(1500.0)),
+                Token::Operator('%'),
+                Token::Operator('('),
+                Token::Number(Number::Integer(1000)),
+                Token::Operator(')'),
+            ]
+        );
+        assert_eq!(
+            tokenize("10 km/h * 2e-1 s^-2 / 2", true).unwrap(),
+            [
+                Token::Number(Number::Integer(10)),
+                Token::Unit("km/h".to_string()),
+                Token::Operator('*'),
+                Token::Number(Number::Float(0.2)),
+                Token::Unit("s^-2".to_string()),
+                Token::Operator('/'),
+                Token::Number(Number::Integer(2)),
+            ]
+        );
+        // Without units letters are not part of the expression
+        assert_eq!(
+            tokenize("2 km", false).unwrap_err(),
+            "Unexpected character 'k' in expression"
+        );
+    }
+}
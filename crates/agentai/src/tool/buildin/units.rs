@@ -0,0 +1,627 @@
+use super::expression::{evaluate, format_number, tokenize, Number, Operand, Token};
+use crate::tool::{
+    default_http_timeout, http_client, toolbox, Tool, ToolBox, ToolError, ToolResult,
+};
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default source of exchange rates, the free Frankfurter API publishing ECB reference rates
+const DEFAULT_RATES_URL: &str = "https://api.frankfurter.app/latest";
+
+/// Default time for which fetched exchange rates are reused
+const DEFAULT_RATES_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Exponents of base dimensions: length, mass, time, temperature and currency
+type Dimension = [i8; 5];
+
+const DIMENSIONLESS: Dimension = [0; 5];
+const BASE_SYMBOLS: [&str; 4] = ["m", "kg", "s", "K"];
+
+/// Exchange rates, units of currency per one unit of the base currency
+type Rates = HashMap<String, f64>;
+
+#[derive(Debug, Clone, Copy)]
+struct Unit {
+    /// Multiplier converting value in this unit to SI base units
+    factor: f64,
+    /// Offset added after scaling, used only by temperature scales
+    offset: f64,
+    dimension: Dimension,
+}
+
+impl Unit {
+    const fn new(factor: f64, dimension: Dimension) -> Self {
+        Self {
+            factor,
+            offset: 0.0,
+            dimension,
+        }
+    }
+
+    fn powi(self, exponent: i8) -> Self {
+        let mut dimension = self.dimension;
+        dimension.iter_mut().for_each(|d| *d *= exponent);
+        Self::new(self.factor.powi(exponent.into()), dimension)
+    }
+
+    fn mul(self, other: Self) -> Self {
+        let mut dimension = self.dimension;
+        for (d, o) in dimension.iter_mut().zip(other.dimension) {
+            *d += o;
+        }
+        Self::new(self.factor * other.factor, dimension)
+    }
+}
+
+const LENGTH: Dimension = [1, 0, 0, 0, 0];
+const AREA: Dimension = [2, 0, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 1, 0];
+const SPEED: Dimension = [1, 0, -1, 0, 0];
+const FORCE: Dimension = [1, 1, -2, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0];
+
+/// Looks up a simple unit by its symbol or name
+fn lookup_unit(name: &str) -> Option<Unit> {
+    let (factor, dimension) = match name {
+        // Case sensitive symbols
+        "m" => (1.0, LENGTH),
+        "km" => (1e3, LENGTH),
+        "cm" => (1e-2, LENGTH),
+        "mm" => (1e-3, LENGTH),
+        "um" | "µm" => (1e-6, LENGTH),
+        "nm" => (1e-9, LENGTH),
+        "mi" => (1609.344, LENGTH),
+        "yd" => (0.9144, LENGTH),
+        "ft" => (0.3048, LENGTH),
+        "in" => (0.0254, LENGTH),
+        "nmi" => (1852.0, LENGTH),
+        "ha" => (1e4, AREA),
+        "acre" => (4_046.856_422_4, AREA),
+        "l" | "L" => (1e-3, VOLUME),
+        "ml" | "mL" => (1e-6, VOLUME),
+        "gal" => (3.785_411_784e-3, VOLUME),
+        "kg" => (1.0, MASS),
+        "g" => (1e-3, MASS),
+        "mg" => (1e-6, MASS),
+        "t" => (1e3, MASS),
+        "lb" => (0.453_592_37, MASS),
+        "oz" => (0.028_349_523_125, MASS),
+        "s" => (1.0, TIME),
+        "ms" => (1e-3, TIME),
+        "min" => (60.0, TIME),
+        "h" => (3600.0, TIME),
+        "d" => (86400.0, TIME),
+        "mph" => (0.447_04, SPEED),
+        "kn" => (1852.0 / 3600.0, SPEED),
+        "N" => (1.0, FORCE),
+        "Pa" => (1.0, PRESSURE),
+        "kPa" => (1e3, PRESSURE),
+        "bar" => (1e5, PRESSURE),
+        "psi" => (6_894.757_293_168, PRESSURE),
+        "J" => (1.0, ENERGY),
+        "kJ" => (1e3, ENERGY),
+        "cal" => (4.184, ENERGY),
+        "kcal" => (4184.0, ENERGY),
+        "Wh" => (3600.0, ENERGY),
+        "kWh" => (3.6e6, ENERGY),
+        "W" => (1.0, POWER),
+        "kW" => (1e3, POWER),
+        "K" => (1.0, TEMPERATURE),
+        "C" | "°C" | "degC" => {
+            return Some(Unit {
+                factor: 1.0,
+                offset: 273.15,
+                dimension: TEMPERATURE,
+            })
+        }
+        "F" | "°F" | "degF" => {
+            return Some(Unit {
+                factor: 5.0 / 9.0,
+                offset: 459.67 * 5.0 / 9.0,
+                dimension: TEMPERATURE,
+            })
+        }
+        // Names are accepted in any case and in plural
+        _ => {
+            let lower = name.to_lowercase();
+            let singular = lower.strip_suffix('s').unwrap_or(&lower);
+            let symbol = match singular {
+                "meter" | "metre" => "m",
+                "kilometer" | "kilometre" => "km",
+                "centimeter" | "centimetre" => "cm",
+                "millimeter" | "millimetre" => "mm",
+                "mile" => "mi",
+                "yard" => "yd",
+                "foot" | "feet" => "ft",
+                "inche" | "inch" => "in",
+                "hectare" => "ha",
+                "liter" | "litre" => "L",
+                "gallon" => "gal",
+                "gram" => "g",
+                "kilogram" => "kg",
+                "tonne" | "ton" => "t",
+                "pound" => "lb",
+                "ounce" => "oz",
+                "second" | "sec" => "s",
+                "minute" => "min",
+                "hour" => "h",
+                "day" => "d",
+                "knot" => "kn",
+                "kelvin" => "K",
+                "celsiu" => "C",
+                "fahrenheit" => "F",
+                _ => return None,
+            };
+            return lookup_unit(symbol);
+        }
+    };
+    Some(Unit::new(factor, dimension))
+}
+
+/// Returns true if `name` looks like ISO 4217 currency code, e.g. `USD`
+fn is_currency_code(name: &str) -> bool {
+    name.len() == 3 && name.bytes().all(|b| b.is_ascii_uppercase()) && lookup_unit(name).is_none()
+}
+
+/// Collects currency codes used in unit expression
+fn currency_codes(unit: &str) -> impl Iterator<Item = &str> {
+    unit.split(['/', '*', '·', '^'])
+        .map(|part| part.trim_end_matches(|c: char| c.is_ascii_digit() || c == '-'))
+        .filter(|part| is_currency_code(part))
+}
+
+/// Parses unit expression, e.g. `km`, `m/s^2`, `kg*m/s2` or `USD/kWh`
+fn parse_unit(unit: &str, rates: &Rates) -> Result<Unit, String> {
+    let unit = unit.trim();
+    if let Some(simple) = lookup_unit(unit) {
+        return Ok(simple);
+    }
+    let mut result = Unit::new(1.0, DIMENSIONLESS);
+    for (index, group) in unit.split('/').enumerate() {
+        for factor in group.split(['*', '·']) {
+            let (name, exponent) = parse_unit_factor(factor, rates)
+                .ok_or_else(|| format!("Unknown unit '{factor}' in '{unit}'"))?;
+            if name.offset != 0.0 {
+                return Err(format!(
+                    "Temperature scale '{factor}' can't be used in compound unit, use K instead"
+                ));
+            }
+            let exponent = if index == 0 { exponent } else { -exponent };
+            result = result.mul(name.powi(exponent));
+        }
+    }
+    Ok(result)
+}
+
+/// Parses single factor of unit expression with optional exponent, e.g. `s^2` or `m3`
+fn parse_unit_factor(factor: &str, rates: &Rates) -> Option<(Unit, i8)> {
+    let factor = factor.trim();
+    let (name, exponent) = match factor.split_once('^') {
+        Some((name, exponent)) => (name, exponent.parse().ok()?),
+        None => match lookup_base_unit(factor, rates) {
+            Some(unit) => return Some((unit, 1)),
+            None => {
+                let name = factor.trim_end_matches(|c: char| c.is_ascii_digit());
+                (name, factor[name.len()..].parse().ok()?)
+            }
+        },
+    };
+    Some((lookup_base_unit(name, rates)?, exponent))
+}
+
+fn lookup_base_unit(name: &str, rates: &Rates) -> Option<Unit> {
+    if is_currency_code(name) {
+        let rate = rates.get(name)?;
+        Some(Unit::new(1.0 / rate, [0, 0, 0, 0, 1]))
+    } else {
+        lookup_unit(name)
+    }
+}
+
+/// Formats dimension using SI base units, e.g. `kg*m/s^2`
+fn format_dimension(dimension: Dimension, currency: &str) -> String {
+    let symbols = BASE_SYMBOLS.iter().copied().chain([currency]);
+    let mut numerator = Vec::new();
+    let mut denominator = Vec::new();
+    for (symbol, exponent) in symbols.zip(dimension) {
+        let part = |exponent: i8| match exponent {
+            1 => symbol.to_string(),
+            _ => format!("{symbol}^{exponent}"),
+        };
+        match exponent {
+            0 => {}
+            e if e > 0 => numerator.push(part(e)),
+            e => denominator.push(part(-e)),
+        }
+    }
+    let numerator = match numerator.is_empty() {
+        true => "1".to_string(),
+        false => numerator.join("*"),
+    };
+    match denominator.as_slice() {
+        [] => numerator,
+        [single] => format!("{numerator}/{single}"),
+        _ => format!("{numerator}/({})", denominator.join("*")),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Quantity {
+    /// Value in SI base units
+    value: f64,
+    dimension: Dimension,
+}
+
+impl Operand for Quantity {
+    fn apply(mut self, op: char, other: Self) -> Result<Self, String> {
+        match op {
+            '+' | '-' => {
+                if self.dimension != other.dimension {
+                    return Err(format!(
+                        "Can't {} quantities with incompatible units {} and {}",
+                        if op == '+' { "add" } else { "subtract" },
+                        format_dimension(self.dimension, "currency"),
+                        format_dimension(other.dimension, "currency"),
+                    ));
+                }
+                self.value = match op {
+                    '+' => self.value + other.value,
+                    _ => self.value - other.value,
+                };
+            }
+            '*' | '/' => {
+                for (d, o) in self.dimension.iter_mut().zip(other.dimension) {
+                    *d += if op == '*' { o } else { -o };
+                }
+                self.value = match op {
+                    '*' => self.value * other.value,
+                    _ if other.value == 0.0 => return Err("Division by zero".to_string()),
+                    _ => self.value / other.value,
+                };
+            }
+            _ => return Err(format!("Operator '{op}' can't be used with quantities")),
+        }
+        Ok(self)
+    }
+
+    fn negate(mut self) -> Result<Self, String> {
+        self.value = -self.value;
+        Ok(self)
+    }
+}
+
+/// Creates quantity from a number with optional unit
+fn quantity(value: Number, unit: Option<&str>, rates: &Rates) -> Result<Quantity, String> {
+    let value = value.to_f64();
+    let Some(unit) = unit else {
+        return Ok(Quantity {
+            value,
+            dimension: DIMENSIONLESS,
+        });
+    };
+    let unit = parse_unit(unit, rates)?;
+    if unit.offset != 0.0 {
+        return Err("Temperature scales with offset (°C, °F) can't be used in \
+            arithmetic, use K or convert_units tool"
+            .to_string());
+    }
+    Ok(Quantity {
+        value: value * unit.factor,
+        dimension: unit.dimension,
+    })
+}
+
+/// # Unit Math Toolbox
+///
+/// This struct provides tools for unit-aware arithmetic. Models often make mistakes mixing units,
+/// e.g. adding meters to feet. These tools track dimension of every quantity, convert units and
+/// reject operations on incompatible quantities.
+///
+/// Supported are units of length, area, volume, mass, time, speed, force, pressure, energy, power
+/// and temperature, compound units like `km/h` or `kg*m/s^2`, and currencies using ISO 4217 codes.
+///
+/// Exchange rates are fetched from [Frankfurter API](https://frankfurter.dev) publishing European
+/// Central Bank reference rates, and cached for one hour. Rates can be fetched from other compatible
+/// API using [`UnitMathToolBox::with_rates_url`], or provided with
+/// [`UnitMathToolBox::with_exchange_rates`]:
+///
+/// ```rust
+/// # use agentai::tool::buildin::UnitMathToolBox;
+/// # use std::collections::HashMap;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let toolbox = UnitMathToolBox::new()
+///     .with_exchange_rates("EUR", HashMap::from([("USD".to_string(), 1.25)]));
+/// let result = toolbox
+///     .calculate_with_units("2 h * 90 km/h + 5 mi".to_string(), Some("km".to_string()))
+///     .await
+///     .unwrap();
+/// assert_eq!(result, "188.04672 km");
+/// let result = toolbox
+///     .convert_units(100.0, "EUR".to_string(), "USD".to_string())
+///     .await
+///     .unwrap();
+/// assert_eq!(result, "125 USD");
+/// # }
+/// ```
+pub struct UnitMathToolBox {
+    client: reqwest::Client,
+    rates_url: String,
+    rates_ttl: Duration,
+    /// Fetched exchange rates with time of fetching, `None` time marks fixed rates
+    rates: Mutex<Option<(Option<Instant>, Rates)>>,
+}
+
+impl Default for UnitMathToolBox {
+    fn default() -> Self {
+        Self {
+            client: http_client(default_http_timeout()),
+            rates_url: DEFAULT_RATES_URL.to_string(),
+            rates_ttl: DEFAULT_RATES_TTL,
+            rates: Mutex::new(None),
+        }
+    }
+}
+
+#[toolbox]
+impl UnitMathToolBox {
+    /// Creates a new `UnitMathToolBox` fetching exchange rates from Frankfurter API.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to share connection pool or to configure proxy.
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Fetches exchange rates from other API. Response must be JSON object with `base` currency
+    /// code and `rates` object mapping currency codes to amount per one unit of base currency.
+    pub fn with_rates_url(mut self, url: &str) -> Self {
+        self.rates_url = url.to_string();
+        self
+    }
+
+    /// Sets for how long fetched exchange rates are reused, one hour by default.
+    pub fn with_rates_ttl(mut self, ttl: Duration) -> Self {
+        self.rates_ttl = ttl;
+        self
+    }
+
+    /// Uses fixed exchange rates instead of fetching them. `rates` map currency codes to amount
+    /// per one unit of `base` currency.
+    pub fn with_exchange_rates(self, base: &str, mut rates: HashMap<String, f64>) -> Self {
+        rates.insert(base.to_string(), 1.0);
+        *self.rates.lock().unwrap() = Some((None, rates));
+        self
+    }
+
+    /// Use this tool to convert a value between units, e.g. miles to kilometers, pounds to
+    /// kilograms, Fahrenheit to Celsius, km/h to m/s, or between currencies using ISO 4217 codes
+    /// (e.g. USD to EUR). Units must measure the same quantity. It returns the converted value
+    /// with its unit.
+    #[tool(readonly)]
+    pub async fn convert_units(
+        &self,
+        /// Value to convert
+        value: f64,
+        /// Unit of the value, e.g. "mi", "lb", "°F", "km/h", "USD"
+        from_unit: String,
+        /// Target unit, e.g. "km", "kg", "°C", "m/s", "EUR"
+        to_unit: String,
+    ) -> ToolResult {
+        let rates = self
+            .rates_for(currency_codes(&from_unit).chain(currency_codes(&to_unit)))
+            .await?;
+        let from = parse_unit(&from_unit, &rates).map_err(ToolError::LLMError)?;
+        let to = parse_unit(&to_unit, &rates).map_err(ToolError::LLMError)?;
+        if from.dimension != to.dimension {
+            return Err(ToolError::LLMError(format!(
+                "Can't convert {from_unit} to {to_unit}, units measure different quantities"
+            )));
+        }
+        let base = value * from.factor + from.offset;
+        let converted = (base - to.offset) / to.factor;
+        Ok(format!("{} {to_unit}", format_number(converted)))
+    }
+
+    /// Use this tool to calculate with quantities that have units, instead of doing the math
+    /// yourself. Write every quantity as a number directly followed by its unit, e.g.
+    /// "2 h * 90 km/h + 5 mi" or "(1200 kWh * 0.25 EUR/kWh) / 12". Supported operators are
+    /// + - * / and parentheses, put spaces around operators. Only quantities with compatible
+    /// units can be added or subtracted. It returns the result with its unit.
+    #[tool(readonly)]
+    pub async fn calculate_with_units(
+        &self,
+        /// Arithmetic expression with units
+        expression: String,
+        /// Optional unit of the result, e.g. "km". By default SI base units are used
+        result_unit: Option<String>,
+    ) -> ToolResult {
+        let tokens = tokenize(&expression, true).map_err(ToolError::LLMError)?;
+        let units = tokens.iter().filter_map(|token| match token {
+            Token::Unit(unit) => Some(unit.as_str()),
+            _ => None,
+        });
+        let mut codes: Vec<&str> = units.flat_map(currency_codes).collect();
+        codes.extend(result_unit.iter().flat_map(|unit| currency_codes(unit)));
+        let rates = self.rates_for(codes.iter().copied()).await?;
+
+        let result = evaluate(&tokens, |value, unit| quantity(value, unit, &rates))
+            .map_err(ToolError::LLMError)?;
+
+        // Amounts of money are shown in the first currency of the expression
+        let money = codes
+            .first()
+            .filter(|_| result.dimension == [0, 0, 0, 0, 1])
+            .map(|code| code.to_string());
+        match result_unit.or(money) {
+            Some(unit) => {
+                let target = parse_unit(&unit, &rates).map_err(ToolError::LLMError)?;
+                if target.dimension != result.dimension {
+                    return Err(ToolError::LLMError(format!(
+                        "Result has unit {}, it can't be converted to {unit}",
+                        format_dimension(result.dimension, "currency")
+                    )));
+                }
+                let value = (result.value - target.offset) / target.factor;
+                Ok(format!("{} {unit}", format_number(value)))
+            }
+            None => {
+                let unit = format_dimension(result.dimension, "currency");
+                Ok(match unit.as_str() {
+                    "1" => format_number(result.value),
+                    _ => format!("{} {unit}", format_number(result.value)),
+                })
+            }
+        }
+    }
+}
+
+impl UnitMathToolBox {
+    /// Returns exchange rates if any currency is used, fetching them when missing or expired
+    async fn rates_for<'a>(
+        &self,
+        mut codes: impl Iterator<Item = &'a str>,
+    ) -> Result<Rates, ToolError> {
+        let Some(code) = codes.next() else {
+            return Ok(Rates::new());
+        };
+        let cached = self.rates.lock().unwrap().clone();
+        let rates = match cached {
+            Some((fetched, rates))
+                if fetched.is_none_or(|fetched| fetched.elapsed() < self.rates_ttl) =>
+            {
+                rates
+            }
+            _ => {
+                let rates = self.fetch_rates().await?;
+                *self.rates.lock().unwrap() = Some((Some(Instant::now()), rates.clone()));
+                rates
+            }
+        };
+        if let Some(code) = std::iter::once(code)
+            .chain(codes)
+            .find(|code| !rates.contains_key(*code))
+        {
+            return Err(ToolError::LLMError(format!(
+                "Unknown currency {code}, exchange rate is not available"
+            )));
+        }
+        Ok(rates)
+    }
+
+    async fn fetch_rates(&self) -> Result<Rates, ToolError> {
+        #[derive(serde::Deserialize)]
+        struct RatesResponse {
+            base: String,
+            rates: Rates,
+        }
+
+        let response = self
+            .client
+            .get(&self.rates_url)
+            .send()
+            .await
+            .map_err(|e| ToolError::Other(anyhow!("Failed to fetch exchange rates: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(ToolError::Other(anyhow!(
+                "Exchange rates request failed with status: {}",
+                response.status()
+            )));
+        }
+        let mut response: RatesResponse = response
+            .json()
+            .await
+            .map_err(|e| ToolError::Other(anyhow!("Failed to parse exchange rates: {}", e)))?;
+        response.rates.insert(response.base, 1.0);
+        Ok(response.rates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toolbox() -> UnitMathToolBox {
+        UnitMathToolBox::new().with_exchange_rates(
+            "EUR",
+            HashMap::from([("USD".to_string(), 1.25), ("PLN".to_string(), 4.0)]),
+        )
+    }
+
+    async fn convert(value: f64, from: &str, to: &str) -> ToolResult {
+        toolbox()
+            .convert_units(value, from.to_string(), to.to_string())
+            .await
+    }
+
+    async fn calculate(expression: &str, unit: Option<&str>) -> ToolResult {
+        toolbox()
+            .calculate_with_units(expression.to_string(), unit.map(str::to_string))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_convert_units() {
+        assert_eq!(convert(1.0, "mi", "km").await.unwrap(), "1.609344 km");
+        assert_eq!(convert(212.0, "°F", "°C").await.unwrap(), "100 °C");
+        assert_eq!(convert(0.0, "celsius", "K").await.unwrap(), "273.15 K");
+        assert_eq!(convert(36.0, "km/h", "m/s").await.unwrap(), "10 m/s");
+        assert_eq!(convert(2.0, "pounds", "g").await.unwrap(), "907.18474 g");
+        assert_eq!(convert(1.0, "m^3", "L").await.unwrap(), "1000 L");
+        assert_eq!(convert(10.0, "USD", "PLN").await.unwrap(), "32 PLN");
+        assert!(matches!(
+            convert(1.0, "kg", "m").await,
+            Err(ToolError::LLMError(_))
+        ));
+        assert!(matches!(
+            convert(1.0, "GBP", "EUR").await,
+            Err(ToolError::LLMError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_calculate_with_units() {
+        assert_eq!(calculate("5 km + 300 m", None).await.unwrap(), "5300 m");
+        assert_eq!(
+            calculate("5 km + 300 m", Some("km")).await.unwrap(),
+            "5.3 km"
+        );
+        assert_eq!(
+            calculate("100 km / (2 h)", Some("km/h")).await.unwrap(),
+            "50 km/h"
+        );
+        assert_eq!(
+            calculate("10 kg * 9.81 m/s^2", None).await.unwrap(),
+            "98.1 m*kg/s^2"
+        );
+        assert_eq!(calculate("(0.1 + 0.2) * 3", None).await.unwrap(), "0.9");
+        assert_eq!(
+            calculate("1200 kWh * 0.25 EUR/kWh + 10 USD", None)
+                .await
+                .unwrap(),
+            "308 EUR"
+        );
+        assert!(matches!(
+            calculate("5 km + 3 kg", None).await,
+            Err(ToolError::LLMError(_))
+        ));
+        assert!(matches!(
+            calculate("20 °C + 5 K", None).await,
+            Err(ToolError::LLMError(_))
+        ));
+        assert!(matches!(
+            calculate("(5 km", None).await,
+            Err(ToolError::LLMError(_))
+        ));
+    }
+}
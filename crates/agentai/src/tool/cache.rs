@@ -0,0 +1,133 @@
+use crate::tool::ToolOutput;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Cache of tool results, used by [`Agent::with_tool_cache`](crate::Agent::with_tool_cache).
+///
+/// Long conversations often repeat the same tool calls, e.g. fetching the same document in
+/// different turns. Results are cached by tool name and arguments, so an identical call
+/// returns the cached result instead of executing the tool again. Only successful results are
+/// cached.
+///
+/// Only read-only and idempotent tools are cached, tools with side effects (see
+/// [`ToolBox::has_side_effects`](crate::tool::ToolBox::has_side_effects)) or not idempotent
+/// (see [`ToolBox::is_idempotent`](crate::tool::ToolBox::is_idempotent)) are always executed. Read-only tools whose results change between calls should be marked as
+/// non-cacheable.
+///
+/// ```rust
+/// use agentai::Agent;
+/// use agentai::tool::ToolCache;
+/// use std::time::Duration;
+///
+/// let agent = Agent::new("You are a useful assistant").with_tool_cache(
+///     ToolCache::new()
+///         .with_ttl(Duration::from_secs(600))
+///         .with_non_cacheable(["get_current_time"]),
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct ToolCache {
+    ttl: Option<Duration>,
+    non_cacheable: HashSet<String>,
+    entries: Mutex<HashMap<(String, String), (Instant, ToolOutput)>>,
+}
+
+impl ToolCache {
+    /// Creates an empty cache, results are kept until the cache is cleared
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long cached results are valid
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Marks tools whose results are never cached, e.g. tools returning current time
+    pub fn with_non_cacheable(
+        mut self,
+        tools: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.non_cacheable.extend(tools.into_iter().map(Into::into));
+        self
+    }
+
+    /// Returns cached result of the tool called with `arguments`, if it is still valid
+    pub fn get(&self, tool_name: &str, arguments: &Value) -> Option<ToolOutput> {
+        let key = self.key(tool_name, arguments)?;
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&key) {
+            Some((stored, _)) if self.ttl.is_some_and(|ttl| stored.elapsed() >= ttl) => {
+                entries.remove(&key);
+                None
+            }
+            Some((_, output)) => Some(output.clone()),
+            None => None,
+        }
+    }
+
+    /// Stores result of the tool called with `arguments`, unless the tool is non-cacheable
+    pub fn insert(&self, tool_name: &str, arguments: &Value, output: ToolOutput) {
+        if let Some(key) = self.key(tool_name, arguments) {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(key, (Instant::now(), output));
+        }
+    }
+
+    /// Removes all cached results
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Key of the call, object keys are sorted by `serde_json`, so the same arguments given in
+    /// a different order have the same key
+    fn key(&self, tool_name: &str, arguments: &Value) -> Option<(String, String)> {
+        if self.non_cacheable.contains(tool_name) {
+            return None;
+        }
+        Some((tool_name.to_string(), arguments.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tool_cache() {
+        let cache = ToolCache::new().with_non_cacheable(["get_current_time"]);
+        cache.insert(
+            "search",
+            &json!({"query": "rust", "limit": 5}),
+            ToolOutput::new("found"),
+        );
+        cache.insert("get_current_time", &json!({}), ToolOutput::new("12:00"));
+
+        let output = cache.get("search", &json!({"limit": 5, "query": "rust"}));
+        assert_eq!(output, Some(ToolOutput::new("found")));
+        assert_eq!(
+            cache.get("search", &json!({"query": "go", "limit": 5})),
+            None
+        );
+        assert_eq!(cache.get("get_current_time", &json!({})), None);
+
+        cache.clear();
+        assert_eq!(
+            cache.get("search", &json!({"query": "rust", "limit": 5})),
+            None
+        );
+    }
+
+    #[test]
+    fn test_tool_cache_ttl() {
+        let cache = ToolCache::new().with_ttl(Duration::ZERO);
+        cache.insert("search", &json!({}), ToolOutput::new("found"));
+        assert_eq!(cache.get("search", &json!({})), None);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::tool::{
+    Tool, ToolArgumentStream, ToolBox, ToolError, ToolErrorPolicy, ToolOutput, ToolResult,
+};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+type SharedToolBox = Arc<dyn ToolBox>;
+
+/// Toolbox whose tools can be extended while the agent is running.
+///
+/// Works like [`ToolBoxSet`](crate::tool::ToolBoxSet), but toolboxes are added through a shared
+/// reference, so a tool can register new capabilities it discovered, e.g. plugins loaded by an
+/// MCP Server. Every added toolbox changes the [revision](ToolBox::revision), then the
+/// [`Agent`](crate::agent::Agent) initializes the toolboxes again and includes the new tool
+/// definitions in the next request of the same run.
+///
+/// ```rust
+/// use agentai::tool::{toolbox, DynamicToolBox, Tool, ToolBox, ToolError, ToolResult};
+/// use std::sync::Arc;
+///
+/// struct PluginLoader {
+///     tools: Arc<DynamicToolBox>,
+/// }
+///
+/// struct WeatherPlugin;
+///
+/// #[toolbox]
+/// impl WeatherPlugin {
+///     /// Returns current weather in the city
+///     #[tool]
+///     fn get_weather(&self, city: String) -> ToolResult {
+///         Ok(format!("Sunny in {city}"))
+///     }
+/// }
+///
+/// #[toolbox]
+/// impl PluginLoader {
+///     /// Loads the weather plugin, adding weather tools
+///     #[tool]
+///     fn load_weather_plugin(&self) -> ToolResult {
+///         self.tools.add_toolbox(WeatherPlugin);
+///         Ok("Weather plugin loaded".to_string())
+///     }
+/// }
+///
+/// let tools = Arc::new(DynamicToolBox::new());
+/// tools.add_toolbox(PluginLoader { tools: tools.clone() });
+/// // Pass `Some(&tools)` to `Agent::run`
+/// ```
+#[derive(Default)]
+pub struct DynamicToolBox {
+    toolboxes: RwLock<Vec<SharedToolBox>>,
+    revision: AtomicU64,
+}
+
+impl DynamicToolBox {
+    /// Creates a new, empty `DynamicToolBox`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a `ToolBox`, its tools are available from the next request to the model.
+    ///
+    /// As in [`ToolBoxSet`](crate::tool::ToolBoxSet), tool calls are dispatched to the first
+    /// toolbox defining the tool, in the order toolboxes were added.
+    pub fn add_toolbox(&self, toolbox: impl ToolBox + 'static) {
+        self.toolboxes.write().unwrap().push(Arc::new(toolbox));
+        self.revision.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Copy of the toolboxes list, so the lock is not held while tools are running
+    fn toolboxes(&self) -> Vec<SharedToolBox> {
+        self.toolboxes.read().unwrap().clone()
+    }
+
+    /// Finds the first `ToolBox` defining a tool with given name
+    fn find_toolbox(&self, tool_name: &str) -> Option<SharedToolBox> {
+        self.toolboxes().into_iter().find(|toolbox| {
+            toolbox
+                .tools_definitions()
+                .is_ok_and(|tools| tools.iter().any(|tool| tool.name == tool_name))
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ToolBox for DynamicToolBox {
+    fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+        let mut all_definitions = Vec::new();
+        for toolbox in self.toolboxes() {
+            all_definitions.extend(toolbox.tools_definitions()?);
+        }
+        Ok(all_definitions)
+    }
+
+    async fn call_tool(&self, tool_name: String, arguments: Value) -> ToolResult {
+        self.call_tool_output(tool_name, arguments)
+            .await
+            .map(|output| output.content)
+    }
+
+    async fn call_tool_output(
+        &self,
+        tool_name: String,
+        arguments: Value,
+    ) -> Result<ToolOutput, ToolError> {
+        for toolbox in self.toolboxes() {
+            match toolbox
+                .call_tool_output(tool_name.clone(), arguments.clone())
+                .await
+            {
+                Err(ToolError::NoToolFound(_)) => continue,
+                result => return result,
+            }
+        }
+        Err(ToolError::NoToolFound(tool_name))
+    }
+
+    fn streams_arguments(&self, tool_name: &str) -> bool {
+        self.find_toolbox(tool_name)
+            .is_some_and(|toolbox| toolbox.streams_arguments(tool_name))
+    }
+
+    async fn call_tool_streamed(
+        &self,
+        tool_name: String,
+        arguments: ToolArgumentStream,
+    ) -> Result<ToolOutput, ToolError> {
+        match self.find_toolbox(&tool_name) {
+            Some(toolbox) => toolbox.call_tool_streamed(tool_name, arguments).await,
+            None => Err(ToolError::NoToolFound(tool_name)),
+        }
+    }
+
+    fn has_side_effects(&self, tool_name: &str) -> bool {
+        self.find_toolbox(tool_name)
+            .is_none_or(|toolbox| toolbox.has_side_effects(tool_name))
+    }
+
+    fn is_idempotent(&self, tool_name: &str) -> bool {
+        self.find_toolbox(tool_name)
+            .is_none_or(|toolbox| toolbox.is_idempotent(tool_name))
+    }
+
+    fn error_policy(&self, tool_name: &str) -> ToolErrorPolicy {
+        self.find_toolbox(tool_name)
+            .map(|toolbox| toolbox.error_policy(tool_name))
+            .unwrap_or_default()
+    }
+
+    fn is_terminal(&self, tool_name: &str) -> bool {
+        self.find_toolbox(tool_name)
+            .is_some_and(|toolbox| toolbox.is_terminal(tool_name))
+    }
+
+    /// Counts added toolboxes, together with revisions of the toolboxes.
+    fn revision(&self) -> u64 {
+        self.toolboxes()
+            .iter()
+            .fold(self.revision.load(Ordering::SeqCst), |revision, toolbox| {
+                revision.wrapping_add(toolbox.revision())
+            })
+    }
+
+    async fn initialize(&self) -> Result<(), ToolError> {
+        for toolbox in self.toolboxes() {
+            toolbox.initialize().await?;
+        }
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<(), ToolError> {
+        for toolbox in self.toolboxes() {
+            toolbox.health_check().await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+//! # Feed Tools
+//!
+//! This module provides [`FeedToolBox`], which lets an agent read RSS and Atom feeds, e.g. to
+//! follow news or monitor releases. Feeds are parsed using the `feed-rs` crate and returned as
+//! a structured list of entries, so the model doesn't have to read raw XML.
+//!
+//! ```rust,no_run
+//! # use agentai::Agent;
+//! # use agentai::tool::feed::FeedToolBox;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let toolbox = FeedToolBox::new().with_max_entries(10);
+//! let mut agent = Agent::new("You are a news assistant");
+//! let answer: String = agent
+//!     .run(
+//!         "gpt-4o",
+//!         "What's new at https://blog.rust-lang.org/feed.xml?",
+//!         Some(&toolbox),
+//!     )
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::tool::{
+    default_http_timeout, http_client, toolbox, Tool, ToolBox, ToolError, ToolResult,
+};
+use reqwest::Client;
+use serde_json::{json, Value};
+
+const DEFAULT_MAX_ENTRIES: usize = 20;
+
+/// Maximum number of characters of an entry summary
+const MAX_SUMMARY_CHARS: usize = 500;
+
+/// # RSS and Atom Feed Tool
+///
+/// Toolbox exposing a `read_feed` tool, which fetches a feed and returns its entries as JSON,
+/// with title, link, summary and date of every entry. Entries are returned in the order of the
+/// feed, usually the newest first, limited to 20 by default.
+pub struct FeedToolBox {
+    client: Client,
+    max_entries: usize,
+}
+
+impl Default for FeedToolBox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[toolbox]
+impl FeedToolBox {
+    /// Creates a new instance of `FeedToolBox`.
+    pub fn new() -> Self {
+        Self {
+            client: http_client(default_http_timeout()),
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to share connection pool or to configure proxy.
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sets maximum number of entries returned from a feed, 20 by default.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    #[allow(rustdoc::bare_urls)]
+    /// Reads an RSS or Atom feed and returns its entries with title, link, summary and date.
+    /// Use it to check latest news, blog posts or releases published by a website.
+    ///
+    /// ## Example
+    ///
+    /// **User:** "What's new at https://blog.rust-lang.org/feed.xml?"
+    #[tool(readonly)]
+    pub async fn read_feed(
+        &self,
+        #[doc = "The full URL of the RSS or Atom feed, including the protocol (e.g., https://)."]
+        url: String,
+    ) -> ToolResult {
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| ToolError::LLMError(format!("Request to {url} failed: {e}")))?;
+        if !response.status().is_success() {
+            return Err(ToolError::LLMError(format!(
+                "Request to {} failed with status: {}",
+                url,
+                response.status()
+            )));
+        }
+        let body = response.bytes().await.map_err(anyhow::Error::new)?;
+
+        let feed = parse_feed(&body, self.max_entries).map_err(|err| {
+            ToolError::LLMError(format!(
+                "Content of {url} is not an RSS or Atom feed: {err}"
+            ))
+        })?;
+        Ok(feed.to_string())
+    }
+}
+
+/// Parses feed into JSON with the feed title and at most `max_entries` entries
+fn parse_feed(body: &[u8], max_entries: usize) -> Result<Value, feed_rs::parser::ParseFeedError> {
+    let feed = feed_rs::parser::parse(body)?;
+    let entries: Vec<Value> = feed
+        .entries
+        .iter()
+        .take(max_entries)
+        .map(|entry| {
+            let summary = entry
+                .summary
+                .as_ref()
+                .map(|summary| summary.content.trim())
+                .or_else(|| {
+                    entry
+                        .content
+                        .as_ref()
+                        .and_then(|content| content.body.as_deref())
+                })
+                .map(|summary| truncate(summary, MAX_SUMMARY_CHARS));
+            json!({
+                "title": entry.title.as_ref().map(|title| title.content.trim()),
+                "link": entry.links.first().map(|link| &link.href),
+                "summary": summary,
+                "date": entry.published.or(entry.updated).map(|date| date.to_rfc3339()),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "title": feed.title.as_ref().map(|title| title.content.trim()),
+        "entries": entries,
+    }))
+}
+
+/// Cuts text to `max_chars` characters, marking that it was truncated
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rss() {
+        let rss = r#"<?xml version="1.0"?>
+            <rss version="2.0"><channel>
+                <title>Rust News</title>
+                <item>
+                    <title>Rust 1.90 released</title>
+                    <link>https://example.com/rust-1-90</link>
+                    <description>New stable version</description>
+                    <pubDate>Thu, 18 Sep 2025 10:00:00 GMT</pubDate>
+                </item>
+                <item><title>Older post</title></item>
+            </channel></rss>"#;
+        assert_eq!(
+            parse_feed(rss.as_bytes(), 1).unwrap(),
+            json!({
+                "title": "Rust News",
+                "entries": [{
+                    "title": "Rust 1.90 released",
+                    "link": "https://example.com/rust-1-90",
+                    "summary": "New stable version",
+                    "date": "2025-09-18T10:00:00+00:00",
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_atom() {
+        let atom = r#"<?xml version="1.0" encoding="utf-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <title>Releases</title>
+                <id>urn:releases</id>
+                <updated>2025-09-01T12:00:00Z</updated>
+                <entry>
+                    <title>v2.0</title>
+                    <id>urn:releases:2</id>
+                    <link href="https://example.com/v2"/>
+                    <updated>2025-09-01T12:00:00Z</updated>
+                    <content type="text">Big release</content>
+                </entry>
+            </feed>"#;
+        let feed = parse_feed(atom.as_bytes(), 20).unwrap();
+        assert_eq!(feed["entries"][0]["link"], "https://example.com/v2");
+        assert_eq!(feed["entries"][0]["summary"], "Big release");
+        assert_eq!(feed["entries"][0]["date"], "2025-09-01T12:00:00+00:00");
+
+        assert!(parse_feed(b"<html></html>", 20).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::tool::{
+    Tool, ToolArgumentStream, ToolBox, ToolError, ToolErrorPolicy, ToolOutput, ToolResult,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+type Filter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Toolbox exposing only selected tools of another toolbox, optionally under different names.
+///
+/// Large toolboxes, e.g. MCP Servers, provide dozens of tools, which overwhelm the model and
+/// make it pick irrelevant ones. `FilteredToolBox` hides tools which are not needed, they are
+/// not included in tool definitions and calling them returns [`ToolError::NoToolFound`].
+/// Filters and renames refer to the original tool names.
+///
+/// ```rust
+/// use agentai::tool::buildin::ScratchpadToolBox;
+/// use agentai::tool::{FilteredToolBox, ToolBox};
+///
+/// let toolbox = FilteredToolBox::new(ScratchpadToolBox::new())
+///     .allow(&["note_read"])
+///     .rename("note_read", "read_note");
+/// let tools = toolbox.tools_definitions().unwrap();
+/// assert_eq!(tools.len(), 1);
+/// assert_eq!(tools[0].name, "read_note");
+/// ```
+pub struct FilteredToolBox<T> {
+    inner: T,
+    filters: Vec<Filter>,
+    /// Exposed names of renamed tools, by original names
+    renames: HashMap<String, String>,
+}
+
+impl<T: ToolBox> FilteredToolBox<T> {
+    /// Creates a toolbox exposing all tools of `inner`.
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            filters: vec![],
+            renames: HashMap::new(),
+        }
+    }
+
+    /// Exposes only tools with given names.
+    pub fn allow(self, tool_names: &[&str]) -> Self {
+        let allowed: Vec<String> = tool_names.iter().map(|name| name.to_string()).collect();
+        self.with_filter(move |tool_name| allowed.iter().any(|name| name == tool_name))
+    }
+
+    /// Hides tools with given names.
+    pub fn deny(self, tool_names: &[&str]) -> Self {
+        let denied: Vec<String> = tool_names.iter().map(|name| name.to_string()).collect();
+        self.with_filter(move |tool_name| !denied.iter().any(|name| name == tool_name))
+    }
+
+    /// Exposes only tools for which `filter` returns `true`, e.g. tools with a common prefix.
+    ///
+    /// Filters are combined, a tool is exposed if it passes all of them.
+    pub fn with_filter(mut self, filter: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Exposes tool `tool_name` as `new_name`, the original name can't be called anymore.
+    pub fn rename(mut self, tool_name: &str, new_name: &str) -> Self {
+        self.renames
+            .insert(tool_name.to_string(), new_name.to_string());
+        self
+    }
+
+    /// Returns the wrapped toolbox
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn is_allowed(&self, tool_name: &str) -> bool {
+        self.filters.iter().all(|filter| filter(tool_name))
+    }
+
+    /// Returns exposed name of the original tool
+    fn exposed_name(&self, tool_name: String) -> String {
+        self.renames.get(&tool_name).cloned().unwrap_or(tool_name)
+    }
+
+    /// Returns original name of the exposed tool, or `None` if it is hidden
+    fn original_name<'a>(&'a self, tool_name: &'a str) -> Option<&'a str> {
+        let original = match self.renames.iter().find(|(_, name)| *name == tool_name) {
+            Some((original, _)) => original.as_str(),
+            None if self.renames.contains_key(tool_name) => return None,
+            None => tool_name,
+        };
+        self.is_allowed(original).then_some(original)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ToolBox> ToolBox for FilteredToolBox<T> {
+    fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+        Ok(self
+            .inner
+            .tools_definitions()?
+            .into_iter()
+            .filter(|tool| self.is_allowed(&tool.name))
+            .map(|mut tool| {
+                tool.name = self.exposed_name(tool.name);
+                tool
+            })
+            .collect())
+    }
+
+    async fn call_tool(&self, tool_name: String, arguments: Value) -> ToolResult {
+        self.call_tool_output(tool_name, arguments)
+            .await
+            .map(|output| output.content)
+    }
+
+    async fn call_tool_output(
+        &self,
+        tool_name: String,
+        arguments: Value,
+    ) -> Result<ToolOutput, ToolError> {
+        match self.original_name(&tool_name) {
+            Some(original) => {
+                self.inner
+                    .call_tool_output(original.to_string(), arguments)
+                    .await
+            }
+            None => Err(ToolError::NoToolFound(tool_name)),
+        }
+    }
+
+    fn streams_arguments(&self, tool_name: &str) -> bool {
+        self.original_name(tool_name)
+            .is_some_and(|original| self.inner.streams_arguments(original))
+    }
+
+    async fn call_tool_streamed(
+        &self,
+        tool_name: String,
+        arguments: ToolArgumentStream,
+    ) -> Result<ToolOutput, ToolError> {
+        match self.original_name(&tool_name) {
+            Some(original) => {
+                self.inner
+                    .call_tool_streamed(original.to_string(), arguments)
+                    .await
+            }
+            None => Err(ToolError::NoToolFound(tool_name)),
+        }
+    }
+
+    fn has_side_effects(&self, tool_name: &str) -> bool {
+        self.original_name(tool_name)
+            .is_none_or(|original| self.inner.has_side_effects(original))
+    }
+
+    fn is_idempotent(&self, tool_name: &str) -> bool {
+        self.original_name(tool_name)
+            .is_none_or(|original| self.inner.is_idempotent(original))
+    }
+
+    fn error_policy(&self, tool_name: &str) -> ToolErrorPolicy {
+        self.original_name(tool_name)
+            .map(|original| self.inner.error_policy(original))
+            .unwrap_or_default()
+    }
+
+    fn is_terminal(&self, tool_name: &str) -> bool {
+        self.original_name(tool_name)
+            .is_some_and(|original| self.inner.is_terminal(original))
+    }
+
+    fn revision(&self) -> u64 {
+        self.inner.revision()
+    }
+
+    async fn initialize(&self) -> Result<(), ToolError> {
+        self.inner.initialize().await
+    }
+
+    async fn health_check(&self) -> Result<(), ToolError> {
+        self.inner.health_check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TimeToolBox;
+
+    #[async_trait::async_trait]
+    impl ToolBox for TimeToolBox {
+        fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+            Ok(vec![
+                Tool::new("stdio_0_get_current_time"),
+                Tool::new("stdio_0_convert_time"),
+                Tool::new("stdio_0_set_alarm"),
+            ])
+        }
+
+        async fn call_tool(&self, tool_name: String, _arguments: Value) -> ToolResult {
+            Ok(format!("called {tool_name}"))
+        }
+
+        fn has_side_effects(&self, tool_name: &str) -> bool {
+            tool_name == "stdio_0_set_alarm"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filtered_toolbox() {
+        let toolbox = FilteredToolBox::new(TimeToolBox)
+            .deny(&["stdio_0_set_alarm"])
+            .rename("stdio_0_convert_time", "convert_time");
+        let names: Vec<String> = toolbox
+            .tools_definitions()
+            .unwrap()
+            .into_iter()
+            .map(|tool| tool.name)
+            .collect();
+        assert_eq!(names, ["stdio_0_get_current_time", "convert_time"]);
+
+        let call = |name: &str| toolbox.call_tool(name.to_string(), Value::Null);
+        assert_eq!(
+            call("convert_time").await.unwrap(),
+            "called stdio_0_convert_time"
+        );
+        assert_eq!(
+            call("stdio_0_get_current_time").await.unwrap(),
+            "called stdio_0_get_current_time"
+        );
+        // Hidden tools and original names of renamed tools can't be called
+        for name in ["stdio_0_set_alarm", "stdio_0_convert_time"] {
+            assert!(matches!(
+                call(name).await,
+                Err(ToolError::NoToolFound(tool_name)) if tool_name == name
+            ));
+        }
+        assert!(!toolbox.has_side_effects("convert_time"));
+    }
+
+    #[tokio::test]
+    async fn test_filtered_toolbox_allow_and_filter() {
+        let toolbox = FilteredToolBox::new(TimeToolBox)
+            .with_filter(|name| name.starts_with("stdio_0_"))
+            .allow(&["stdio_0_set_alarm", "other"]);
+        let tools = toolbox.tools_definitions().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "stdio_0_set_alarm");
+        assert!(toolbox.has_side_effects("stdio_0_set_alarm"));
+        assert!(matches!(
+            toolbox
+                .call_tool("stdio_0_convert_time".to_string(), Value::Null)
+                .await,
+            Err(ToolError::NoToolFound(_))
+        ));
+    }
+}
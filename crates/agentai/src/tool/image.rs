@@ -0,0 +1,134 @@
+//! # Image Generation Tools
+//!
+//! This module provides [`ImageGenToolBox`], a tool allowing an agent to create images
+//! using OpenAI-compatible image generation API, e.g. DALL·E.
+//!
+//! GenAI doesn't support image generation, but its [`Client`](genai::Client) is still used
+//! to resolve endpoint and API key of the model. This way the toolbox respects authentication
+//! and endpoint resolvers configured on the client. Models not recognized by GenAI, like
+//! `dall-e-3`, need a [`ServiceTargetResolver`](genai::resolver::ServiceTargetResolver) pointing
+//! to OpenAI, while `gpt-image-1` is resolved to OpenAI by default.
+
+use crate::tool::{
+    default_http_timeout, http_client, toolbox, Tool, ToolBox, ToolError, ToolResult,
+};
+use anyhow::Context;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Minimum timeout of image generation requests, generating an image often takes longer than
+/// other requests made by tools
+const MIN_GENERATION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Provides a tool that enables an LLM to generate images from a text prompt.
+///
+/// The tool returns an image URL, or a `data:` URL with base64 encoded image when the model
+/// doesn't return URLs. Agent can reference it in its answer.
+///
+/// ```rust
+/// # use agentai::tool::image::ImageGenToolBox;
+/// let toolbox = ImageGenToolBox::new(genai::Client::default(), "gpt-image-1");
+/// ```
+pub struct ImageGenToolBox {
+    genai: genai::Client,
+    model: String,
+    client: Client,
+}
+
+#[toolbox]
+impl ImageGenToolBox {
+    /// Creates a new instance of `ImageGenToolBox`.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - GenAI client used to resolve endpoint and API key of the model.
+    /// * `model` - The image generation model, e.g. `gpt-image-1`.
+    pub fn new(client: genai::Client, model: &str) -> Self {
+        Self {
+            genai: client,
+            model: model.to_string(),
+            client: http_client(default_http_timeout().max(MIN_GENERATION_TIMEOUT)),
+        }
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to share connection pool or to configure proxy.
+    ///
+    /// Default client waits at least 2 minutes for an image, longer than
+    /// [default timeout](crate::tool::set_default_http_timeout) of other tools.
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Generates an image based on a description and returns its URL. Use it when the user asks
+    /// to draw, create or visualize something. Reference returned URL in your answer.
+    ///
+    /// ## Example
+    ///
+    /// **User:** "Draw me a cat wearing a hat"
+    #[tool]
+    pub async fn generate_image(
+        &self,
+        #[doc = "Detailed description of the image to generate"] prompt: String,
+    ) -> ToolResult {
+        let target = self
+            .genai
+            .resolve_service_target(&self.model)
+            .await
+            .map_err(anyhow::Error::new)?;
+        let url = format!("{}images/generations", target.endpoint.base_url());
+        let body = json!({
+            "model": &*target.model.model_name,
+            "prompt": prompt,
+            "n": 1,
+        });
+
+        let mut request = self.client.post(url).json(&body);
+        // Local servers may not require any key
+        if let Ok(api_key) = target.auth.single_key_value() {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(anyhow::Error::new)?;
+        let json: Value = response.json().await.map_err(anyhow::Error::new)?;
+
+        Ok(image_from_response(&json).context("image generation response has no image")?)
+    }
+}
+
+/// Extracts the first image from image generation response, as URL or base64 `data:` URL
+fn image_from_response(response: &Value) -> Option<String> {
+    let image = &response["data"][0];
+    if let Some(url) = image["url"].as_str() {
+        return Some(url.to_string());
+    }
+    image["b64_json"]
+        .as_str()
+        .map(|data| format!("data:image/png;base64,{data}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_from_response() {
+        let response = json!({"data": [{"url": "https://example.com/cat.png"}]});
+        assert_eq!(
+            image_from_response(&response).as_deref(),
+            Some("https://example.com/cat.png")
+        );
+
+        let response = json!({"data": [{"b64_json": "aGVsbG8="}]});
+        assert_eq!(
+            image_from_response(&response).as_deref(),
+            Some("data:image/png;base64,aGVsbG8=")
+        );
+
+        assert_eq!(image_from_response(&json!({"data": []})), None);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::tool::{
+    Tool, ToolArgumentStream, ToolBox, ToolError, ToolErrorPolicy, ToolOutput, ToolResult,
+};
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::future::Future;
+use tokio::sync::OnceCell;
+
+type Init<T> = Box<dyn Fn() -> BoxFuture<'static, anyhow::Result<T>> + Send + Sync>;
+
+/// Toolbox created asynchronously when it is used for the first time.
+///
+/// Some toolboxes need async setup before they can report their tools, e.g. connect to a
+/// database or to an MCP Server. `LazyToolBox` defers this setup until the first agent run,
+/// see [`ToolBox::initialize`]. If initialization fails, it is retried on the next use.
+///
+/// ```rust,no_run
+/// use agentai::tool::mcp::McpToolBox;
+/// use agentai::tool::LazyToolBox;
+///
+/// let toolbox = LazyToolBox::new(|| async {
+///     McpToolBox::new("uvx", ["mcp-server-time"], None).await
+/// });
+/// ```
+pub struct LazyToolBox<T> {
+    init: Init<T>,
+    toolbox: OnceCell<T>,
+}
+
+impl<T: ToolBox + 'static> LazyToolBox<T> {
+    /// Creates a toolbox initialized using `init` function.
+    pub fn new<F, Fut>(init: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<T>> + Send + 'static,
+    {
+        Self {
+            init: Box::new(move || Box::pin(init())),
+            toolbox: OnceCell::new(),
+        }
+    }
+
+    /// Returns the toolbox if it is already initialized
+    pub fn get(&self) -> Option<&T> {
+        self.toolbox.get()
+    }
+
+    async fn get_or_init(&self) -> Result<&T, ToolError> {
+        Ok(self.toolbox.get_or_try_init(|| (self.init)()).await?)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: ToolBox + 'static> ToolBox for LazyToolBox<T> {
+    /// Returns definitions of the initialized toolbox, or
+    /// [`ToolError::ToolsDefinitionNotReady`] before initialization.
+    fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+        self.get()
+            .ok_or(ToolError::ToolsDefinitionNotReady)?
+            .tools_definitions()
+    }
+
+    async fn call_tool(&self, tool_name: String, arguments: Value) -> ToolResult {
+        self.get_or_init()
+            .await?
+            .call_tool(tool_name, arguments)
+            .await
+    }
+
+    async fn call_tool_output(
+        &self,
+        tool_name: String,
+        arguments: Value,
+    ) -> Result<ToolOutput, ToolError> {
+        self.get_or_init()
+            .await?
+            .call_tool_output(tool_name, arguments)
+            .await
+    }
+
+    fn streams_arguments(&self, tool_name: &str) -> bool {
+        self.get()
+            .is_some_and(|toolbox| toolbox.streams_arguments(tool_name))
+    }
+
+    async fn call_tool_streamed(
+        &self,
+        tool_name: String,
+        arguments: ToolArgumentStream,
+    ) -> Result<ToolOutput, ToolError> {
+        self.get_or_init()
+            .await?
+            .call_tool_streamed(tool_name, arguments)
+            .await
+    }
+
+    fn has_side_effects(&self, tool_name: &str) -> bool {
+        self.get()
+            .is_none_or(|toolbox| toolbox.has_side_effects(tool_name))
+    }
+
+    fn is_idempotent(&self, tool_name: &str) -> bool {
+        self.get()
+            .is_none_or(|toolbox| toolbox.is_idempotent(tool_name))
+    }
+
+    fn error_policy(&self, tool_name: &str) -> ToolErrorPolicy {
+        self.get()
+            .map(|toolbox| toolbox.error_policy(tool_name))
+            .unwrap_or_default()
+    }
+
+    fn is_terminal(&self, tool_name: &str) -> bool {
+        self.get()
+            .is_some_and(|toolbox| toolbox.is_terminal(tool_name))
+    }
+
+    fn revision(&self) -> u64 {
+        self.get().map_or(0, |toolbox| toolbox.revision())
+    }
+
+    async fn initialize(&self) -> Result<(), ToolError> {
+        self.get_or_init().await?.initialize().await
+    }
+
+    /// Initializes the toolbox if needed, then checks it.
+    async fn health_check(&self) -> Result<(), ToolError> {
+        self.get_or_init().await?.health_check().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct StaticToolBox;
+
+    #[async_trait::async_trait]
+    impl ToolBox for StaticToolBox {
+        fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+            Ok(vec![Tool::new("ping")])
+        }
+
+        async fn call_tool(&self, _tool_name: String, _arguments: Value) -> ToolResult {
+            Ok("pong".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lazy_toolbox_initializes_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let toolbox = LazyToolBox::new(move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    anyhow::bail!("Connection refused");
+                }
+                Ok(StaticToolBox)
+            }
+        });
+
+        assert!(matches!(
+            toolbox.tools_definitions(),
+            Err(ToolError::ToolsDefinitionNotReady)
+        ));
+        // Failed initialization is retried
+        assert!(toolbox.initialize().await.is_err());
+        toolbox.initialize().await.unwrap();
+        toolbox.initialize().await.unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(toolbox.tools_definitions().unwrap()[0].name, "ping");
+        assert_eq!(
+            toolbox
+                .call_tool("ping".to_string(), Value::Null)
+                .await
+                .unwrap(),
+            "pong"
+        );
+    }
+}
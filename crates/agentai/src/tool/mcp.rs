@@ -3,28 +3,98 @@
 //! This module external tools that can connect with MCP Servers.
 //!
 //! Supported connection types:
-//! - `stdio`
+//! - `stdio`, see [`McpToolBox::new`]
+//! - Streamable HTTP, see [`StreamableHttpClientTransport`]
 //!
+//! Tool calls which don't finish within a timeout fail, so a hung server can't block the agent,
+//! see [`McpToolBox::with_timeout`] and [`set_default_call_timeout`].
 //!
+//! Servers can also be loaded from a JSON config file in the `mcpServers` format used by
+//! other MCP clients, see [`McpToolBox::from_config_file`].
 
-use crate::tool::{Tool, ToolBox, ToolError};
-use anyhow::Result as AnyhowResult;
+mod http;
+
+pub use http::StreamableHttpClientTransport;
+
+use crate::tool::{Tool, ToolBox, ToolBoxSet, ToolError};
+use anyhow::{anyhow, Context, Result as AnyhowResult};
 use async_trait::async_trait;
 use log::trace;
 use mcp_client_rs::{
     client::{Client, ClientBuilder},
-    MessageContent,
+    transport::Transport,
+    ClientCapabilities, Implementation, MessageContent,
 };
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+
+/// Default timeout of MCP tool calls
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+static CALL_TIMEOUT_MILLIS: AtomicU64 = AtomicU64::new(DEFAULT_CALL_TIMEOUT.as_millis() as u64);
+
+/// Sets timeout of tool calls made by MCP toolboxes, [`DEFAULT_CALL_TIMEOUT`] by default.
+///
+/// It applies to toolboxes created after the call. A single toolbox can use a different
+/// timeout, see [`McpToolBox::with_timeout`].
+///
+/// ```rust
+/// # use std::time::Duration;
+/// agentai::tool::mcp::set_default_call_timeout(Duration::from_secs(300));
+/// ```
+pub fn set_default_call_timeout(timeout: Duration) {
+    CALL_TIMEOUT_MILLIS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Returns timeout of MCP tool calls, see [`set_default_call_timeout`].
+pub fn default_call_timeout() -> Duration {
+    Duration::from_millis(CALL_TIMEOUT_MILLIS.load(Ordering::Relaxed))
+}
 
+/// Toolbox exposing tools provided by an MCP Server.
+///
+/// Cloning `McpToolBox` is cheap, all clones share the same connection to the MCP Server.
+/// To reuse an already established connection in a new toolbox use [`McpToolBox::from_client`].
+#[derive(Clone)]
 pub struct McpToolBox {
     client: Arc<Client>,
+    /// Tool definitions with names visible to the agent
     tools: Vec<Tool>,
+    /// Original names of tools reported by the server, by names visible to the agent
+    original_names: HashMap<String, String>,
+    text_only: bool,
+    /// Original names of tools hidden from the agent
+    disabled_tools: HashSet<String>,
+    /// Maximum time of a single tool call
+    timeout: Duration,
+    /// Name of the server used in error messages
+    server_name: Option<String>,
 }
 
 impl McpToolBox {
+    /// Starts an MCP Server as a local process communicating over `stdio`, and connects to it.
+    ///
+    /// `envs` are set for the server process in addition to the environment inherited from
+    /// the current process, overriding inherited variables with the same names. They are used
+    /// to pass secrets and configuration, e.g. API keys, without exposing them in arguments.
+    ///
+    /// ```rust,no_run
+    /// # use agentai::tool::mcp::McpToolBox;
+    /// # use std::collections::HashMap;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let envs = HashMap::from([("GITHUB_TOKEN".to_string(), std::env::var("GITHUB_TOKEN")?)]);
+    /// let toolbox = McpToolBox::new("npx", ["-y", "@modelcontextprotocol/server-github"], Some(envs))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
     pub async fn new(
         cmd: &str,
         args: impl IntoIterator<Item = impl AsRef<str>>,
@@ -42,6 +112,96 @@ impl McpToolBox {
         let client = builder.spawn_and_initialize().await?;
         trace!("McpToolBox::new for client initialized");
 
+        Self::from_client(Arc::new(client)).await
+    }
+
+    /// Connects to all MCP Servers defined in a JSON config file, using the `mcpServers` format
+    /// adopted by other MCP clients, e.g. Claude Desktop.
+    ///
+    /// Servers started as a local process are defined with `command` and optional `args` and
+    /// `env`. Remote servers are defined with `url`, and connected using
+    /// [`StreamableHttpClientTransport`], with optional `headers`, e.g. for authentication.
+    /// Optional `timeout` sets the
+    /// [timeout of tool calls](McpToolBox::with_timeout) of the server in seconds. Servers are
+    /// connected in order of their names, and returned as a [`ToolBoxSet`]. Loading fails if
+    /// any of the servers can't be connected.
+    ///
+    /// ```json
+    /// {
+    ///   "mcpServers": {
+    ///     "time": {
+    ///       "command": "uvx",
+    ///       "args": ["mcp-server-time", "--local-timezone", "UTC"]
+    ///     },
+    ///     "docs": {
+    ///       "url": "https://mcp.example.com/mcp",
+    ///       "headers": {"Authorization": "Bearer secret-token"},
+    ///       "timeout": 10
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    pub async fn from_config_file(path: impl AsRef<Path>) -> AnyhowResult<ToolBoxSet> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read MCP config {}", path.display()))?;
+        let servers = parse_config(&config)
+            .with_context(|| format!("Invalid MCP config {}", path.display()))?;
+
+        let mut toolbox = ToolBoxSet::new();
+        for (name, server) in servers {
+            trace!("McpToolBox::from_config_file connecting to server: {name}");
+            let timeout = server.timeout.map(Duration::from_secs);
+            let server_toolbox = match server {
+                McpServerConfig {
+                    command: Some(command),
+                    ..
+                } => Self::new(&command, server.args, Some(server.env)).await,
+                McpServerConfig { url: Some(url), .. } => {
+                    match HeaderMap::try_from(&server.headers) {
+                        Ok(headers) => {
+                            let transport =
+                                StreamableHttpClientTransport::new(&url).with_headers(headers);
+                            Self::from_transport(transport).await
+                        }
+                        Err(err) => Err(anyhow!("Invalid headers: {err}")),
+                    }
+                }
+                _ => Err(anyhow!("Either `command` or `url` is required")),
+            };
+            let mut server_toolbox = server_toolbox
+                .with_context(|| format!("Failed to connect to MCP server {name}"))?
+                .with_server_name(&name);
+            if let Some(timeout) = timeout {
+                server_toolbox = server_toolbox.with_timeout(timeout);
+            }
+            toolbox.add_tool(server_toolbox);
+        }
+        Ok(toolbox)
+    }
+
+    /// Connects to an MCP Server using provided transport, e.g. [`StreamableHttpClientTransport`].
+    pub async fn from_transport(transport: impl Transport) -> AnyhowResult<Self> {
+        let client = Client::new(Arc::new(transport));
+        client
+            .initialize(
+                Implementation {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                ClientCapabilities::default(),
+            )
+            .await?;
+        trace!("McpToolBox::from_transport client initialized");
+
+        Self::from_client(Arc::new(client)).await
+    }
+
+    /// Creates a toolbox using an already initialized MCP client.
+    ///
+    /// This allows multiple toolboxes, possibly used by different agents, to share a single
+    /// connection to the same MCP Server instead of spawning a new server for each of them.
+    pub async fn from_client(client: Arc<Client>) -> AnyhowResult<Self> {
         let mut tools = vec![];
 
         for tool_desc in client.list_tools().await?.tools {
@@ -52,11 +212,90 @@ impl McpToolBox {
             });
         }
 
+        let original_names = tools
+            .iter()
+            .map(|tool| (tool.name.clone(), tool.name.clone()))
+            .collect();
         Ok(Self {
-            client: Arc::new(client),
+            client,
             tools,
+            original_names,
+            text_only: true,
+            disabled_tools: HashSet::new(),
+            timeout: default_call_timeout(),
+            server_name: None,
         })
     }
+
+    /// Controls how tool results are passed to the agent.
+    ///
+    /// When enabled (default), results containing only text parts are passed as concatenated
+    /// text. Results with other content types (images, resources), or all results when disabled,
+    /// are passed as JSON serialized MCP content.
+    pub fn with_text_only(mut self, text_only: bool) -> Self {
+        self.text_only = text_only;
+        self
+    }
+
+    /// Sets maximum time of a single tool call, [`default_call_timeout`] by default.
+    ///
+    /// Calls exceeding it fail with an error naming the tool and the server, which is passed to
+    /// the model like other tool errors. Timeouts are set per toolbox, so a slow local server
+    /// can get more time than a remote one.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets name of the server used in error messages, by default the
+    /// [label](McpToolBox::with_label) is used.
+    pub fn with_server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    /// Prefixes names of tools visible to the agent with `label`, e.g. with label `time` the
+    /// `get_current_time` tool is presented as `time_get_current_time`.
+    ///
+    /// A label describing the server helps the model select the right tool, and avoids name
+    /// conflicts when tools of multiple servers are used together. Calls are dispatched using
+    /// original names reported by the server.
+    pub fn with_label(mut self, label: &str) -> Self {
+        let mut original_names = HashMap::new();
+        for tool in &mut self.tools {
+            let original = self.original_names.remove(&tool.name).unwrap_or_default();
+            tool.name = format!("{label}_{original}");
+            original_names.insert(tool.name.clone(), original);
+        }
+        self.original_names = original_names;
+        self.server_name.get_or_insert_with(|| label.to_string());
+        self
+    }
+
+    /// Hides tools provided by the MCP Server from the agent, e.g. to suppress dangerous tools
+    /// of a third-party server while keeping the rest.
+    ///
+    /// Tools are matched by their original names reported by the server, also when the toolbox
+    /// has a [label](McpToolBox::with_label). Disabled tools are not included in tool
+    /// definitions, and calling them fails with [`ToolError::NoToolFound`]. Names not provided
+    /// by the server are ignored.
+    pub fn disable_tools(mut self, names: &[&str]) -> Self {
+        self.disabled_tools
+            .extend(names.iter().map(|name| name.to_string()));
+        let disabled_tools = &self.disabled_tools;
+        self.original_names
+            .retain(|_, original| !disabled_tools.contains(original));
+        let original_names = &self.original_names;
+        self.tools
+            .retain(|tool| original_names.contains_key(&tool.name));
+        self
+    }
+
+    /// Returns the MCP client used by this toolbox, it can be used to create other toolboxes
+    /// sharing the same connection.
+    pub fn client(&self) -> Arc<Client> {
+        self.client.clone()
+    }
 }
 
 #[async_trait]
@@ -66,24 +305,90 @@ impl ToolBox for McpToolBox {
     }
 
     async fn call_tool(&self, tool_name: String, arguments: Value) -> Result<String, ToolError> {
-        let call_result = self
-            .client
-            .call_tool(&tool_name, arguments)
+        let Some(original_name) = self.original_names.get(&tool_name) else {
+            return Err(ToolError::NoToolFound(tool_name));
+        };
+        let arguments = object_arguments(&tool_name, arguments)?;
+        let call = self.client.call_tool(original_name, arguments);
+        let call_result = tokio::time::timeout(self.timeout, call)
             .await
+            .map_err(|_| {
+                let server = match &self.server_name {
+                    Some(name) => format!("MCP server '{name}'"),
+                    None => "MCP server".to_string(),
+                };
+                anyhow!(
+                    "Tool '{tool_name}' of {server} timed out after {:?}",
+                    self.timeout
+                )
+            })?
             .map_err(anyhow::Error::new)?;
 
-        // TODO: Right now we supports only text response from tool
-        let msg = call_result
-            .content
-            .iter()
-            .filter_map(|msg| match msg {
-                MessageContent::Text { text } => Some(text.clone()),
-                _ => None,
-            })
-            .collect::<Vec<_>>()
-            .join("\n");
+        format_content(&call_result.content, self.text_only)
+    }
 
-        Ok(msg)
+    /// Sends `ping` request to the MCP Server.
+    async fn health_check(&self) -> Result<(), ToolError> {
+        self.client
+            .request("ping", None)
+            .await
+            .map_err(anyhow::Error::new)?;
+        Ok(())
+    }
+}
+
+/// Checks that tool arguments are a JSON object, as required by MCP.
+///
+/// Some models send `null` for tools without parameters, it is replaced with an empty object.
+/// Other values are rejected with an error passed to the model, so it can call the tool again.
+fn object_arguments(tool_name: &str, arguments: Value) -> Result<Value, ToolError> {
+    match arguments {
+        Value::Object(_) => Ok(arguments),
+        Value::Null => Ok(Value::Object(Default::default())),
+        arguments => Err(ToolError::LLMError(format!(
+            "Arguments of tool '{tool_name}' must be a JSON object, got: {arguments}"
+        ))),
+    }
+}
+
+/// Server entry of the `mcpServers` config, see [`McpToolBox::from_config_file`]
+#[derive(Debug, Deserialize)]
+struct McpServerConfig {
+    command: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    url: Option<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// Timeout of tool calls in seconds
+    timeout: Option<u64>,
+}
+
+/// Parses servers of the `mcpServers` config, by their names
+fn parse_config(config: &str) -> AnyhowResult<BTreeMap<String, McpServerConfig>> {
+    #[derive(Deserialize)]
+    struct McpConfig {
+        #[serde(rename = "mcpServers")]
+        servers: BTreeMap<String, McpServerConfig>,
+    }
+
+    Ok(serde_json::from_str::<McpConfig>(config)?.servers)
+}
+
+/// Converts MCP tool result content into text passed to the agent
+fn format_content(content: &[MessageContent], text_only: bool) -> Result<String, ToolError> {
+    let texts = content
+        .iter()
+        .map(|msg| match msg {
+            MessageContent::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    match texts {
+        Some(texts) if text_only => Ok(texts.join("\n")),
+        _ => serde_json::to_string(content).map_err(|err| ToolError::Other(err.into())),
     }
 }
 
@@ -98,6 +403,88 @@ mod tests {
         McpToolBox::new("uvx", ["mcp-server-time", "--local-timezone", "UTC"], None).await
     }
 
+    #[test]
+    fn test_parse_config() {
+        let servers = parse_config(
+            r#"{"mcpServers": {
+                "time": {"command": "uvx", "args": ["mcp-server-time"], "env": {"TZ": "UTC"}},
+                "docs": {
+                    "url": "https://mcp.example.com/mcp",
+                    "headers": {"Authorization": "Bearer token"},
+                    "timeout": 10
+                }
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(servers.keys().collect::<Vec<_>>(), ["docs", "time"]);
+        let time = &servers["time"];
+        assert_eq!(time.command.as_deref(), Some("uvx"));
+        assert_eq!(time.args, ["mcp-server-time"]);
+        assert_eq!(time.env["TZ"], "UTC");
+        assert_eq!(
+            servers["docs"].url.as_deref(),
+            Some("https://mcp.example.com/mcp")
+        );
+        assert_eq!(servers["docs"].timeout, Some(10));
+        assert_eq!(servers["docs"].headers["Authorization"], "Bearer token");
+        assert_eq!(time.timeout, None);
+
+        assert!(parse_config(r#"{"servers": {}}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_from_config_file_invalid_server() {
+        let path = std::env::temp_dir().join("agentai_test_mcp_config.json");
+        std::fs::write(&path, r#"{"mcpServers": {"broken": {"args": []}}}"#).unwrap();
+        let err = McpToolBox::from_config_file(&path).await.err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.to_string(), "Failed to connect to MCP server broken");
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Either `command` or `url` is required"
+        );
+    }
+
+    #[test]
+    fn test_object_arguments() {
+        assert_eq!(object_arguments("echo", json!(null)).unwrap(), json!({}));
+        assert_eq!(
+            object_arguments("echo", json!({"text": "hi"})).unwrap(),
+            json!({"text": "hi"})
+        );
+        let err = object_arguments("echo", json!([])).unwrap_err();
+        assert!(matches!(err, ToolError::LLMError(_)));
+        assert_eq!(
+            err.to_string(),
+            "Arguments of tool 'echo' must be a JSON object, got: []"
+        );
+        assert!(object_arguments("echo", json!("text")).is_err());
+    }
+
+    #[test]
+    fn test_format_content() {
+        let text = |text: &str| MessageContent::Text {
+            text: text.to_string(),
+        };
+        let content = [text("first"), text("second")];
+        assert_eq!(format_content(&content, true).unwrap(), "first\nsecond");
+        assert_eq!(
+            format_content(&content, false).unwrap(),
+            r#"[{"type":"text","text":"first"},{"type":"text","text":"second"}]"#
+        );
+
+        let content = [
+            text("chart"),
+            MessageContent::Image {
+                uri: "file:///chart.png".to_string(),
+                alt_text: None,
+            },
+        ];
+        assert!(format_content(&content, true)
+            .unwrap()
+            .contains("file:///chart.png"));
+    }
+
     #[tokio::test]
     async fn test_new_and_tools_definitions() -> AnyhowResult<()> {
         let mcp_tools = create_test_toolbox().await?;
@@ -150,6 +537,20 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_from_client_shares_connection() -> AnyhowResult<()> {
+        let mcp_tools = create_test_toolbox().await?;
+        let shared = McpToolBox::from_client(mcp_tools.client()).await?;
+
+        assert!(Arc::ptr_eq(&mcp_tools.client, &shared.client));
+        assert_eq!(
+            mcp_tools.tools_definitions()?.len(),
+            shared.tools_definitions()?.len()
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_call_tool_invalid_tool() -> AnyhowResult<()> {
         let mcp_tools = create_test_toolbox().await?;
@@ -0,0 +1,408 @@
+//! Streamable HTTP transport for MCP clients.
+
+use crate::tool::{default_http_timeout, http_client};
+use async_trait::async_trait;
+use futures::Stream;
+use log::trace;
+use mcp_client_rs::transport::{Message, Transport};
+use mcp_client_rs::Error;
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
+use reqwest::{Client, StatusCode};
+use std::pin::Pin;
+use std::sync::Mutex;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+const SESSION_ID_HEADER: &str = "Mcp-Session-Id";
+
+/// MCP transport using the Streamable HTTP protocol.
+///
+/// Every message is sent as a `POST` request to the server URL. Responses are accepted both as
+/// JSON and as a Server-Sent Events stream. The session id assigned by the server is sent with
+/// all following requests.
+///
+/// HTTP requests are made using a [`reqwest::Client`], a configured client can be provided
+/// with [`StreamableHttpClientTransport::with_http_client`], e.g. to use a proxy or custom
+/// certificates. Headers required by the server, e.g. for authentication, are set with
+/// [`StreamableHttpClientTransport::with_headers`].
+///
+/// ```rust,no_run
+/// # use agentai::tool::mcp::{McpToolBox, StreamableHttpClientTransport};
+/// # use reqwest::header::{HeaderMap, AUTHORIZATION};
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let http_client = reqwest::Client::builder()
+///     .proxy(reqwest::Proxy::all("http://proxy.internal:8080")?)
+///     .build()?;
+/// let mut headers = HeaderMap::new();
+/// headers.insert(AUTHORIZATION, "Bearer secret-token".parse()?);
+/// let transport = StreamableHttpClientTransport::new("https://mcp.example.com/mcp")
+///     .with_http_client(http_client)
+///     .with_headers(headers);
+/// let toolbox = McpToolBox::from_transport(transport).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct StreamableHttpClientTransport {
+    url: String,
+    client: Client,
+    headers: HeaderMap,
+    session_id: Mutex<Option<String>>,
+    sender: UnboundedSender<Message>,
+    receiver: Mutex<Option<UnboundedReceiver<Message>>>,
+}
+
+impl StreamableHttpClientTransport {
+    /// Creates a new transport sending messages to the MCP endpoint at `url`.
+    pub fn new(url: &str) -> Self {
+        let (sender, receiver) = unbounded_channel();
+        Self {
+            url: url.to_string(),
+            client: http_client(default_http_timeout()),
+            headers: HeaderMap::new(),
+            session_id: Mutex::new(None),
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to configure proxy, TLS or retries.
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Adds headers sent with every request, e.g. `Authorization` or an API key header.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    fn forward(&self, message: Message) -> Result<(), Error> {
+        trace!("MCP HTTP message received: {message:?}");
+        self.sender
+            .send(message)
+            .map_err(|err| Error::Transport(err.to_string()))
+    }
+}
+
+#[async_trait]
+impl Transport for StreamableHttpClientTransport {
+    async fn send(&self, message: Message) -> Result<(), Error> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .headers(self.headers.clone())
+            .header(ACCEPT, "application/json, text/event-stream")
+            .json(&message);
+        let session_id = self.session_id.lock().unwrap().clone();
+        if let Some(session_id) = session_id {
+            request = request.header(SESSION_ID_HEADER, session_id);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|err| Error::Transport(err.to_string()))?;
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(Error::Transport(format!(
+                "MCP server rejected the request with status {status}, check authentication headers"
+            )));
+        }
+        let response = response
+            .error_for_status()
+            .map_err(|err| Error::Transport(err.to_string()))?;
+        if let Some(session_id) = response.headers().get(SESSION_ID_HEADER) {
+            if let Ok(session_id) = session_id.to_str() {
+                *self.session_id.lock().unwrap() = Some(session_id.to_string());
+            }
+        }
+        if response.status() == StatusCode::ACCEPTED {
+            // Notifications and responses don't get any reply
+            return Ok(());
+        }
+
+        let is_event_stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+        let body = response
+            .text()
+            .await
+            .map_err(|err| Error::Transport(err.to_string()))?;
+
+        if is_event_stream {
+            for data in sse_data(&body) {
+                self.forward(parse_message(&data)?)?;
+            }
+        } else if !body.trim().is_empty() {
+            self.forward(parse_message(&body)?)?;
+        }
+        Ok(())
+    }
+
+    fn receive(&self) -> Pin<Box<dyn Stream<Item = Result<Message, Error>> + Send>> {
+        match self.receiver.lock().unwrap().take() {
+            Some(receiver) => Box::pin(futures::stream::unfold(
+                receiver,
+                |mut receiver| async move {
+                    let message = receiver.recv().await?;
+                    Some((Ok(message), receiver))
+                },
+            )),
+            None => Box::pin(futures::stream::once(async {
+                Err(Error::Transport(
+                    "Transport messages stream was already taken".to_string(),
+                ))
+            })),
+        }
+    }
+
+    async fn close(&self) -> Result<(), Error> {
+        let session_id = self.session_id.lock().unwrap().take();
+        if let Some(session_id) = session_id {
+            // Server may not support explicit session termination, so the result is ignored
+            let _ = self
+                .client
+                .delete(&self.url)
+                .headers(self.headers.clone())
+                .header(SESSION_ID_HEADER, session_id)
+                .send()
+                .await;
+        }
+        Ok(())
+    }
+}
+
+fn parse_message(data: &str) -> Result<Message, Error> {
+    serde_json::from_str(data).map_err(|err| Error::Serialization(err.to_string()))
+}
+
+/// Extracts data of every event from a Server-Sent Events stream
+fn sse_data(body: &str) -> Vec<String> {
+    body.replace("\r\n", "\n")
+        .split("\n\n")
+        .filter_map(|event| {
+            let data = event
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect::<Vec<_>>();
+            (!data.is_empty()).then(|| data.join("\n"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool::mcp::McpToolBox;
+    use crate::tool::{ToolBox, ToolError};
+    use reqwest::header::AUTHORIZATION;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Reads a single HTTP request, returns its headers (lowercase) and body
+    async fn read_request(stream: &mut tokio::net::TcpStream) -> (String, String) {
+        let mut request = vec![];
+        let mut buf = [0; 4096];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some(headers_end) = text.find("\r\n\r\n") {
+                let headers = text[..headers_end].to_lowercase();
+                let content_length = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .and_then(|value| value.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                if n == 0 || request.len() >= headers_end + 4 + content_length {
+                    return (headers, text[headers_end + 4..].to_string());
+                }
+            }
+        }
+    }
+
+    /// Starts MCP server with a single `echo` tool, using both JSON and SSE responses.
+    /// Calls echoing `hang` never get a response.
+    async fn mock_mcp_server() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/mcp", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (headers, body) = read_request(&mut stream).await;
+                let request: Value = serde_json::from_str(&body).unwrap();
+                let has_session = headers.contains("mcp-session-id: session-1");
+                let (status, content_type, body) = match request["method"].as_str() {
+                    _ if headers.contains("authorization: bearer invalid") => {
+                        ("401 Unauthorized", "application/json", "".to_string())
+                    }
+                    _ if request.get("id").is_none() => {
+                        ("202 Accepted", "application/json", "".to_string())
+                    }
+                    Some("initialize") => {
+                        let result = json!({"jsonrpc": "2.0", "id": request["id"], "result": {
+                            "protocolVersion": "2025-03-26",
+                            "serverInfo": {"name": "mock", "version": "1.0"},
+                            "capabilities": {}
+                        }});
+                        ("200 OK", "application/json", result.to_string())
+                    }
+                    _ if !has_session => ("400 Bad Request", "application/json", "".to_string()),
+                    Some("tools/list") => {
+                        let result = json!({"jsonrpc": "2.0", "id": request["id"], "result": {
+                            "tools": [{"name": "echo", "description": "Echo", "inputSchema": {"type": "object"}}]
+                        }});
+                        (
+                            "200 OK",
+                            "text/event-stream",
+                            format!("event: message\ndata: {result}\n\n"),
+                        )
+                    }
+                    _ if request["params"]["arguments"]["text"] == "hang" => {
+                        tokio::spawn(async move {
+                            let _stream = stream;
+                            std::future::pending::<()>().await;
+                        });
+                        continue;
+                    }
+                    _ => {
+                        let text = request["params"]["arguments"]["text"].clone();
+                        let result = json!({"jsonrpc": "2.0", "id": request["id"], "result": {
+                            "content": [{"type": "text", "text": text}],
+                            "isError": false
+                        }});
+                        ("200 OK", "application/json", result.to_string())
+                    }
+                };
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nMcp-Session-Id: session-1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_mcp_over_http() {
+        let url = mock_mcp_server().await;
+        let transport = StreamableHttpClientTransport::new(&url).with_http_client(Client::new());
+        let toolbox = McpToolBox::from_transport(transport).await.unwrap();
+
+        toolbox.health_check().await.unwrap();
+
+        let tools = toolbox.tools_definitions().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "echo");
+
+        let result = toolbox
+            .call_tool("echo".to_string(), json!({"text": "hello"}))
+            .await
+            .unwrap();
+        assert_eq!(result, "hello");
+
+        let result = toolbox.call_tool("echo".to_string(), json!([])).await;
+        assert!(matches!(result, Err(ToolError::LLMError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_disable_tools() {
+        let url = mock_mcp_server().await;
+        let transport = StreamableHttpClientTransport::new(&url).with_http_client(Client::new());
+        let toolbox = McpToolBox::from_transport(transport)
+            .await
+            .unwrap()
+            .disable_tools(&["echo", "unknown"]);
+
+        assert!(toolbox.tools_definitions().unwrap().is_empty());
+        let result = toolbox
+            .call_tool("echo".to_string(), json!({"text": "hello"}))
+            .await;
+        assert!(matches!(result, Err(ToolError::NoToolFound(name)) if name == "echo"));
+    }
+
+    #[tokio::test]
+    async fn test_with_label() {
+        let url = mock_mcp_server().await;
+        let transport = StreamableHttpClientTransport::new(&url).with_http_client(Client::new());
+        let toolbox = McpToolBox::from_transport(transport)
+            .await
+            .unwrap()
+            .with_label("mock");
+
+        let tools = toolbox.tools_definitions().unwrap();
+        assert_eq!(tools[0].name, "mock_echo");
+        let result = toolbox
+            .call_tool("mock_echo".to_string(), json!({"text": "hello"}))
+            .await
+            .unwrap();
+        assert_eq!(result, "hello");
+        let result = toolbox
+            .call_tool("echo".to_string(), json!({"text": "hello"}))
+            .await;
+        assert!(matches!(result, Err(ToolError::NoToolFound(name)) if name == "echo"));
+
+        let toolbox = toolbox.disable_tools(&["echo"]);
+        assert!(toolbox.tools_definitions().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_with_headers() {
+        let url = mock_mcp_server().await;
+        let headers = |token: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(AUTHORIZATION, format!("Bearer {token}").parse().unwrap());
+            headers
+        };
+
+        let transport = StreamableHttpClientTransport::new(&url)
+            .with_http_client(Client::new())
+            .with_headers(headers("valid"));
+        assert!(McpToolBox::from_transport(transport).await.is_ok());
+
+        let transport = StreamableHttpClientTransport::new(&url)
+            .with_http_client(Client::new())
+            .with_headers(headers("invalid"));
+        let err = McpToolBox::from_transport(transport).await.err().unwrap();
+        assert!(
+            err.to_string()
+                .contains("401 Unauthorized, check authentication headers"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_timeout() {
+        let url = mock_mcp_server().await;
+        let transport = StreamableHttpClientTransport::new(&url).with_http_client(Client::new());
+        let toolbox = McpToolBox::from_transport(transport)
+            .await
+            .unwrap()
+            .with_label("mock")
+            .with_timeout(Duration::from_millis(100));
+
+        let result = toolbox
+            .call_tool("mock_echo".to_string(), json!({"text": "hang"}))
+            .await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Tool 'mock_echo' of MCP server 'mock' timed out after 100ms"
+        );
+        let result = toolbox
+            .call_tool("mock_echo".to_string(), json!({"text": "hello"}))
+            .await;
+        assert_eq!(result.unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_sse_data() {
+        let body =
+            "event: message\r\ndata: {\"id\": 1}\r\n\r\n: comment\n\ndata: first\ndata:second\n\n";
+        assert_eq!(sse_data(body), vec!["{\"id\": 1}", "first\nsecond"]);
+    }
+}
@@ -17,7 +17,11 @@
 //!
 //! Ready-to-use `ToolBox` implementations are available:
 //! - [crate::tool::buildin]: Provides a set of useful built-in tools. (Requires the `tools-buildin` feature).
+//! - `crate::tool::feed`: Provides a `ToolBox` reading RSS and Atom feeds. (Requires the `tools-feed` feature).
+//! - [crate::tool::image]: Provides a `ToolBox` for generating images. (Requires the `tools-image` feature).
 //! - [crate::tool::mcp]: Provides a `ToolBox` for interacting with the MCP Client. (Requires the `mcp-client` feature).
+//! - [crate::tool::openapi]: Provides a `ToolBox` generating tools from an OpenAPI specification. (Requires the `tools-openapi` feature).
+//! - `crate::tool::sql`: Provides a `ToolBox` running read-only queries against SQL databases. (Requires the `tools-sql` feature).
 //! - [crate::tool::web]: Provides toolboxes for interacting with the web, such as searching and fetching content. (Requires the `tools-web` feature).
 //!
 //! For examples demonstrating how to use tools and toolboxes, look into the `examples` folder.
@@ -28,13 +32,46 @@
 #[cfg(feature = "tools-buildin")]
 pub mod buildin;
 
+#[cfg(feature = "tools-feed")]
+pub mod feed;
+
+#[cfg(feature = "tools-image")]
+pub mod image;
+
 #[cfg(feature = "mcp-client")]
 pub mod mcp;
 
+#[cfg(feature = "tools-openapi")]
+pub mod openapi;
+
+#[cfg(feature = "tools-sql")]
+pub mod sql;
+
 #[cfg(feature = "tools-web")]
 pub mod web;
 
+mod cache;
+mod dynamic;
+mod filter;
+mod lazy;
+mod session;
+
+pub use cache::ToolCache;
+pub use dynamic::DynamicToolBox;
+pub use filter::FilteredToolBox;
+pub use lazy::LazyToolBox;
+pub use session::session_var;
+pub(crate) use session::with_session_vars;
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
 // Re-export Tool structure, it is being used by ToolBoxes
@@ -53,9 +90,293 @@ pub use genai::chat::Tool;
 
 pub type ToolResult = Result<String, ToolError>;
 
+/// Chunks of JSON arguments of a tool call, see [`ToolBox::call_tool_streamed`]
+pub type ToolArgumentStream = BoxStream<'static, String>;
+
+/// Content produced by a tool.
+///
+/// Tools return plain strings, this enum helps to describe results that should not be inlined
+/// into the conversation. Converting it into a `String` produces the text stored in history:
+///
+/// ```rust
+/// use agentai::tool::{ToolContent, ToolResult};
+///
+/// fn generate_report() -> ToolResult {
+///     // ... write report to disk ...
+///     Ok(ToolContent::FileRef {
+///         path: "reports/summary.pdf".into(),
+///         mime: "application/pdf".to_string(),
+///         description: Some("Quarterly sales summary".to_string()),
+///     }
+///     .into())
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolContent {
+    /// Text passed to the model as is
+    Text(String),
+    /// File created by the tool, only a reference is passed to the model instead of file content
+    FileRef {
+        /// Location of the file, accessible by the user
+        path: PathBuf,
+        /// MIME type of the file
+        mime: String,
+        /// Short description of file content
+        description: Option<String>,
+    },
+}
+
+impl fmt::Display for ToolContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolContent::Text(text) => f.write_str(text),
+            ToolContent::FileRef {
+                path,
+                mime,
+                description,
+            } => {
+                write!(f, "File created at {} ({mime})", path.display())?;
+                if let Some(description) = description {
+                    write!(f, ": {description}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<ToolContent> for String {
+    fn from(content: ToolContent) -> Self {
+        content.to_string()
+    }
+}
+
+/// Tool result with suggestions of follow-up actions.
+///
+/// Some tools naturally lead to others, e.g. after searching for a product the next step is
+/// checking its availability. Tools can return `ToolOutput` with suggested tools, which the
+/// [`Agent`](crate::agent::Agent) passes to the model as a system note following the tool results.
+///
+/// Tools defined with [`#[toolbox]`](crate::tool::toolbox) can return
+/// `Result<ToolOutput, ToolError>` instead of [`ToolResult`]:
+///
+/// ```rust
+/// use agentai::tool::{ToolError, ToolOutput};
+///
+/// fn search_products(query: &str) -> Result<ToolOutput, ToolError> {
+///     Ok(ToolOutput::new(format!("Found 3 products matching '{query}'"))
+///         .with_suggested_tools(["check_availability"]))
+/// }
+/// ```
+///
+/// When a `ToolOutput` is converted into a `String`, only the content is kept.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolOutput {
+    /// Result of the tool passed to the model
+    pub content: String,
+    /// Names of tools suggested to be called next
+    pub suggested_tools: Vec<String>,
+    /// Free form hint about the next step, e.g. a question worth asking the user
+    pub hint: Option<String>,
+    /// How the agent run continues after the tool, see [`ToolOutcome`]
+    pub outcome: ToolOutcome,
+}
+
+/// How the [`Agent`](crate::agent::Agent) run continues after a tool returns [`ToolOutput`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ToolOutcome {
+    /// Model may call more tools
+    #[default]
+    Continue,
+    /// Tool was the final action of the run, e.g. an email was sent. Model gets the tool result
+    /// to formulate the final answer, but it can't call more tools. Tools which always finish
+    /// the run can be reported by [`ToolBox::is_terminal`] instead.
+    Finish,
+    /// Run ends immediately, without another model request, and the message is the final
+    /// answer, e.g. when the tool finds out the request can't be fulfilled. Structured answers
+    /// are deserialized from the message. Results of other tools called in the same turn are
+    /// still added to the history.
+    Abort(String),
+}
+
+impl ToolOutput {
+    /// Creates output without suggestions
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets names of tools suggested to be called next
+    pub fn with_suggested_tools(
+        mut self,
+        tools: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.suggested_tools = tools.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets free form hint about the next step
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Sets how the agent run continues after the tool
+    pub fn with_outcome(mut self, outcome: ToolOutcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+
+    /// Returns note passed to the model, `None` if there are no suggestions
+    pub(crate) fn suggestions_note(&self, tool_name: &str) -> Option<String> {
+        let mut notes = vec![];
+        if !self.suggested_tools.is_empty() {
+            notes.push(format!(
+                "Tool '{tool_name}' suggests calling next: {}.",
+                self.suggested_tools.join(", ")
+            ));
+        }
+        if let Some(hint) = &self.hint {
+            notes.push(format!("Hint from tool '{tool_name}': {hint}"));
+        }
+        (!notes.is_empty()).then(|| notes.join("\n"))
+    }
+}
+
+impl From<String> for ToolOutput {
+    fn from(content: String) -> Self {
+        Self::new(content)
+    }
+}
+
+impl From<ToolContent> for ToolOutput {
+    fn from(content: ToolContent) -> Self {
+        Self::new(content)
+    }
+}
+
+impl From<ToolOutput> for String {
+    fn from(output: ToolOutput) -> Self {
+        output.content
+    }
+}
+
 // Re-export tool and toolbox macros, they are used to generate auto implementation of
 pub use agentai_macros::toolbox;
 
+/// Deserializes arguments of a tool call, used by code generated by the [`toolbox`] macro.
+///
+/// Failures are reported as [`ToolError::InvalidArguments`] with the path of the invalid value.
+/// Missing (`null`) arguments are treated as an empty object, some providers send them for
+/// tools with only optional parameters.
+#[doc(hidden)]
+pub fn parse_arguments<T: serde::de::DeserializeOwned>(
+    tool_name: &str,
+    arguments: Value,
+) -> Result<T, ToolError> {
+    let arguments = match arguments {
+        Value::Null => Value::Object(Default::default()),
+        arguments => arguments,
+    };
+    serde_path_to_error::deserialize(arguments).map_err(|err| ToolError::InvalidArguments {
+        tool_name: tool_name.to_string(),
+        path: err.path().to_string(),
+        message: err.inner().to_string(),
+    })
+}
+
+/// Tools of an `impl` block marked with `#[toolbox(part = "...")]`, used by code generated by the
+/// [`toolbox`] macro.
+#[doc(hidden)]
+pub struct ToolBoxPart {
+    /// Names of all tools of the block
+    pub tools: &'static [&'static str],
+    /// Tools marked as `readonly`
+    pub readonly: &'static [&'static str],
+    /// Tools marked as `terminal`
+    pub terminal: &'static [&'static str],
+    /// Tools marked as `non_idempotent`
+    pub non_idempotent: &'static [&'static str],
+}
+
+/// Checks if any tool name is used in more than one group, used by code generated by the
+/// [`toolbox`] macro to check tool names of all `impl` blocks during compilation.
+#[doc(hidden)]
+pub const fn has_duplicate_tools(groups: &[&[&str]]) -> bool {
+    let mut group = 0;
+    while group < groups.len() {
+        let mut tool = 0;
+        while tool < groups[group].len() {
+            let mut other_group = group + 1;
+            while other_group < groups.len() {
+                let mut other_tool = 0;
+                while other_tool < groups[other_group].len() {
+                    if str_eq(groups[group][tool], groups[other_group][other_tool]) {
+                        return true;
+                    }
+                    other_tool += 1;
+                }
+                other_group += 1;
+            }
+            tool += 1;
+        }
+        group += 1;
+    }
+    false
+}
+
+/// Compares strings in const context
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Default timeout of HTTP requests made by built-in tools
+pub const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+static HTTP_TIMEOUT_MILLIS: AtomicU64 = AtomicU64::new(DEFAULT_HTTP_TIMEOUT.as_millis() as u64);
+
+/// Sets timeout of HTTP requests made by built-in tools, [`DEFAULT_HTTP_TIMEOUT`] by default.
+///
+/// The timeout covers the whole request, from connecting until the response body is read, so a
+/// single hung server can't stall the agent. It applies to toolboxes and MCP transports created
+/// after the call. A single toolbox can use a different timeout by passing its own client to
+/// `with_http_client`.
+///
+/// ```rust
+/// # use std::time::Duration;
+/// agentai::tool::set_default_http_timeout(Duration::from_secs(10));
+/// ```
+pub fn set_default_http_timeout(timeout: Duration) {
+    HTTP_TIMEOUT_MILLIS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Returns timeout of HTTP requests made by built-in tools, see [`set_default_http_timeout`].
+pub fn default_http_timeout() -> Duration {
+    Duration::from_millis(HTTP_TIMEOUT_MILLIS.load(Ordering::Relaxed))
+}
+
+/// HTTP client used by built-in tools unless a client is provided
+#[allow(dead_code)] // Unused when no built-in tools are enabled
+pub(crate) fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .unwrap_or_default()
+}
+
 /// Manages a collection of callable `Tool` instances.
 ///
 /// Implementors of `ToolBox` provide a way to group related tools and expose them to the
@@ -68,8 +389,12 @@ pub use agentai_macros::toolbox;
 /// For most use cases, implementing this trait can be significantly simplified by using
 /// the [`#[toolbox]`](crate::tool::toolbox) attribute macro. This macro automatically
 /// generates the necessary `ToolBox` implementation for a struct based on its methods.
+///
+/// Toolboxes have to be `Send + Sync`: the [`Agent`](crate::agent::Agent) holds `&dyn ToolBox`
+/// across `.await` points, so runs with tools, including streamed ones, are `Send` futures which
+/// can be spawned on a multi-threaded runtime.
 #[async_trait::async_trait]
-pub trait ToolBox {
+pub trait ToolBox: Send + Sync {
     /// Returns a list of all `Tool` instances contained within this ToolBox.
     /// These definitions include the tool's name, description, and parameters,
     /// which are used by the language model to decide which tool to call.
@@ -103,6 +428,273 @@ pub trait ToolBox {
     /// A `Result` containing the tool's output as a `String` on success,
     /// or a `ToolError` if the tool call fails or the tool is not found.
     async fn call_tool(&self, tool_name: String, arguments: Value) -> ToolResult;
+
+    /// Calls a tool like [`ToolBox::call_tool`], returning result with suggestions of follow-up
+    /// actions, see [`ToolOutput`].
+    ///
+    /// The [`Agent`](crate::agent::Agent) calls tools using this method. Default implementation
+    /// calls [`ToolBox::call_tool`] and returns its result without suggestions. Toolboxes
+    /// providing suggestions should override it, implementing `call_tool` by dropping them.
+    /// [`#[toolbox]`](crate::tool::toolbox) macro does it for tools returning
+    /// `Result<ToolOutput, ToolError>`.
+    ///
+    async fn call_tool_output(
+        &self,
+        tool_name: String,
+        arguments: Value,
+    ) -> Result<ToolOutput, ToolError> {
+        self.call_tool(tool_name, arguments)
+            .await
+            .map(ToolOutput::from)
+    }
+
+    /// Returns `true` if the tool accepts arguments streamed in chunks, see
+    /// [`ToolBox::call_tool_streamed`]. Default implementation returns `false`.
+    fn streams_arguments(&self, _tool_name: &str) -> bool {
+        false
+    }
+
+    /// Calls a tool with arguments streamed in chunks, as they are generated by the model.
+    ///
+    /// When an argument is a large blob, e.g. a document to analyze, buffering all of it before
+    /// dispatch adds latency. Tools opting in with [`ToolBox::streams_arguments`] can start
+    /// processing before the whole input is generated. Concatenated chunks form JSON arguments
+    /// of the call.
+    ///
+    /// This method is called only for tools opting in, use [`call_tool_with_stream`] to call
+    /// any tool, arguments of other tools are buffered. Default implementation returns
+    /// [`ToolError::NoToolFound`].
+    ///
+    /// Currently the [`Agent`](crate::agent::Agent) always calls tools with buffered arguments,
+    /// because GenAI delivers tool calls only when they are complete.
+    async fn call_tool_streamed(
+        &self,
+        tool_name: String,
+        _arguments: ToolArgumentStream,
+    ) -> Result<ToolOutput, ToolError> {
+        Err(ToolError::NoToolFound(tool_name))
+    }
+
+    /// Returns `true` if calling the tool may modify state outside of the agent, e.g. write
+    /// files, send messages or change records in a database.
+    ///
+    /// This metadata can be used by safety policies, for example to auto-approve read-only tools
+    /// while requiring confirmation for mutating ones. Default implementation assumes that every
+    /// tool has side effects. When using [`#[toolbox]`](crate::tool::toolbox) macro, tools can be
+    /// marked as read-only with `#[tool(readonly)]`.
+    fn has_side_effects(&self, _tool_name: &str) -> bool {
+        true
+    }
+
+    /// Returns `true` if calling the tool again with the same arguments has the same effect as
+    /// calling it once, so a failed call can be safely retried, see
+    /// [`Agent::with_tool_retry`](crate::agent::Agent::with_tool_retry).
+    ///
+    /// Default implementation assumes that every tool is idempotent. Tools like sending a
+    /// message or making a payment should return `false`. When using
+    /// [`#[toolbox]`](crate::tool::toolbox) macro, tools can be marked with
+    /// `#[tool(non_idempotent)]`.
+    fn is_idempotent(&self, _tool_name: &str) -> bool {
+        true
+    }
+
+    /// Returns how the agent should handle errors returned by the tool.
+    ///
+    /// By default errors are fed back to the model, which allows it to react, e.g. by calling
+    /// the tool again with corrected arguments. Toolboxes wrapping critical operations can return
+    /// [`ToolErrorPolicy::Abort`] to stop the agent run instead.
+    fn error_policy(&self, _tool_name: &str) -> ToolErrorPolicy {
+        ToolErrorPolicy::FeedToModel
+    }
+
+    /// Returns `true` if the tool is the final action of a run, e.g. sending an email, after
+    /// which the model should not act anymore.
+    ///
+    /// After a terminal tool succeeds, the [`Agent`](crate::agent::Agent) makes one more request
+    /// without tools, so the model can only formulate the final answer using the tool result.
+    /// When using [`#[toolbox]`](crate::tool::toolbox) macro, tools can be marked as terminal
+    /// with `#[tool(terminal)]`. Tools deciding at runtime can return [`ToolOutcome::Finish`].
+    fn is_terminal(&self, _tool_name: &str) -> bool {
+        false
+    }
+
+    /// Returns a number changed whenever tools of the toolbox change.
+    ///
+    /// The [`Agent`](crate::agent::Agent) prepares tool definitions once per run. Toolboxes
+    /// whose tools can change during a run, e.g. when a tool discovers new capabilities, report
+    /// a new revision, then definitions are prepared again and sent with the next request, see
+    /// [`DynamicToolBox`]. Default implementation returns `0`, tools never change.
+    fn revision(&self) -> u64 {
+        0
+    }
+
+    /// Prepares the toolbox before its tools are used.
+    ///
+    /// The [`Agent`](crate::agent::Agent) awaits this method at the beginning of every run,
+    /// before tool definitions are requested. Toolboxes requiring async setup, e.g. opening
+    /// a database connection or fetching tool definitions from a remote server, can do it here.
+    /// Because it is called on every run, setup should be done only once, see [`LazyToolBox`].
+    async fn initialize(&self) -> Result<(), ToolError> {
+        Ok(())
+    }
+
+    /// Checks if the toolbox is ready to be used.
+    ///
+    /// Toolboxes backed by external services, e.g. MCP Servers or web APIs, can probe the
+    /// service here, so problems are detected before the agent relies on them. Use
+    /// [`Agent::health_check`](crate::agent::Agent::health_check) to fail fast at startup.
+    ///
+    /// Default implementation does nothing.
+    async fn health_check(&self) -> Result<(), ToolError> {
+        Ok(())
+    }
+}
+
+/// Calls a tool with arguments streamed in chunks.
+///
+/// Tools opting in to streaming, see [`ToolBox::streams_arguments`], receive the stream as is.
+/// For other tools chunks are buffered and parsed, then the tool is called using
+/// [`ToolBox::call_tool_output`]. Invalid JSON is reported as [`ToolError::LLMError`].
+pub async fn call_tool_with_stream(
+    toolbox: &dyn ToolBox,
+    tool_name: String,
+    arguments: ToolArgumentStream,
+) -> Result<ToolOutput, ToolError> {
+    if toolbox.streams_arguments(&tool_name) {
+        return toolbox.call_tool_streamed(tool_name, arguments).await;
+    }
+    let arguments: String = arguments.collect::<Vec<_>>().await.concat();
+    let arguments = serde_json::from_str(&arguments)
+        .map_err(|err| ToolError::LLMError(format!("Invalid tool arguments: {err}")))?;
+    toolbox.call_tool_output(tool_name, arguments).await
+}
+
+/// Problem with tool definitions found by [`validate_tool_schemas`].
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    /// Toolbox failed to provide tool definitions
+    #[error("Failed to get tool definitions: {0}")]
+    Definitions(ToolError),
+    /// Schema of the tool is not a valid JSON Schema of an object
+    #[error("Invalid schema of tool '{tool_name}': {message}")]
+    InvalidSchema { tool_name: String, message: String },
+}
+
+/// Checks that schemas of all tools are valid JSON Schemas describing an object.
+///
+/// Malformed schemas are rejected by providers at request time, often with an opaque error.
+/// Call it at startup to catch mistakes early, especially in hand-written toolboxes or tools
+/// generated from external specifications. All problems are returned, not only the first one.
+///
+/// ```rust
+/// # use agentai::tool::{validate_tool_schemas, ToolBoxSet};
+/// let toolbox = ToolBoxSet::default();
+/// if let Err(errors) = validate_tool_schemas(&toolbox) {
+///     for error in errors {
+///         eprintln!("{error}");
+///     }
+/// }
+/// ```
+pub fn validate_tool_schemas(toolbox: &dyn ToolBox) -> Result<(), Vec<SchemaError>> {
+    let tools = toolbox
+        .tools_definitions()
+        .map_err(|err| vec![SchemaError::Definitions(err)])?;
+    let errors: Vec<_> = tools
+        .iter()
+        .filter_map(|tool| {
+            let schema = tool.schema.as_ref()?;
+            let message = if !schema.is_object() {
+                "schema has to be a JSON object".to_string()
+            } else if schema.get("type").is_some_and(|kind| kind != "object") {
+                format!("arguments have to be an object, not {}", schema["type"])
+            } else {
+                jsonschema::validator_for(schema).err()?.to_string()
+            };
+            Some(SchemaError::InvalidSchema {
+                tool_name: tool.name.clone(),
+                message,
+            })
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Describes how the [`Agent`](crate::agent::Agent) handles errors returned by a tool,
+/// see [`ToolBox::error_policy`] and [`Agent::with_tool_error_policy`](crate::agent::Agent::with_tool_error_policy).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToolErrorPolicy {
+    /// Error message is passed to the model as the tool result
+    #[default]
+    FeedToModel,
+    /// Agent run is stopped and the tool error is returned
+    Abort,
+}
+
+/// Shared toolboxes can be used everywhere a `ToolBox` is expected.
+///
+/// This allows to keep one toolbox instance (e.g. with an open MCP connection) and use it
+/// in multiple agents or in multiple [`ToolBoxSet`]s.
+#[async_trait::async_trait]
+impl<T: ToolBox + ?Sized> ToolBox for Arc<T> {
+    fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+        (**self).tools_definitions()
+    }
+
+    async fn call_tool(&self, tool_name: String, arguments: Value) -> ToolResult {
+        (**self).call_tool(tool_name, arguments).await
+    }
+
+    async fn call_tool_output(
+        &self,
+        tool_name: String,
+        arguments: Value,
+    ) -> Result<ToolOutput, ToolError> {
+        (**self).call_tool_output(tool_name, arguments).await
+    }
+
+    fn streams_arguments(&self, tool_name: &str) -> bool {
+        (**self).streams_arguments(tool_name)
+    }
+
+    async fn call_tool_streamed(
+        &self,
+        tool_name: String,
+        arguments: ToolArgumentStream,
+    ) -> Result<ToolOutput, ToolError> {
+        (**self).call_tool_streamed(tool_name, arguments).await
+    }
+
+    fn has_side_effects(&self, tool_name: &str) -> bool {
+        (**self).has_side_effects(tool_name)
+    }
+
+    fn is_idempotent(&self, tool_name: &str) -> bool {
+        (**self).is_idempotent(tool_name)
+    }
+
+    fn error_policy(&self, tool_name: &str) -> ToolErrorPolicy {
+        (**self).error_policy(tool_name)
+    }
+
+    fn is_terminal(&self, tool_name: &str) -> bool {
+        (**self).is_terminal(tool_name)
+    }
+
+    fn revision(&self) -> u64 {
+        (**self).revision()
+    }
+
+    async fn initialize(&self) -> Result<(), ToolError> {
+        (**self).initialize().await
+    }
+
+    async fn health_check(&self) -> Result<(), ToolError> {
+        (**self).health_check().await
+    }
 }
 
 #[derive(Error, Debug)]
@@ -126,6 +718,17 @@ pub enum ToolError {
     /// human readable error message, this message will be passed to the LLM
     #[error("{0}")]
     LLMError(String),
+    /// Indicates that arguments provided by the model don't match parameters of the tool.
+    /// Like [`ToolError::LLMError`], it is passed to the LLM, so the tool can be called again.
+    #[error("Invalid arguments for tool '{tool_name}' at {path}: {message}")]
+    InvalidArguments {
+        /// Name of the called tool
+        tool_name: String,
+        /// Path of the invalid value in arguments, e.g. `address.city`, `.` for the whole object
+        path: String,
+        /// Description of the problem
+        message: String,
+    },
     /// Indicates a failure occurred during the execution of a specific tool.
     /// This is a general error variant that can encapsulate various runtime issues
     /// encountered while the tool's logic is running.
@@ -137,6 +740,51 @@ pub enum ToolError {
     Other(#[from] anyhow::Error),
 }
 
+impl ToolError {
+    /// Makes copies of the error for every caller of a coalesced tool call, see
+    /// [`Agent::with_coalesced_tool_calls`](crate::agent::Agent::with_coalesced_tool_calls).
+    /// Underlying error of [`ToolError::Other`] is shared by the copies.
+    pub(crate) fn shared(self) -> impl Fn() -> ToolError {
+        let other = match self {
+            ToolError::Other(err) => Ok(SharedError(Arc::new(err))),
+            err => Err(err),
+        };
+        move || match &other {
+            Ok(shared) => ToolError::Other(anyhow::Error::new(shared.clone())),
+            Err(ToolError::ToolsDefinitionNotReady) => ToolError::ToolsDefinitionNotReady,
+            Err(ToolError::NoToolFound(name)) => ToolError::NoToolFound(name.clone()),
+            Err(ToolError::LLMError(message)) => ToolError::LLMError(message.clone()),
+            Err(ToolError::InvalidArguments {
+                tool_name,
+                path,
+                message,
+            }) => ToolError::InvalidArguments {
+                tool_name: tool_name.clone(),
+                path: path.clone(),
+                message: message.clone(),
+            },
+            Err(ToolError::ExecutionError) => ToolError::ExecutionError,
+            Err(ToolError::Other(_)) => unreachable!("other errors are shared"),
+        }
+    }
+}
+
+/// Error of [`ToolError::Other`] shared by copies made with [`ToolError::shared`]
+#[derive(Debug, Clone)]
+struct SharedError(Arc<anyhow::Error>);
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::error::Error for SharedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
 /// A collection of `ToolBox` instances.
 ///
 /// It allows for managing multiple toolboxes as a single unit, aggregating
@@ -147,7 +795,7 @@ pub enum ToolError {
 /// a tool with a matching name will be used to execute the call.
 #[derive(Default)]
 pub struct ToolBoxSet {
-    toolboxes: Vec<Box<dyn ToolBox + Send + Sync>>,
+    toolboxes: Vec<Box<dyn ToolBox>>,
 }
 
 impl ToolBoxSet {
@@ -161,9 +809,21 @@ impl ToolBoxSet {
     /// The order in which toolboxes are added is significant. When a tool call
     /// is made, the `ToolBoxSet` will search for the tool in the order the
     /// toolboxes were added.
-    pub fn add_tool(&mut self, toolbox: impl ToolBox + Send + Sync + 'static) {
+    pub fn add_tool(&mut self, toolbox: impl ToolBox + 'static) {
         self.toolboxes.push(Box::new(toolbox));
     }
+
+    /// Finds the first `ToolBox` defining a tool with given name
+    fn find_toolbox(&self, tool_name: &str) -> Option<&dyn ToolBox> {
+        self.toolboxes
+            .iter()
+            .find(|toolbox| {
+                toolbox
+                    .tools_definitions()
+                    .is_ok_and(|tools| tools.iter().any(|tool| tool.name == tool_name))
+            })
+            .map(|toolbox| toolbox.as_ref())
+    }
 }
 
 #[async_trait::async_trait]
@@ -201,4 +861,544 @@ impl ToolBox for ToolBoxSet {
         }
         Err(ToolError::NoToolFound(tool_name))
     }
+
+    /// Calls a tool like [`ToolBoxSet::call_tool`], keeping suggestions of follow-up actions.
+    async fn call_tool_output(
+        &self,
+        tool_name: String,
+        arguments: Value,
+    ) -> Result<ToolOutput, ToolError> {
+        for toolbox in &self.toolboxes {
+            match toolbox
+                .call_tool_output(tool_name.clone(), arguments.clone())
+                .await
+            {
+                Err(ToolError::NoToolFound(_)) => continue,
+                result => return result,
+            }
+        }
+        Err(ToolError::NoToolFound(tool_name))
+    }
+
+    /// Returns streaming support reported by the first `ToolBox` defining the tool.
+    fn streams_arguments(&self, tool_name: &str) -> bool {
+        self.find_toolbox(tool_name)
+            .is_some_and(|toolbox| toolbox.streams_arguments(tool_name))
+    }
+
+    /// Calls a tool with streamed arguments using the first `ToolBox` defining the tool.
+    async fn call_tool_streamed(
+        &self,
+        tool_name: String,
+        arguments: ToolArgumentStream,
+    ) -> Result<ToolOutput, ToolError> {
+        match self.find_toolbox(&tool_name) {
+            Some(toolbox) => toolbox.call_tool_streamed(tool_name, arguments).await,
+            None => Err(ToolError::NoToolFound(tool_name)),
+        }
+    }
+
+    /// Returns side effects flag reported by the first `ToolBox` defining the tool.
+    ///
+    /// Unknown tools are reported as having side effects.
+    fn has_side_effects(&self, tool_name: &str) -> bool {
+        self.find_toolbox(tool_name)
+            .is_none_or(|toolbox| toolbox.has_side_effects(tool_name))
+    }
+
+    /// Returns idempotency flag reported by the first `ToolBox` defining the tool.
+    fn is_idempotent(&self, tool_name: &str) -> bool {
+        self.find_toolbox(tool_name)
+            .is_none_or(|toolbox| toolbox.is_idempotent(tool_name))
+    }
+
+    /// Returns error policy of the first `ToolBox` defining the tool.
+    fn error_policy(&self, tool_name: &str) -> ToolErrorPolicy {
+        self.find_toolbox(tool_name)
+            .map(|toolbox| toolbox.error_policy(tool_name))
+            .unwrap_or_default()
+    }
+
+    /// Returns terminal flag of the first `ToolBox` defining the tool.
+    fn is_terminal(&self, tool_name: &str) -> bool {
+        self.find_toolbox(tool_name)
+            .is_some_and(|toolbox| toolbox.is_terminal(tool_name))
+    }
+
+    /// Returns sum of revisions of contained toolboxes, so a change of any of them is visible.
+    fn revision(&self) -> u64 {
+        self.toolboxes.iter().fold(0, |revision, toolbox| {
+            revision.wrapping_add(toolbox.revision())
+        })
+    }
+
+    /// Initializes all contained toolboxes, in the order they were added.
+    async fn initialize(&self) -> Result<(), ToolError> {
+        for toolbox in &self.toolboxes {
+            toolbox.initialize().await?;
+        }
+        Ok(())
+    }
+
+    /// Checks all contained toolboxes, in the order they were added, failing on the first error.
+    async fn health_check(&self) -> Result<(), ToolError> {
+        for toolbox in &self.toolboxes {
+            toolbox.health_check().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::hanging_server;
+
+    struct StreamingToolBox;
+
+    #[async_trait::async_trait]
+    impl ToolBox for StreamingToolBox {
+        fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+            Ok(vec![])
+        }
+
+        async fn call_tool(&self, tool_name: String, arguments: Value) -> ToolResult {
+            Ok(format!("{tool_name} {arguments}"))
+        }
+
+        fn streams_arguments(&self, tool_name: &str) -> bool {
+            tool_name == "analyze"
+        }
+
+        async fn call_tool_streamed(
+            &self,
+            _tool_name: String,
+            arguments: ToolArgumentStream,
+        ) -> Result<ToolOutput, ToolError> {
+            let chunks = arguments.count().await;
+            Ok(ToolOutput::new(format!("received {chunks} chunks")))
+        }
+    }
+
+    struct DefinitionsToolBox(Vec<Tool>);
+
+    #[async_trait::async_trait]
+    impl ToolBox for DefinitionsToolBox {
+        fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+            Ok(self.0.clone())
+        }
+
+        async fn call_tool(&self, tool_name: String, _arguments: Value) -> ToolResult {
+            Err(ToolError::NoToolFound(tool_name))
+        }
+    }
+
+    #[test]
+    fn test_validate_tool_schemas() {
+        let valid = DefinitionsToolBox(vec![
+            Tool::new("no_arguments"),
+            Tool::new("search").with_schema(serde_json::json!({
+                "type": "object",
+                "properties": {"query": {"type": "string"}},
+                "required": ["query"]
+            })),
+        ]);
+        assert!(validate_tool_schemas(&valid).is_ok());
+
+        let invalid = DefinitionsToolBox(vec![
+            Tool::new("string").with_schema(serde_json::json!("object")),
+            Tool::new("array").with_schema(serde_json::json!({"type": "array"})),
+            Tool::new("malformed").with_schema(serde_json::json!({
+                "type": "object",
+                "properties": {"query": {"type": "text"}}
+            })),
+        ]);
+        let errors = validate_tool_schemas(&invalid).unwrap_err();
+        let tools: Vec<_> = errors
+            .iter()
+            .map(|error| match error {
+                SchemaError::InvalidSchema { tool_name, .. } => tool_name.as_str(),
+                SchemaError::Definitions(_) => "",
+            })
+            .collect();
+        assert_eq!(tools, ["string", "array", "malformed"]);
+        assert_eq!(
+            errors[1].to_string(),
+            "Invalid schema of tool 'array': arguments have to be an object, not \"array\""
+        );
+    }
+
+    struct CustomSchemaToolBox;
+
+    #[toolbox]
+    impl CustomSchemaToolBox {
+        /// Searches products
+        #[tool(
+            readonly,
+            schema = r#"{"type": "object", "properties": {"query": {"type": "string", "minLength": 1}}, "required": ["query"]}"#
+        )]
+        fn search(&self, query: String) -> ToolResult {
+            Ok(format!("Found {query}"))
+        }
+
+        /// Counts products
+        #[tool(schema_with = "Self::count_schema")]
+        fn count(&self, limit: u32) -> ToolResult {
+            Ok(limit.to_string())
+        }
+
+        fn count_schema() -> Value {
+            serde_json::json!({"type": "object", "properties": {"limit": {"type": "integer"}}})
+        }
+    }
+
+    #[tokio::test]
+    async fn test_toolbox_custom_schema() {
+        let toolbox = CustomSchemaToolBox;
+        let tools = toolbox.tools_definitions().unwrap();
+        assert_eq!(
+            tools[0].schema.as_ref().unwrap()["properties"]["query"]["minLength"],
+            1
+        );
+        assert_eq!(
+            tools[1].schema,
+            Some(
+                serde_json::json!({"type": "object", "properties": {"limit": {"type": "integer"}}})
+            )
+        );
+        assert!(validate_tool_schemas(&toolbox).is_ok());
+        // Arguments are still deserialized into method parameters
+        let result = toolbox
+            .call_tool("search".to_string(), serde_json::json!({"query": "shoes"}))
+            .await
+            .unwrap();
+        assert_eq!(result, "Found shoes");
+    }
+
+    #[derive(Debug)]
+    enum InventoryError {
+        UnknownProduct(String),
+        DatabaseDown,
+    }
+
+    impl From<InventoryError> for ToolError {
+        fn from(err: InventoryError) -> Self {
+            match err {
+                InventoryError::UnknownProduct(name) => {
+                    ToolError::LLMError(format!("Product '{name}' does not exist"))
+                }
+                InventoryError::DatabaseDown => ToolError::ExecutionError,
+            }
+        }
+    }
+
+    struct InventoryToolBox;
+
+    #[toolbox]
+    impl InventoryToolBox {
+        /// Returns stock of the product
+        #[tool]
+        fn stock(&self, name: String) -> Result<String, InventoryError> {
+            match name.as_str() {
+                "shoes" => Ok("5".to_string()),
+                "offline" => Err(InventoryError::DatabaseDown),
+                _ => Err(InventoryError::UnknownProduct(name)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_toolbox_typed_errors() {
+        let stock = |name: &str| {
+            InventoryToolBox.call_tool("stock".to_string(), serde_json::json!({"name": name}))
+        };
+        assert_eq!(stock("shoes").await.unwrap(), "5");
+        assert!(matches!(
+            stock("hats").await,
+            Err(ToolError::LLMError(message)) if message == "Product 'hats' does not exist"
+        ));
+        assert!(matches!(
+            stock("offline").await,
+            Err(ToolError::ExecutionError)
+        ));
+    }
+
+    #[derive(serde::Serialize)]
+    struct Product {
+        name: String,
+        stock: u32,
+    }
+
+    struct CatalogToolBox;
+
+    #[toolbox]
+    impl CatalogToolBox {
+        /// Returns the product
+        #[tool]
+        fn product(&self, name: String) -> Result<Product, ToolError> {
+            Ok(Product { name, stock: 5 })
+        }
+
+        /// Lists categories
+        #[tool]
+        async fn categories(&self) -> Result<Vec<String>, InventoryError> {
+            Ok(vec!["shoes".to_string(), "hats".to_string()])
+        }
+
+        /// Returns description of the product
+        #[tool]
+        fn description(&self, name: String) -> Result<String, ToolError> {
+            Ok(format!("\"{name}\" for everyday use"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_toolbox_serialized_results() {
+        let call =
+            |name: &str, arguments: Value| CatalogToolBox.call_tool(name.to_string(), arguments);
+        let product = call("product", serde_json::json!({"name": "shoes"})).await;
+        assert_eq!(product.unwrap(), r#"{"name":"shoes","stock":5}"#);
+        let categories = call("categories", Value::Null).await;
+        assert_eq!(categories.unwrap(), r#"["shoes","hats"]"#);
+        // Strings are not encoded as JSON
+        let description = call("description", serde_json::json!({"name": "shoes"})).await;
+        assert_eq!(description.unwrap(), "\"shoes\" for everyday use");
+    }
+
+    struct OrdersToolBox;
+
+    #[toolbox]
+    impl OrdersToolBox {
+        /// Lists orders of the customer
+        #[tool]
+        fn list_orders(
+            &self,
+            customer: String,
+            /// Maximum number of orders
+            #[tool_param(default = 10)]
+            limit: u32,
+            #[tool_param(default = "newest")] sort: String,
+            status: Option<String>,
+        ) -> ToolResult {
+            Ok(format!("{customer} {limit} {sort} {status:?}"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_toolbox_optional_parameters() {
+        let tools = OrdersToolBox.tools_definitions().unwrap();
+        let schema = tools[0].schema.clone().unwrap();
+        assert_eq!(schema["required"], serde_json::json!(["customer"]));
+        assert_eq!(schema["properties"]["limit"]["default"], 10);
+        assert_eq!(schema["properties"]["sort"]["default"], "newest");
+
+        let list = |arguments: Value| OrdersToolBox.call_tool("list_orders".to_string(), arguments);
+        let orders = list(serde_json::json!({"customer": "ann"})).await;
+        assert_eq!(orders.unwrap(), "ann 10 newest None");
+        let arguments =
+            serde_json::json!({"customer": "ann", "limit": 2, "sort": "oldest", "status": "paid"});
+        assert_eq!(
+            list(arguments).await.unwrap(),
+            "ann 2 oldest Some(\"paid\")"
+        );
+        // Required parameters are still required
+        let err = list(serde_json::json!({"limit": 2})).await.unwrap_err();
+        assert!(matches!(err, ToolError::InvalidArguments { .. }));
+    }
+
+    struct SplitToolBox;
+
+    #[toolbox(part = "files")]
+    impl SplitToolBox {
+        /// Reads a file
+        #[tool(readonly)]
+        fn read_file(&self, path: String) -> ToolResult {
+            Ok(format!("content of {path}"))
+        }
+
+        /// Deletes a file
+        #[tool(non_idempotent)]
+        async fn delete_file(&self, path: String) -> ToolResult {
+            Ok(format!("{path} deleted"))
+        }
+    }
+
+    #[toolbox(part = "mail")]
+    impl SplitToolBox {
+        /// Sends an email
+        #[tool(terminal)]
+        fn send_email(&self, to: String) -> ToolResult {
+            Ok(format!("sent to {to}"))
+        }
+    }
+
+    #[toolbox(parts = ["files", "mail"])]
+    impl SplitToolBox {
+        /// Searches the web
+        #[tool(readonly)]
+        fn web_search(&self, query: String) -> ToolResult {
+            Ok(format!("found {query}"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_toolbox_parts() {
+        let toolbox = SplitToolBox;
+        let names: Vec<String> = toolbox
+            .tools_definitions()
+            .unwrap()
+            .into_iter()
+            .map(|tool| tool.name)
+            .collect();
+        assert_eq!(
+            names,
+            ["web_search", "read_file", "delete_file", "send_email"]
+        );
+
+        let call = |name: &str, arguments: Value| toolbox.call_tool(name.to_string(), arguments);
+        let file = call("read_file", serde_json::json!({"path": "a.txt"})).await;
+        assert_eq!(file.unwrap(), "content of a.txt");
+        let deleted = call("delete_file", serde_json::json!({"path": "a.txt"})).await;
+        assert_eq!(deleted.unwrap(), "a.txt deleted");
+        let found = call("web_search", serde_json::json!({"query": "rust"})).await;
+        assert_eq!(found.unwrap(), "found rust");
+        assert!(matches!(
+            call("missing", Value::Null).await,
+            Err(ToolError::NoToolFound(_))
+        ));
+
+        assert!(!toolbox.has_side_effects("web_search"));
+        assert!(!toolbox.has_side_effects("read_file"));
+        assert!(toolbox.has_side_effects("send_email"));
+        assert!(toolbox.is_terminal("send_email"));
+        assert!(!toolbox.is_terminal("web_search"));
+        assert!(!toolbox.is_idempotent("delete_file"));
+        assert!(toolbox.is_idempotent("read_file"));
+    }
+
+    #[test]
+    fn test_has_duplicate_tools() {
+        assert!(!has_duplicate_tools(&[&["search"], &["read", "write"]]));
+        assert!(has_duplicate_tools(&[&["search"], &["read", "search"]]));
+        // Duplicates within a group are checked by the macro
+        assert!(!has_duplicate_tools(&[&["read", "read"]]));
+    }
+
+    struct ClockToolBox;
+
+    #[toolbox]
+    impl ClockToolBox {
+        /// Returns current time
+        #[tool]
+        fn now(&self) -> ToolResult {
+            Ok("12:00".to_string())
+        }
+
+        /// Returns current time in a time zone
+        #[tool]
+        fn now_in(&self, time_zone: Option<String>) -> ToolResult {
+            Ok(format!("12:00 {}", time_zone.unwrap_or("UTC".to_string())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_toolbox_ignores_missing_arguments() {
+        for arguments in [
+            Value::Null,
+            serde_json::json!({}),
+            serde_json::json!({"x": 1}),
+        ] {
+            let output = ClockToolBox.call_tool("now".to_string(), arguments).await;
+            assert_eq!(output.unwrap(), "12:00");
+        }
+        for arguments in [Value::Null, serde_json::json!({})] {
+            let output = ClockToolBox
+                .call_tool("now_in".to_string(), arguments)
+                .await;
+            assert_eq!(output.unwrap(), "12:00 UTC");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_call_tool_with_stream() {
+        let chunks = |chunks: &[&str]| -> ToolArgumentStream {
+            let chunks: Vec<String> = chunks.iter().map(|chunk| chunk.to_string()).collect();
+            futures::stream::iter(chunks).boxed()
+        };
+
+        let output = call_tool_with_stream(
+            &StreamingToolBox,
+            "analyze".to_string(),
+            chunks(&["{\"text\": ", "\"long", " text\"}"]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.content, "received 3 chunks");
+
+        // Arguments of tools not opting in are buffered
+        let output = call_tool_with_stream(
+            &StreamingToolBox,
+            "echo".to_string(),
+            chunks(&["{\"text\": ", "\"hi\"}"]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output.content, "echo {\"text\":\"hi\"}");
+
+        let result = call_tool_with_stream(
+            &StreamingToolBox,
+            "echo".to_string(),
+            chunks(&["{\"text\": "]),
+        )
+        .await;
+        assert!(matches!(result, Err(ToolError::LLMError(_))));
+    }
+
+    #[test]
+    fn test_tool_content_file_ref_summary() {
+        let content = ToolContent::FileRef {
+            path: PathBuf::from("/tmp/report.csv"),
+            mime: "text/csv".to_string(),
+            description: Some("Monthly report".to_string()),
+        };
+        assert_eq!(
+            String::from(content),
+            "File created at /tmp/report.csv (text/csv): Monthly report"
+        );
+        assert_eq!(ToolContent::Text("text".to_string()).to_string(), "text");
+    }
+
+    #[test]
+    fn test_tool_error_shared() {
+        let err = anyhow::Error::new(std::io::Error::other("disk full")).context("Saving failed");
+        let shared = ToolError::Other(err).shared();
+        for _ in 0..2 {
+            let ToolError::Other(err) = shared() else {
+                panic!("Variant of the error changed");
+            };
+            assert_eq!(format!("{err:#}"), "Saving failed: disk full");
+            assert!(err.chain().any(|err| err.is::<std::io::Error>()));
+        }
+
+        let shared = ToolError::InvalidArguments {
+            tool_name: "search".to_string(),
+            path: "query".to_string(),
+            message: "missing".to_string(),
+        }
+        .shared();
+        assert!(matches!(shared(), ToolError::InvalidArguments { path, .. } if path == "query"));
+    }
+
+    #[tokio::test]
+    async fn test_http_client_timeout() {
+        assert_eq!(default_http_timeout(), DEFAULT_HTTP_TIMEOUT);
+
+        let url = hanging_server().await;
+        let err = http_client(Duration::from_millis(100))
+            .get(&url)
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout());
+    }
 }
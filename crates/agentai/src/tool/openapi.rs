@@ -0,0 +1,570 @@
+//! # OpenAPI Tools
+//!
+//! This module provides [`OpenApiToolBox`], which generates tools from an OpenAPI (or Swagger)
+//! JSON specification. Every operation of the API becomes a separate tool, so existing REST APIs
+//! can be used by an agent without writing a tool for every endpoint.
+//!
+//! Tool is named after the `operationId` of the operation, or after HTTP method and path when
+//! the id is missing. Its parameters are built from operation parameters, the JSON request body
+//! is passed in the `body` parameter. Calling the tool makes the corresponding HTTP request and
+//! returns the response body.
+//!
+//! ```rust,no_run
+//! # use agentai::Agent;
+//! # use agentai::tool::openapi::{OpenApiAuth, OpenApiToolBox};
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let spec = std::fs::read_to_string("petstore.json")?;
+//! let toolbox = OpenApiToolBox::from_spec(
+//!     &spec,
+//!     "https://petstore.example.com/v1",
+//!     OpenApiAuth::Bearer("<API TOKEN>".to_string()),
+//! )?;
+//! let mut agent = Agent::new("You are a pet store assistant");
+//! let answer: String = agent
+//!     .run("gpt-4o-mini", "Which pets are available?", Some(&toolbox))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::tool::{default_http_timeout, http_client, Tool, ToolBox, ToolError, ToolResult};
+use anyhow::{anyhow, Context};
+use log::debug;
+use reqwest::{Client, Method, Url};
+use serde_json::{json, Map, Value};
+
+/// HTTP methods which can be described by an OpenAPI path item
+const METHODS: [&str; 7] = ["get", "post", "put", "patch", "delete", "head", "options"];
+
+/// Name of the tool parameter holding the request body
+const BODY_PARAMETER: &str = "body";
+
+/// Maximum length of a tool name accepted by most providers
+const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// Maximum depth of resolved `$ref` references, protects against recursive schemas
+const MAX_REF_DEPTH: usize = 16;
+
+/// Authentication added to every request made by [`OpenApiToolBox`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OpenApiAuth {
+    /// Requests are sent without authentication
+    #[default]
+    None,
+    /// Token sent in the `Authorization: Bearer` header
+    Bearer(String),
+    /// API key sent in a request header
+    ApiKeyHeader {
+        /// Name of the header, e.g. `X-API-Key`
+        name: String,
+        /// API key value
+        value: String,
+    },
+    /// API key sent as a query parameter
+    ApiKeyQuery {
+        /// Name of the query parameter, e.g. `api_key`
+        name: String,
+        /// API key value
+        value: String,
+    },
+}
+
+/// Location of an operation parameter
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParameterLocation {
+    Path,
+    Query,
+    Header,
+}
+
+#[derive(Debug, Clone)]
+struct Parameter {
+    name: String,
+    location: ParameterLocation,
+}
+
+#[derive(Debug, Clone)]
+struct Operation {
+    tool: Tool,
+    method: Method,
+    path: String,
+    parameters: Vec<Parameter>,
+}
+
+/// Provides a tool for every operation of an API described by an OpenAPI specification.
+///
+/// Both OpenAPI 3 and Swagger 2 specifications in JSON format are supported. Local `$ref`
+/// references are resolved, so tool schemas are self-contained. Operations using `GET`, `HEAD`
+/// or `OPTIONS` methods are reported as read-only, see [`ToolBox::has_side_effects`].
+///
+/// Responses with an error status are returned to the model as tool errors, including the
+/// response body, so it can correct its request.
+pub struct OpenApiToolBox {
+    client: Client,
+    base_url: String,
+    auth: OpenApiAuth,
+    operations: Vec<Operation>,
+}
+
+impl OpenApiToolBox {
+    /// Creates a new instance of `OpenApiToolBox` from an OpenAPI specification.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec_json` - OpenAPI specification in JSON format.
+    /// * `base_url` - URL of the API server, operation paths are appended to it.
+    /// * `auth` - Authentication added to every request.
+    pub fn from_spec(spec_json: &str, base_url: &str, auth: OpenApiAuth) -> anyhow::Result<Self> {
+        let spec: Value =
+            serde_json::from_str(spec_json).context("OpenAPI specification is not valid JSON")?;
+        Url::parse(base_url).with_context(|| format!("Invalid API base URL {base_url}"))?;
+        Ok(Self {
+            client: http_client(default_http_timeout()),
+            base_url: base_url.to_string(),
+            auth,
+            operations: parse_operations(&spec)?,
+        })
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to share connection pool or to configure proxy.
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Builds URL of the operation, with path parameters taken from arguments
+    fn operation_url(&self, operation: &Operation, arguments: &Map<String, Value>) -> ToolResult {
+        let mut url = Url::parse(&self.base_url).map_err(anyhow::Error::new)?;
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| anyhow!("API base URL {} can't have a path", self.base_url))?;
+            segments.pop_if_empty();
+            for segment in operation.path.split('/').filter(|s| !s.is_empty()) {
+                let mut segment = segment.to_string();
+                for parameter in &operation.parameters {
+                    let placeholder = format!("{{{}}}", parameter.name);
+                    if parameter.location != ParameterLocation::Path
+                        || !segment.contains(&placeholder)
+                    {
+                        continue;
+                    }
+                    let value = arguments.get(&parameter.name).ok_or_else(|| {
+                        ToolError::LLMError(format!(
+                            "Missing required path parameter '{}'",
+                            parameter.name
+                        ))
+                    })?;
+                    segment = segment.replace(&placeholder, &parameter_value(value));
+                }
+                segments.push(&segment);
+            }
+        }
+        Ok(url.to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl ToolBox for OpenApiToolBox {
+    fn tools_definitions(&self) -> Result<Vec<Tool>, ToolError> {
+        Ok(self
+            .operations
+            .iter()
+            .map(|operation| operation.tool.clone())
+            .collect())
+    }
+
+    async fn call_tool(&self, tool_name: String, arguments: Value) -> ToolResult {
+        let operation = self
+            .operations
+            .iter()
+            .find(|operation| operation.tool.name == tool_name)
+            .ok_or(ToolError::NoToolFound(tool_name))?;
+        let arguments = match arguments {
+            Value::Object(arguments) => arguments,
+            Value::Null => Map::new(),
+            _ => {
+                return Err(ToolError::LLMError(
+                    "Tool arguments must be a JSON object".to_string(),
+                ))
+            }
+        };
+
+        let url = self.operation_url(operation, &arguments)?;
+        let mut request = self.client.request(operation.method.clone(), url);
+        for parameter in &operation.parameters {
+            let Some(value) = arguments.get(&parameter.name).filter(|v| !v.is_null()) else {
+                continue;
+            };
+            request = match parameter.location {
+                ParameterLocation::Path => request,
+                ParameterLocation::Query => {
+                    request.query(&[(&parameter.name, parameter_value(value))])
+                }
+                ParameterLocation::Header => {
+                    request.header(&parameter.name, parameter_value(value))
+                }
+            };
+        }
+        if let Some(body) = arguments.get(BODY_PARAMETER) {
+            request = request.json(body);
+        }
+        request = match &self.auth {
+            OpenApiAuth::None => request,
+            OpenApiAuth::Bearer(token) => request.bearer_auth(token),
+            OpenApiAuth::ApiKeyHeader { name, value } => request.header(name, value),
+            OpenApiAuth::ApiKeyQuery { name, value } => request.query(&[(name, value)]),
+        };
+
+        let response = request.send().await.map_err(anyhow::Error::new)?;
+        let status = response.status();
+        let body = response.text().await.map_err(anyhow::Error::new)?;
+        if !status.is_success() {
+            // Model may be able to fix its request, e.g. when a parameter has invalid value
+            return Err(ToolError::LLMError(format!(
+                "Request failed with status {status}: {body}"
+            )));
+        }
+        Ok(body)
+    }
+
+    fn has_side_effects(&self, tool_name: &str) -> bool {
+        self.operations
+            .iter()
+            .find(|operation| operation.tool.name == tool_name)
+            .is_none_or(|operation| {
+                ![Method::GET, Method::HEAD, Method::OPTIONS].contains(&operation.method)
+            })
+    }
+
+    /// Operations using `POST` and `PATCH` methods are not idempotent, following HTTP semantics.
+    fn is_idempotent(&self, tool_name: &str) -> bool {
+        self.operations
+            .iter()
+            .find(|operation| operation.tool.name == tool_name)
+            .is_none_or(|operation| ![Method::POST, Method::PATCH].contains(&operation.method))
+    }
+}
+
+/// Converts argument value into text used in URL or header
+fn parameter_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Parses all operations defined in the specification
+fn parse_operations(spec: &Value) -> anyhow::Result<Vec<Operation>> {
+    let paths = spec["paths"]
+        .as_object()
+        .context("OpenAPI specification has no paths")?;
+    let mut operations: Vec<Operation> = vec![];
+    for (path, item) in paths {
+        let item = resolve_refs(item, spec, 0);
+        let shared_parameters = item["parameters"].as_array().cloned().unwrap_or_default();
+        for method in METHODS {
+            let Some(operation) = item.get(method) else {
+                continue;
+            };
+            let operation = parse_operation(spec, path, method, operation, &shared_parameters)?;
+            if operations
+                .iter()
+                .any(|other| other.tool.name == operation.tool.name)
+            {
+                return Err(anyhow!(
+                    "Duplicated OpenAPI operation {}",
+                    operation.tool.name
+                ));
+            }
+            operations.push(operation);
+        }
+    }
+    Ok(operations)
+}
+
+fn parse_operation(
+    spec: &Value,
+    path: &str,
+    method: &str,
+    operation: &Value,
+    shared_parameters: &[Value],
+) -> anyhow::Result<Operation> {
+    let name = tool_name(
+        operation["operationId"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{method}_{path}"))
+            .as_str(),
+    );
+
+    let mut properties = Map::new();
+    let mut required = vec![];
+    let mut parameters: Vec<Parameter> = vec![];
+    // Operation parameters override parameters shared by all operations of the path
+    let operation_parameters = operation["parameters"].as_array().cloned();
+    for parameter in shared_parameters
+        .iter()
+        .chain(operation_parameters.iter().flatten())
+    {
+        let parameter = resolve_refs(parameter, spec, 0);
+        let Some(parameter_name) = parameter["name"].as_str() else {
+            continue;
+        };
+        let location = match parameter["in"].as_str() {
+            Some("path") => ParameterLocation::Path,
+            Some("query") => ParameterLocation::Query,
+            Some("header") => ParameterLocation::Header,
+            Some("body") => {
+                // Swagger 2 describes request body as a parameter
+                add_body(&mut properties, &mut required, &parameter);
+                continue;
+            }
+            location => {
+                debug!("OpenAPI parameter {parameter_name} in {location:?} is not supported");
+                continue;
+            }
+        };
+
+        let mut schema = match parameter.get("schema") {
+            Some(schema) => schema.clone(),
+            // Swagger 2 keeps schema keywords directly in the parameter
+            None => swagger_parameter_schema(&parameter),
+        };
+        if let (Some(description), Some(schema)) =
+            (parameter["description"].as_str(), schema.as_object_mut())
+        {
+            schema.insert("description".to_string(), json!(description));
+        }
+        properties.insert(parameter_name.to_string(), schema);
+        required.retain(|name| name != parameter_name);
+        if location == ParameterLocation::Path || parameter["required"] == true {
+            required.push(parameter_name.to_string());
+        }
+        parameters.retain(|other| other.name != parameter_name);
+        parameters.push(Parameter {
+            name: parameter_name.to_string(),
+            location,
+        });
+    }
+
+    let request_body = resolve_refs(&operation["requestBody"], spec, 0);
+    if let Some(schema) = request_body["content"]["application/json"].get("schema") {
+        let mut body = json!({"schema": schema, "required": request_body["required"]});
+        if let Some(description) = request_body["description"].as_str() {
+            body["description"] = json!(description);
+        }
+        add_body(&mut properties, &mut required, &body);
+    }
+
+    let description = [&operation["summary"], &operation["description"]]
+        .iter()
+        .filter_map(|text| text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let schema = resolve_refs(
+        &json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        }),
+        spec,
+        0,
+    );
+    let mut tool = Tool::new(&name).with_schema(schema);
+    if !description.is_empty() {
+        tool = tool.with_description(description);
+    }
+
+    Ok(Operation {
+        tool,
+        method: Method::from_bytes(method.to_ascii_uppercase().as_bytes())?,
+        path: path.to_string(),
+        parameters,
+    })
+}
+
+/// Adds request body described by `body` (with `schema`, `required` and `description`) to tool
+/// parameters
+fn add_body(properties: &mut Map<String, Value>, required: &mut Vec<String>, body: &Value) {
+    let mut schema = body["schema"].clone();
+    if let (Some(description), Some(schema)) =
+        (body["description"].as_str(), schema.as_object_mut())
+    {
+        schema.insert("description".to_string(), json!(description));
+    }
+    properties.insert(BODY_PARAMETER.to_string(), schema);
+    if body["required"] == true {
+        required.push(BODY_PARAMETER.to_string());
+    }
+}
+
+/// Creates schema of a Swagger 2 parameter, which keeps schema keywords next to its name
+fn swagger_parameter_schema(parameter: &Value) -> Value {
+    let schema = [
+        "type", "format", "items", "enum", "default", "minimum", "maximum",
+    ]
+    .into_iter()
+    .filter_map(|key| Some((key.to_string(), parameter.get(key)?.clone())))
+    .collect::<Map<_, _>>();
+    Value::Object(schema)
+}
+
+/// Replaces local `$ref` references (e.g. `#/components/schemas/Pet`) with referenced values
+fn resolve_refs(value: &Value, spec: &Value, depth: usize) -> Value {
+    match value {
+        Value::Object(object) => {
+            if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+                return match reference.strip_prefix('#') {
+                    Some(pointer) if depth < MAX_REF_DEPTH => spec
+                        .pointer(pointer)
+                        .map(|target| resolve_refs(target, spec, depth + 1))
+                        .unwrap_or_else(|| json!({})),
+                    // Recursive or external references are replaced with any value schema
+                    _ => json!({}),
+                };
+            }
+            Value::Object(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.clone(), resolve_refs(value, spec, depth)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| resolve_refs(item, spec, depth))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Converts operation id into a valid tool name
+fn tool_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    // Separators replaced next to each other, e.g. in `get_/pets`, are collapsed into one
+    let name = name
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+    name.chars().take(MAX_TOOL_NAME_LEN).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const SPEC: &str = r##"{
+        "openapi": "3.0.0",
+        "paths": {
+            "/pets/{petId}": {
+                "parameters": [{"name": "petId", "in": "path", "schema": {"type": "integer"}}],
+                "get": {
+                    "operationId": "getPet",
+                    "summary": "Returns a pet",
+                    "parameters": [{"name": "verbose", "in": "query", "schema": {"type": "boolean"}}]
+                }
+            },
+            "/pets": {
+                "post": {
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Pet"}}}
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": {
+                "Pet": {"type": "object", "properties": {"name": {"type": "string"}}}
+            }
+        }
+    }"##;
+
+    #[test]
+    fn test_parse_operations() {
+        let toolbox =
+            OpenApiToolBox::from_spec(SPEC, "http://localhost/v1", OpenApiAuth::None).unwrap();
+        let tools = toolbox.tools_definitions().unwrap();
+        let names: Vec<_> = tools.iter().map(|tool| tool.name.as_str()).collect();
+        assert_eq!(names, ["post_pets", "getPet"]);
+
+        assert_eq!(
+            tools[0].schema,
+            Some(json!({
+                "type": "object",
+                "properties": {
+                    "body": {"type": "object", "properties": {"name": {"type": "string"}}}
+                },
+                "required": ["body"]
+            }))
+        );
+        assert_eq!(tools[1].description.as_deref(), Some("Returns a pet"));
+        assert_eq!(
+            tools[1].schema,
+            Some(json!({
+                "type": "object",
+                "properties": {
+                    "petId": {"type": "integer"},
+                    "verbose": {"type": "boolean"}
+                },
+                "required": ["petId"]
+            }))
+        );
+        assert!(!toolbox.has_side_effects("getPet"));
+        assert!(toolbox.has_side_effects("post_pets"));
+    }
+
+    #[tokio::test]
+    async fn test_call_operation() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            // Respond with the request line and authorization header
+            let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+            let request_line = request.lines().next().unwrap().to_string();
+            let auth = request
+                .lines()
+                .find(|line| line.starts_with("authorization:"))
+                .unwrap_or_default();
+            let body = format!("{request_line}\n{auth}");
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let toolbox =
+            OpenApiToolBox::from_spec(SPEC, &base_url, OpenApiAuth::Bearer("token".to_string()))
+                .unwrap();
+        let result = toolbox
+            .call_tool("getPet".to_string(), json!({"petId": 7, "verbose": true}))
+            .await
+            .unwrap();
+        assert_eq!(
+            result,
+            "get /v1/pets/7?verbose=true http/1.1\nauthorization: bearer token"
+        );
+
+        let err = toolbox
+            .call_tool("getPet".to_string(), json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Missing required path parameter 'petId'");
+    }
+}
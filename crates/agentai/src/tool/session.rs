@@ -0,0 +1,61 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+tokio::task_local! {
+    static SESSION_VARS: Arc<HashMap<String, Value>>;
+}
+
+/// Returns value of a session variable set with
+/// [`Agent::set_session_var`](crate::Agent::set_session_var).
+///
+/// Session variables carry values controlled by the application, not by the model, e.g. id of
+/// the authenticated user, locale or permissions. They are available to every tool called by
+/// the agent, without passing them as tool arguments. Returns `None` if the variable is not set,
+/// or when called outside of a tool call made by the agent, including tasks spawned by the tool.
+///
+/// ```rust
+/// use agentai::tool::{session_var, toolbox, Tool, ToolBox, ToolError, ToolResult};
+///
+/// struct OrdersToolBox;
+///
+/// #[toolbox]
+/// impl OrdersToolBox {
+///     /// Lists orders of the current user
+///     #[tool]
+///     fn list_orders(&self) -> ToolResult {
+///         let user_id = session_var("user_id")
+///             .ok_or_else(|| ToolError::LLMError("User is not logged in".to_string()))?;
+///         Ok(format!("No orders of user {user_id}"))
+///     }
+/// }
+/// ```
+pub fn session_var(key: &str) -> Option<Value> {
+    SESSION_VARS
+        .try_with(|vars| vars.get(key).cloned())
+        .ok()
+        .flatten()
+}
+
+/// Runs the tool call future with session variables available through [`session_var`]
+pub(crate) async fn with_session_vars<F: Future>(
+    vars: Arc<HashMap<String, Value>>,
+    future: F,
+) -> F::Output {
+    SESSION_VARS.scope(vars, future).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_session_var() {
+        assert_eq!(session_var("user_id"), None);
+        let vars = Arc::new(HashMap::from([("user_id".to_string(), json!(42))]));
+        let value = with_session_vars(vars, async { session_var("user_id") }).await;
+        assert_eq!(value, Some(json!(42)));
+    }
+}
@@ -0,0 +1,309 @@
+//! # SQL Database Tools
+//!
+//! This module provides [`SqlToolBox`], which lets an agent query a SQL database. Only read-only
+//! `SELECT` statements are allowed, and results are returned as JSON rows capped by a row limit.
+//!
+//! Databases are accessed through [`sqlx::AnyPool`], so every database supported by `sqlx`
+//! drivers enabled in your crate can be used. This crate enables the SQLite driver, other
+//! drivers, e.g. `postgres`, are enabled by adding `sqlx` dependency with the driver feature.
+//!
+//! ```rust,no_run
+//! # use agentai::Agent;
+//! # use agentai::tool::sql::SqlToolBox;
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let toolbox = SqlToolBox::connect("sqlite://sales.db").await?.with_max_rows(50);
+//! let mut agent = Agent::new("You are a data analyst, answer questions using the database");
+//! let answer: String = agent
+//!     .run("gpt-4o", "Which product sold best last month?", Some(&toolbox))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::tool::{toolbox, Tool, ToolBox, ToolError, ToolResult};
+use futures::{StreamExt, TryStreamExt};
+use serde_json::{json, Map, Value};
+use sqlx::any::{AnyPoolOptions, AnyRow, AnyTypeInfoKind};
+use sqlx::{AnyPool, Column, Row, ValueRef};
+
+const DEFAULT_MAX_ROWS: usize = 100;
+
+/// Keywords of statements modifying the database or its settings
+const FORBIDDEN_KEYWORDS: &[&str] = &[
+    "ALTER", "ATTACH", "CALL", "COPY", "CREATE", "DELETE", "DETACH", "DROP", "EXEC", "EXECUTE",
+    "GRANT", "INSERT", "INTO", "LOCK", "MERGE", "PRAGMA", "REINDEX", "RENAME", "REPLACE", "REVOKE",
+    "SET", "TRUNCATE", "UPDATE", "UPSERT", "VACUUM",
+];
+
+/// # SQL Query Tool
+///
+/// Toolbox exposing a `query` tool, which runs read-only SQL queries and returns rows as JSON.
+///
+/// Statements are checked before execution: only a single `SELECT` statement (optionally
+/// starting with `WITH`) is allowed, and statements containing keywords which modify data,
+/// schema or settings are rejected. Queries run in a transaction which is always rolled back.
+/// These checks are a safety net, for full protection connect with a database user having
+/// read-only permissions.
+///
+/// The model can pass values as query parameters instead of embedding them in SQL, using
+/// placeholders of the database (`?` for SQLite and MySQL, `$1` for PostgreSQL).
+pub struct SqlToolBox {
+    pool: AnyPool,
+    max_rows: usize,
+}
+
+#[toolbox]
+impl SqlToolBox {
+    /// Creates a toolbox using provided connection pool.
+    pub fn new(pool: AnyPool) -> Self {
+        Self {
+            pool,
+            max_rows: DEFAULT_MAX_ROWS,
+        }
+    }
+
+    /// Connects to the database at `url`, e.g. `sqlite://data.db` or `postgres://localhost/db`.
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new().connect(url).await?;
+        Ok(Self::new(pool))
+    }
+
+    /// Sets maximum number of rows returned by a query, 100 by default. Results with more rows
+    /// are truncated and marked as such.
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = max_rows;
+        self
+    }
+
+    /// Runs a read-only SQL SELECT query against the database and returns matching rows as JSON.
+    /// Only a single SELECT statement is allowed. Pass values as `params` using placeholders of
+    /// the database instead of embedding them in the query. Number of returned rows is limited,
+    /// `truncated` is set when more rows match, use aggregation or filters to narrow results.
+    #[tool(readonly)]
+    pub async fn query(
+        &self,
+        #[doc = "SQL SELECT statement to run."] sql: String,
+        #[doc = "Values of query placeholders, in order of placeholders."] params: Option<
+            Vec<Value>,
+        >,
+    ) -> ToolResult {
+        let sql = check_read_only(&sql).map_err(ToolError::LLMError)?;
+
+        let mut query = sqlx::query(sql);
+        for param in params.unwrap_or_default() {
+            query = match param {
+                Value::Null => query.bind(None::<String>),
+                Value::Bool(value) => query.bind(value),
+                Value::Number(number) => match number.as_i64() {
+                    Some(value) => query.bind(value),
+                    None => query.bind(number.as_f64()),
+                },
+                Value::String(value) => query.bind(value),
+                value => query.bind(value.to_string()),
+            };
+        }
+
+        let mut tx = self.pool.begin().await.map_err(anyhow::Error::new)?;
+        let rows: Vec<AnyRow> = query
+            .fetch(&mut *tx)
+            .take(self.max_rows + 1)
+            .try_collect()
+            .await
+            .map_err(|err| ToolError::LLMError(format!("Query failed: {err}")))?;
+        tx.rollback().await.map_err(anyhow::Error::new)?;
+
+        let truncated = rows.len() > self.max_rows;
+        let rows = rows
+            .iter()
+            .take(self.max_rows)
+            .map(row_to_json)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(anyhow::Error::new)?;
+        Ok(json!({"rows": rows, "truncated": truncated}).to_string())
+    }
+}
+
+/// Checks that `sql` is a single read-only statement, returns it without trailing semicolons
+fn check_read_only(sql: &str) -> Result<&str, String> {
+    let sql = sql.trim().trim_end_matches([';', ' ', '\n', '\r', '\t']);
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c.to_ascii_uppercase());
+            continue;
+        }
+        if !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        match c {
+            // String literals and quoted identifiers may contain any text
+            '\'' | '"' | '`' => {
+                while chars.next_if(|next| *next != c).is_some() {}
+                chars.next();
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                while chars.next_if(|next| *next != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == '*' && chars.next_if_eq(&'/').is_some() {
+                        break;
+                    }
+                }
+            }
+            ';' => return Err("Only a single SQL statement is allowed".to_string()),
+            _ => {}
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    if !matches!(words.first().map(String::as_str), Some("SELECT" | "WITH")) {
+        return Err("Only SELECT statements are allowed".to_string());
+    }
+    if let Some(keyword) = words
+        .iter()
+        .find(|word| FORBIDDEN_KEYWORDS.contains(&word.as_str()))
+    {
+        return Err(format!(
+            "Statement contains {keyword}, only read-only SELECT statements are allowed"
+        ));
+    }
+    Ok(sql)
+}
+
+/// Converts a row into a JSON object keyed by column names
+fn row_to_json(row: &AnyRow) -> Result<Value, sqlx::Error> {
+    let mut object = Map::new();
+    for column in row.columns() {
+        let index = column.ordinal();
+        let raw = row.try_get_raw(index)?;
+        let value = if raw.is_null() {
+            Value::Null
+        } else {
+            match raw.type_info().kind() {
+                AnyTypeInfoKind::Null => Value::Null,
+                AnyTypeInfoKind::Bool => Value::Bool(row.try_get(index)?),
+                AnyTypeInfoKind::SmallInt | AnyTypeInfoKind::Integer | AnyTypeInfoKind::BigInt => {
+                    Value::from(row.try_get::<i64, _>(index)?)
+                }
+                AnyTypeInfoKind::Real => Value::from(row.try_get::<f32, _>(index)? as f64),
+                AnyTypeInfoKind::Double => Value::from(row.try_get::<f64, _>(index)?),
+                AnyTypeInfoKind::Text => Value::String(row.try_get(index)?),
+                AnyTypeInfoKind::Blob => {
+                    let blob: Vec<u8> = row.try_get(index)?;
+                    Value::String(format!("<binary data, {} bytes>", blob.len()))
+                }
+            }
+        };
+        object.insert(column.name().to_string(), value);
+    }
+    Ok(Value::Object(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_toolbox() -> SqlToolBox {
+        sqlx::any::install_default_drivers();
+        // Every connection to in-memory SQLite database has its own database
+        let pool = AnyPoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::raw_sql(
+            "CREATE TABLE products (id INTEGER, name TEXT, price REAL, image BLOB);
+             INSERT INTO products VALUES (1, 'Tea', 4.5, x'00ff'), (2, 'Coffee', 7.25, NULL),
+                 (3, 'Milk', 2.0, NULL);",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        SqlToolBox::new(pool)
+    }
+
+    #[test]
+    fn test_check_read_only() {
+        assert_eq!(
+            check_read_only("SELECT * FROM t; \n"),
+            Ok("SELECT * FROM t")
+        );
+        assert!(check_read_only("with recent as (select 1) select * from recent").is_ok());
+        // Keywords inside literals and comments are ignored
+        assert!(check_read_only("SELECT 'DROP TABLE t; --' AS text -- DELETE\n").is_ok());
+        assert!(check_read_only("SELECT \"update\" FROM t /* insert */").is_ok());
+
+        assert!(check_read_only("DELETE FROM t").is_err());
+        assert!(check_read_only("SELECT 1; DROP TABLE t").is_err());
+        assert!(check_read_only("SELECT * INTO backup FROM t").is_err());
+        assert!(
+            check_read_only("WITH gone AS (DELETE FROM t RETURNING *) SELECT * FROM gone").is_err()
+        );
+        assert!(check_read_only("PRAGMA table_info(t)").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let toolbox = test_toolbox().await.with_max_rows(2);
+        let result = toolbox
+            .call_tool(
+                "query".to_string(),
+                json!({"sql": "SELECT id, name, price, image FROM products ORDER BY id"}),
+            )
+            .await
+            .unwrap();
+        let result: Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(
+            result,
+            json!({
+                "rows": [
+                    {"id": 1, "name": "Tea", "price": 4.5, "image": "<binary data, 2 bytes>"},
+                    {"id": 2, "name": "Coffee", "price": 7.25, "image": null},
+                ],
+                "truncated": true
+            })
+        );
+
+        let result = toolbox
+            .call_tool(
+                "query".to_string(),
+                json!({"sql": "SELECT name FROM products WHERE price < ?", "params": [3]}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result, r#"{"rows":[{"name":"Milk"}],"truncated":false}"#);
+    }
+
+    #[tokio::test]
+    async fn test_query_rejected() {
+        let toolbox = test_toolbox().await;
+        let err = toolbox
+            .query("DELETE FROM products".to_string(), None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, ToolError::LLMError(message) if message == "Only SELECT statements are allowed")
+        );
+
+        let err = toolbox
+            .query("SELECT * FROM missing".to_string(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ToolError::LLMError(message) if message.starts_with("Query failed")));
+
+        // Rejected statements do not modify the database
+        let result = toolbox
+            .query("SELECT COUNT(*) AS n FROM products".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(result, r#"{"rows":[{"n":3}],"truncated":false}"#);
+    }
+}
@@ -4,16 +4,43 @@
 //! It includes functionalities such as performing web searches and fetching content from URLs.
 //! These tools empower an AI agent to access and process information from the internet.
 //!
+//! [`SummarizeUrlToolBox`] combines fetching with summarization done by a separate model call,
+//! so large page content never enters the main agent context.
+//!
 //! For a practical demonstration of these tools, please refer to the example located at
 //! [examples/tools_web.rs](crate::examples::tools_web).
 
-use crate::tool::{toolbox, Tool, ToolBox, ToolError, ToolResult};
+use crate::tool::{
+    default_http_timeout, http_client, toolbox, Tool, ToolBox, ToolError, ToolResult,
+};
 use anyhow::Context;
-use reqwest::Client;
+use futures::future::join_all;
+use genai::chat::{ChatMessage, ChatRequest};
+use log::warn;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Response};
 use serde_json::Value;
 
 const BRAVE_API_URL: &str = "https://api.search.brave.com/res/v1/web/search";
 
+/// Maximum number of results returned by Brave Search API in a single request
+const BRAVE_MAX_COUNT: usize = 20;
+
+/// Number of top search results with page content, see [`WebSearchToolBox::with_fetch_content`]
+const FETCHED_RESULTS: usize = 3;
+
+/// Maximum number of characters of page text included in a search result
+const MAX_RESULT_CONTENT_CHARS: usize = 5_000;
+
+/// Default maximum size of a fetched page, see [`WebFetchToolBox::with_max_bytes`]
+pub const DEFAULT_MAX_FETCH_BYTES: usize = 1024 * 1024;
+
+/// Maximum number of characters of page text sent for summarization
+const MAX_SUMMARY_INPUT_CHARS: usize = 50_000;
+
+const SUMMARIZE_SYSTEM: &str = "You summarize web pages. Write a short summary of the page \
+content provided by the user, keeping the most important facts. Respond only with the summary.";
+
 /// # Brave Web Search Tool
 ///
 /// This is a simple implementation of [crate::tool::ToolBox] for Web Search using Brave Search engine.
@@ -29,6 +56,39 @@ const BRAVE_API_URL: &str = "https://api.search.brave.com/res/v1/web/search";
 pub struct WebSearchToolBox {
     client: Client,
     api_key: String,
+    fetch_content: bool,
+    config: WebSearchConfig,
+}
+
+/// Parameters of search requests made by [`WebSearchToolBox`], see
+/// [`WebSearchToolBox::with_config`].
+///
+/// ```rust
+/// # use agentai::tool::web::{WebSearchConfig, WebSearchToolBox};
+/// let tool = WebSearchToolBox::new("<API KEY>").with_config(WebSearchConfig {
+///     count: 10,
+///     country: Some("PL".to_string()),
+///     search_lang: Some("pl".to_string()),
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebSearchConfig {
+    /// Number of returned results, 5 by default. Brave Search returns at most 20 results.
+    pub count: usize,
+    /// Country code of the region results come from, e.g. `US`, any region by default
+    pub country: Option<String>,
+    /// Language code of results, e.g. `en`, detected from the query by default
+    pub search_lang: Option<String>,
+}
+
+impl Default for WebSearchConfig {
+    fn default() -> Self {
+        Self {
+            count: 5,
+            country: None,
+            search_lang: None,
+        }
+    }
 }
 
 #[toolbox]
@@ -40,28 +100,73 @@ impl WebSearchToolBox {
     /// * `api_key` - A string slice that holds the API key for the Brave Search API.
     pub fn new(api_key: &str) -> Self {
         Self {
-            client: Client::default(),
+            client: http_client(default_http_timeout()),
             api_key: api_key.to_string(),
+            fetch_content: false,
+            config: WebSearchConfig::default(),
         }
     }
 
+    /// Sets parameters of search requests, like the number of results and their region.
+    ///
+    /// Number of results is limited to 1-20, supported by Brave Search API.
+    pub fn with_config(mut self, mut config: WebSearchConfig) -> Self {
+        if !(1..=BRAVE_MAX_COUNT).contains(&config.count) {
+            warn!(
+                "Brave Search supports 1-{BRAVE_MAX_COUNT} results, {} results were requested",
+                config.count
+            );
+            config.count = config.count.clamp(1, BRAVE_MAX_COUNT);
+        }
+        self.config = config;
+        self
+    }
+
+    /// Query parameters of the search request
+    fn search_params(&self, query: String) -> Vec<(&'static str, String)> {
+        let mut params = vec![
+            ("q", query),
+            ("count", self.config.count.to_string()),
+            ("result_filter", "web".to_string()),
+        ];
+        if let Some(country) = &self.config.country {
+            params.push(("country", country.clone()));
+        }
+        if let Some(search_lang) = &self.config.search_lang {
+            params.push(("search_lang", search_lang.clone()));
+        }
+        params
+    }
+
+    /// Includes text content of top search results in the tool result, disabled by default.
+    ///
+    /// Pages of the first few results are fetched concurrently, converted to Markdown and truncated,
+    /// so the model can read them without separate fetch calls. This saves turns of research
+    /// agents, at the cost of larger tool results.
+    pub fn with_fetch_content(mut self, fetch_content: bool) -> Self {
+        self.fetch_content = fetch_content;
+        self
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to share connection pool or to configure proxy.
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
     /// A tool that performs web searches using a specified query parameter to retrieve relevant
     /// results from a search engine. As the result you will receive list of websites with description.
     ///
     /// ## Example
     ///
     /// **User:** "What is the latest news about AI?"
-    #[tool]
+    #[tool(readonly)]
     pub async fn web_search(
         &self,
         #[doc = "The search terms or keywords to be used by the search engine for retrieving relevant results."]
         query: String,
     ) -> ToolResult {
-        let params = [
-            ("q", query.as_str()),
-            ("count", "5"),
-            ("result_filter", "web"),
-        ];
+        let params = self.search_params(query);
         let response = self
             .client
             .get(BRAVE_API_URL)
@@ -74,6 +179,7 @@ impl WebSearchToolBox {
         let json: Value = response.json().await.map_err(anyhow::Error::new)?;
 
         let mut results: Vec<String> = vec![];
+        let mut urls: Vec<&str> = vec![];
 
         let response = json["web"]["results"]
             .as_array()
@@ -89,16 +195,50 @@ impl WebSearchToolBox {
             results.push(format!(
                 "Title: {title}\nDescription: {description}\nURL: {url}"
             ));
+            urls.push(url);
+        }
+
+        if self.fetch_content {
+            let contents = join_all(
+                urls.iter()
+                    .take(FETCHED_RESULTS)
+                    .map(|url| fetch_text(&self.client, url, MAX_RESULT_CONTENT_CHARS)),
+            )
+            .await;
+            for (result, content) in results.iter_mut().zip(contents) {
+                result.push_str(&format!("\nContent: {content}"));
+            }
         }
 
         Ok(results.join("\n\n"))
     }
 }
 
+/// Form in which [`WebFetchToolBox`] returns fetched pages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FetchMode {
+    /// Body of the response without any changes
+    #[default]
+    Raw,
+    /// HTML converted to plain text, without scripts, styles and markup
+    Text,
+    /// HTML converted to markdown, keeping headings, links and lists
+    Markdown,
+}
+
 /// Provides a tool that enables an LLM to fetch the content of a web page.
 /// This is useful for accessing the raw text from a website to be used as context.
+///
+/// By default the raw HTML is returned, use [`FetchMode::Markdown`] to save context of the agent:
+///
+/// ```rust
+/// # use agentai::tool::web::{FetchMode, WebFetchToolBox};
+/// let toolbox = WebFetchToolBox::new().with_mode(FetchMode::Markdown);
+/// ```
 pub struct WebFetchToolBox {
     client: Client,
+    max_bytes: usize,
+    mode: FetchMode,
 }
 
 impl Default for WebFetchToolBox {
@@ -112,10 +252,34 @@ impl WebFetchToolBox {
     /// Creates a new instance of `WebFetchToolBox`.
     pub fn new() -> Self {
         Self {
-            client: Client::default(),
+            client: http_client(default_http_timeout()),
+            max_bytes: DEFAULT_MAX_FETCH_BYTES,
+            mode: FetchMode::default(),
         }
     }
 
+    /// Sets form in which fetched pages are returned, [`FetchMode::Raw`] by default.
+    pub fn with_mode(mut self, mode: FetchMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets maximum size of a fetched page, [`DEFAULT_MAX_FETCH_BYTES`] by default.
+    ///
+    /// Download of a larger page is stopped as soon as the limit is exceeded, and the model is
+    /// informed that the page is too large. Responses which are not text, e.g. images or
+    /// archives, are rejected regardless of their size.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to share connection pool or to configure proxy.
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
     #[allow(rustdoc::bare_urls)]
     /// Fetches the content of a web page given its URL. This tool is useful for accessing the
     /// raw text content of a webpage. The content is returned as a single string.
@@ -123,31 +287,489 @@ impl WebFetchToolBox {
     /// ## Example
     ///
     /// **User:** "Fetch me page at: https://github.com/AdamStrojek/rust-agentai/"
-    #[tool]
+    #[tool(readonly)]
     pub async fn web_fetch(
         &self,
         #[doc = "The full URL of the web page to fetch, including the protocol (e.g., https://)."]
         url: String,
     ) -> ToolResult {
-        let response = self
-            .client
-            .get(&url)
-            .send()
+        let body = fetch(&self.client, &url, self.max_bytes).await?;
+        Ok(match self.mode {
+            FetchMode::Raw => body,
+            FetchMode::Text => html_to_text(&body),
+            FetchMode::Markdown => html_to_markdown(&body),
+        })
+    }
+}
+
+/// Provides a tool that enables an LLM to get a short summary of a web page.
+///
+/// The page is fetched, converted to text and summarized by the configured model in a separate
+/// request. Only the summary is returned to the agent, keeping its context small.
+///
+/// ```rust
+/// # use agentai::tool::web::SummarizeUrlToolBox;
+/// let toolbox = SummarizeUrlToolBox::new(genai::Client::default(), "gpt-4o-mini");
+/// ```
+pub struct SummarizeUrlToolBox {
+    genai: genai::Client,
+    model: String,
+    client: Client,
+}
+
+#[toolbox]
+impl SummarizeUrlToolBox {
+    /// Creates a new instance of `SummarizeUrlToolBox`.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - GenAI client used for summarization requests.
+    /// * `model` - The model used to summarize pages, a small and cheap model is usually enough.
+    pub fn new(client: genai::Client, model: &str) -> Self {
+        Self {
+            genai: client,
+            model: model.to_string(),
+            client: http_client(default_http_timeout()),
+        }
+    }
+
+    /// Uses provided HTTP client for requests, e.g. to share connection pool or to configure proxy.
+    pub fn with_http_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    #[allow(rustdoc::bare_urls)]
+    /// Fetches a web page and returns a short summary of its content. Prefer it over fetching
+    /// the whole page when only the gist of the page is needed.
+    ///
+    /// ## Example
+    ///
+    /// **User:** "What is https://github.com/AdamStrojek/rust-agentai/ about?"
+    #[tool(readonly)]
+    pub async fn summarize_url(
+        &self,
+        #[doc = "The full URL of the web page to summarize, including the protocol (e.g., https://)."]
+        url: String,
+    ) -> ToolResult {
+        let body = fetch(&self.client, &url, DEFAULT_MAX_FETCH_BYTES).await?;
+        let text: String = html_to_text(&body)
+            .chars()
+            .take(MAX_SUMMARY_INPUT_CHARS)
+            .collect();
+
+        let chat_req = ChatRequest::new(vec![
+            ChatMessage::system(SUMMARIZE_SYSTEM),
+            ChatMessage::user(text),
+        ]);
+        let chat_resp = self
+            .genai
+            .exec_chat(&self.model, chat_req, None)
             .await
-            .map_err(|e| ToolError::LLMError(format!("Request to {url} failed: {e}")))?;
-
-        if !response.status().is_success() {
-            return Err(ToolError::LLMError(format!(
-                "Request to {} failed with status: {}",
-                url,
-                response.status()
-            )));
+            .map_err(anyhow::Error::new)?;
+
+        Ok(chat_resp
+            .content_text_into_string()
+            .context("summary response has no text")?)
+    }
+}
+
+/// Fetches body of a web page, request failures are reported to the LLM.
+///
+/// Responses which are not text or are larger than `max_bytes` are rejected without reading
+/// them whole.
+async fn fetch(client: &Client, url: &str, max_bytes: usize) -> ToolResult {
+    let response = request(client, url).await?;
+    let too_large = || {
+        ToolError::LLMError(format!(
+            "Page {url} is too large, it exceeds the limit of {max_bytes} bytes"
+        ))
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes as u64)
+    {
+        return Err(too_large());
+    }
+
+    match read_body(response, max_bytes).await? {
+        (_, true) => Err(too_large()),
+        (body, false) => Ok(body),
+    }
+}
+
+/// Sends a request for a web page, responses with error status or not being text are rejected
+async fn request(client: &Client, url: &str) -> Result<Response, ToolError> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| ToolError::LLMError(format!("Request to {url} failed: {e}")))?;
+
+    if !response.status().is_success() {
+        return Err(ToolError::LLMError(format!(
+            "Request to {} failed with status: {}",
+            url,
+            response.status()
+        )));
+    }
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !is_text_content(content_type) {
+        return Err(ToolError::LLMError(format!(
+            "Content of {url} is not text ({content_type}), it can't be read"
+        )));
+    }
+    Ok(response)
+}
+
+/// Reads at most `max_bytes` of the response body, returns it with `true` if the rest of the
+/// body was not read
+async fn read_body(mut response: Response, max_bytes: usize) -> Result<(String, bool), ToolError> {
+    let mut body = vec![];
+    let mut truncated = false;
+    while let Some(chunk) = response.chunk().await.map_err(anyhow::Error::new)? {
+        if body.len() + chunk.len() > max_bytes {
+            body.extend_from_slice(&chunk[..max_bytes - body.len()]);
+            truncated = true;
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok((String::from_utf8_lossy(&body).into_owned(), truncated))
+}
+
+/// Checks if a response with given `Content-Type` can be read as text, responses without the
+/// type are assumed to be text
+fn is_text_content(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.is_empty()
+        || mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || [
+            "application/json",
+            "application/xml",
+            "application/javascript",
+            "application/x-yaml",
+        ]
+        .contains(&mime.as_str())
+}
+
+/// Fetches a web page as Markdown truncated to `max_chars`, failures are described in the text.
+///
+/// Pages larger than [`DEFAULT_MAX_FETCH_BYTES`] are truncated too, only their beginning is read.
+async fn fetch_text(client: &Client, url: &str, max_chars: usize) -> String {
+    let page = match request(client, url).await {
+        Ok(response) => read_body(response, DEFAULT_MAX_FETCH_BYTES).await,
+        Err(err) => Err(err),
+    };
+    match page {
+        Ok((body, truncated)) => {
+            let text = html_to_markdown(&body);
+            match text.char_indices().nth(max_chars) {
+                Some((end, _)) => format!("{}... [truncated]", &text[..end]),
+                None if truncated => format!("{text}... [truncated]"),
+                None => text,
+            }
         }
+        Err(err) => format!("not available, {err}"),
+    }
+}
 
-        let body = response.text().await.map_err(anyhow::Error::new)?;
+/// Converts HTML into plain text.
+///
+/// Removes tags together with content of `script` and `style` elements, decodes common entities
+/// and collapses whitespace. Plain text input is returned with collapsed whitespace.
+fn html_to_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        text.push(' ');
+        rest = &rest[start..];
+        let tag_end = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let tag = rest[..tag_end].to_ascii_lowercase();
+        rest = &rest[tag_end..];
+        // Skip content of elements that are not visible
+        for element in ["script", "style"] {
+            if tag.starts_with(&format!("<{element}")) {
+                let closing = format!("</{element}");
+                let end = rest
+                    .to_ascii_lowercase()
+                    .find(&closing)
+                    .unwrap_or(rest.len());
+                rest = &rest[end..];
+            }
+        }
+    }
+    text.push_str(rest);
 
-        // TODO: Add HTML2MD converter
+    decode_entities(&text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
-        Ok(body)
+/// Converts HTML into markdown.
+///
+/// Keeps headings, links, paragraphs and list items, other markup is removed like in
+/// [`html_to_text`]. Links without a target or pointing to scripts are replaced by their text.
+fn html_to_markdown(html: &str) -> String {
+    let mut markdown = String::with_capacity(html.len());
+    // Targets of currently open links, `None` for links which are kept as text
+    let mut links: Vec<Option<String>> = vec![];
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        markdown.push_str(
+            &rest[..start]
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        if rest[..start].ends_with(char::is_whitespace) {
+            markdown.push(' ');
+        }
+        rest = &rest[start..];
+        let tag_end = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let tag = &rest[..tag_end];
+        rest = &rest[tag_end..];
+
+        let lowercase = tag.to_ascii_lowercase();
+        let closing = lowercase.starts_with("</");
+        let name: String = lowercase
+            .trim_start_matches(['<', '/'])
+            .chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect();
+        match name.as_str() {
+            "script" | "style" | "noscript" | "template" | "svg" | "head" if !closing => {
+                let end = rest
+                    .to_ascii_lowercase()
+                    .find(&format!("</{name}"))
+                    .unwrap_or(rest.len());
+                rest = &rest[end..];
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                markdown.push_str("\n\n");
+                if !closing {
+                    let level = name[1..].parse().unwrap_or(1);
+                    markdown.push_str(&"#".repeat(level));
+                    markdown.push(' ');
+                }
+            }
+            "li" if !closing => markdown.push_str("\n- "),
+            "br" | "tr" => markdown.push('\n'),
+            "p" | "div" | "section" | "article" | "header" | "footer" | "main" | "nav" | "ul"
+            | "ol" | "table" | "blockquote" | "pre" => markdown.push_str("\n\n"),
+            "a" if !closing => {
+                let href = attribute(tag, "href")
+                    .filter(|href| !href.starts_with('#') && !href.starts_with("javascript:"));
+                if href.is_some() {
+                    markdown.push('[');
+                }
+                links.push(href);
+            }
+            "a" => {
+                if let Some(Some(href)) = links.pop() {
+                    markdown.push_str(&format!("]({href})"));
+                }
+            }
+            _ => markdown.push(' '),
+        }
+    }
+    markdown.push_str(rest);
+
+    // Collapse spaces in lines and blank lines left by nested block elements
+    let markdown = decode_entities(&markdown);
+    let mut lines: Vec<String> = vec![];
+    for line in markdown.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !line.is_empty() || lines.last().is_some_and(|last| !last.is_empty()) {
+            lines.push(line);
+        }
+    }
+    lines
+        .join("\n")
+        .trim()
+        .replace("[ ", "[")
+        .replace(" ](", "](")
+}
+
+/// Returns value of an attribute of an HTML tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.to_ascii_lowercase().find(&format!(" {name}="))? + name.len() + 2;
+    let value = &tag[start..];
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value
+            .split(|c: char| c.is_whitespace() || c == '>')
+            .next()?,
+    };
+    Some(value.to_string()).filter(|value| !value.is_empty())
+}
+
+/// Decodes common HTML entities
+fn decode_entities(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_params() {
+        let tool = WebSearchToolBox::new("key");
+        assert_eq!(
+            tool.search_params("rust".to_string()),
+            [
+                ("q", "rust".to_string()),
+                ("count", "5".to_string()),
+                ("result_filter", "web".to_string())
+            ]
+        );
+
+        let tool = tool.with_config(WebSearchConfig {
+            count: 50,
+            country: Some("PL".to_string()),
+            search_lang: Some("pl".to_string()),
+        });
+        let params = tool.search_params("rust".to_string());
+        assert!(params.contains(&("count", "20".to_string())));
+        assert!(params.contains(&("country", "PL".to_string())));
+        assert!(params.contains(&("search_lang", "pl".to_string())));
+    }
+
+    /// Starts a server responding to every request with given content type and body
+    async fn serve(content_type: &'static str, body: String) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nConnection: close\r\n\r\n{body}"
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_web_fetch_limits() {
+        let tool = WebFetchToolBox::new().with_max_bytes(100);
+
+        let url = serve("text/html; charset=utf-8", "<p>Small page</p>".to_string()).await;
+        assert_eq!(tool.web_fetch(url).await.unwrap(), "<p>Small page</p>");
+
+        // Without Content-Length the body is read until the limit is exceeded
+        let url = serve("text/plain", "a".repeat(1000)).await;
+        let err = tool.web_fetch(url.clone()).await.unwrap_err();
+        assert!(matches!(err, ToolError::LLMError(_)));
+        assert_eq!(
+            err.to_string(),
+            format!("Page {url} is too large, it exceeds the limit of 100 bytes")
+        );
+
+        let url = serve("application/zip", "PK".to_string()).await;
+        let err = tool.web_fetch(url.clone()).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Content of {url} is not text (application/zip), it can't be read")
+        );
+    }
+
+    #[test]
+    fn test_is_text_content() {
+        assert!(is_text_content(""));
+        assert!(is_text_content("text/html; charset=UTF-8"));
+        assert!(is_text_content("application/json"));
+        assert!(is_text_content("application/rss+xml"));
+        assert!(!is_text_content("image/png"));
+        assert!(!is_text_content("application/octet-stream"));
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        let html = r##"<html><head><title>Title</title><style>p { color: red; }</style>
+            <SCRIPT>alert("<b>")</SCRIPT></head>
+            <body><p>Fish &amp; chips</p><p>cost&nbsp;5</p></body></html>"##;
+        assert_eq!(html_to_text(html), "Title Fish & chips cost 5");
+        assert_eq!(html_to_text("plain\n\ntext"), "plain text");
+    }
+
+    #[test]
+    fn test_html_to_markdown() {
+        let html = r##"<html><head><title>Page</title><style>p{}</style></head><body>
+            <nav><a href="#main">Skip</a></nav>
+            <h1>Fish &amp; <em>chips</em></h1>
+            <p>Cost is 5,
+               see <a class="x" href='https://example.com/menu'> the menu </a>.</p>
+            <script>alert(1)</script>
+            <ul><li>Cod</li><li>Haddock</li></ul>
+        </body></html>"##;
+        assert_eq!(
+            html_to_markdown(html),
+            "Skip\n\n# Fish & chips\n\nCost is 5, see [the menu](https://example.com/menu).\n\n- Cod\n- Haddock"
+        );
+        assert_eq!(html_to_markdown("plain  text"), "plain text");
+    }
+
+    #[test]
+    fn test_attribute() {
+        assert_eq!(
+            attribute(r#"<a HREF="/a b" id=x>"#, "href"),
+            Some("/a b".to_string())
+        );
+        assert_eq!(attribute("<a id=x>", "id"), Some("x".to_string()));
+        assert_eq!(attribute("<a id=x>", "href"), None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_text() {
+        let client = Client::new();
+        let body = "<html><body><h1>Rust</h1><p>Rust is a language</p></body></html>";
+        let url = serve("text/html", body.to_string()).await;
+        assert_eq!(
+            fetch_text(&client, &url, 100).await,
+            "# Rust\n\nRust is a language"
+        );
+        assert_eq!(
+            fetch_text(&client, &url, 15).await,
+            "# Rust\n\nRust is... [truncated]"
+        );
+
+        // Pages over the size limit are truncated instead of rejected
+        let url = serve("text/plain", "a".repeat(DEFAULT_MAX_FETCH_BYTES + 10)).await;
+        let text = fetch_text(&client, &url, usize::MAX).await;
+        assert_eq!(
+            text.len(),
+            DEFAULT_MAX_FETCH_BYTES + "... [truncated]".len()
+        );
+        assert!(text.ends_with("a... [truncated]"));
+
+        let url = serve("image/png", "PNG".to_string()).await;
+        assert!(fetch_text(&client, &url, 100)
+            .await
+            .starts_with("not available, Content of"));
     }
 }
@@ -0,0 +1,216 @@
+//! # Conversation Transcript
+//!
+//! This module provides a provider-agnostic, serializable representation of the conversation
+//! held by an [`Agent`](crate::agent::Agent).
+//!
+//! Internally the agent stores its history as GenAI [`ChatMessage`]s, which are an
+//! implementation detail and may change between versions. [`Transcript`] decouples your
+//! storage or analysis format from these internal types.
+//!
+//! ```rust
+//! # use agentai::Agent;
+//! let agent = Agent::new("You are a useful assistant");
+//! let transcript = agent.transcript();
+//! let json = serde_json::to_string(&transcript).unwrap();
+//! ```
+
+use genai::chat::{ChatMessage, ChatRole, ContentPart, ImageSource, MessageContent, Usage};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, SystemTime};
+
+/// Full conversation exported from an agent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    /// Messages in the order they were added to the conversation
+    pub messages: Vec<Message>,
+}
+
+/// Author of a message in the transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+/// Single message in the transcript.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    /// Author of the message
+    pub role: Role,
+    /// Name of the participant, used to distinguish agents in multi-agent conversations,
+    /// see [`Agent::with_name`](crate::agent::Agent::with_name)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Text content of the message, empty for messages containing only tool calls or results
+    pub content: String,
+    /// Tool calls requested by the model in this message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<TranscriptToolCall>,
+    /// Results of tool calls provided in this message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_results: Vec<TranscriptToolResult>,
+    /// When the message was added to the conversation, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<SystemTime>,
+    /// Details of the model request which generated the message, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<Generation>,
+}
+
+/// Details of the model request which generated an assistant message.
+///
+/// Together with [`Message::timestamp`] it allows to analyze cost and latency of every turn
+/// of the conversation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Generation {
+    /// Model used for the request
+    pub model: String,
+    /// Number of tokens sent to the model, if reported by the provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<i32>,
+    /// Number of tokens generated by the model, if reported by the provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<i32>,
+    /// Time from sending the request to receiving the response
+    pub latency: Duration,
+}
+
+/// Tool call requested by the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptToolCall {
+    /// Identifier of the call, used to match it with its result
+    pub call_id: String,
+    /// Name of the called tool
+    pub name: String,
+    /// Arguments provided by the model
+    pub arguments: Value,
+}
+
+/// Result of a tool call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptToolResult {
+    /// Identifier of the call this result belongs to
+    pub call_id: String,
+    /// Content returned by the tool
+    pub content: String,
+}
+
+impl Generation {
+    pub(crate) fn new(model: &str, usage: &Usage, latency: Duration) -> Self {
+        Self {
+            model: model.to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            latency,
+        }
+    }
+}
+
+impl From<&ChatMessage> for Message {
+    fn from(msg: &ChatMessage) -> Self {
+        let role = match msg.role {
+            ChatRole::System => Role::System,
+            ChatRole::User => Role::User,
+            ChatRole::Assistant => Role::Assistant,
+            ChatRole::Tool => Role::Tool,
+        };
+        let mut message = Message {
+            role,
+            name: None,
+            content: String::new(),
+            tool_calls: vec![],
+            tool_results: vec![],
+            timestamp: None,
+            generation: None,
+        };
+        match &msg.content {
+            MessageContent::Text(text) => message.content = text.clone(),
+            MessageContent::Parts(parts) => {
+                message.content = parts
+                    .iter()
+                    .map(|part| match part {
+                        ContentPart::Text(text) => text.clone(),
+                        ContentPart::Image {
+                            content_type,
+                            source: ImageSource::Url(url),
+                        } => format!("[image {content_type}: {url}]"),
+                        ContentPart::Image { content_type, .. } => {
+                            format!("[image {content_type}]")
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            MessageContent::ToolCalls(calls) => {
+                message.tool_calls = calls
+                    .iter()
+                    .map(|call| TranscriptToolCall {
+                        call_id: call.call_id.clone(),
+                        name: call.fn_name.clone(),
+                        arguments: call.fn_arguments.clone(),
+                    })
+                    .collect()
+            }
+            MessageContent::ToolResponses(responses) => {
+                message.tool_results = responses
+                    .iter()
+                    .map(|response| TranscriptToolResult {
+                        call_id: response.call_id.clone(),
+                        content: response.content.clone(),
+                    })
+                    .collect()
+            }
+        }
+        message
+    }
+}
+
+impl<'a> FromIterator<&'a ChatMessage> for Transcript {
+    fn from_iter<T: IntoIterator<Item = &'a ChatMessage>>(iter: T) -> Self {
+        Self {
+            messages: iter.into_iter().map(Message::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genai::chat::{ToolCall, ToolResponse};
+    use serde_json::json;
+
+    #[test]
+    fn test_transcript_from_history() {
+        let history = [
+            ChatMessage::system("system"),
+            ChatMessage::user("question"),
+            ChatMessage::from(vec![ToolCall {
+                call_id: "call_1".to_string(),
+                fn_name: "echo".to_string(),
+                fn_arguments: json!({"text": "hello"}),
+            }]),
+            ChatMessage::from(ToolResponse::new("call_1", "hello")),
+            ChatMessage::assistant("answer"),
+        ];
+        let transcript: Transcript = history.iter().collect();
+
+        assert_eq!(transcript.messages.len(), 5);
+        assert_eq!(transcript.messages[0].role, Role::System);
+        assert_eq!(transcript.messages[1].content, "question");
+        assert_eq!(transcript.messages[2].role, Role::Assistant);
+        assert_eq!(transcript.messages[2].tool_calls[0].name, "echo");
+        assert_eq!(transcript.messages[3].role, Role::Tool);
+        assert_eq!(transcript.messages[3].tool_results[0].content, "hello");
+        assert_eq!(transcript.messages[4].content, "answer");
+
+        let json = serde_json::to_value(&transcript).unwrap();
+        assert_eq!(
+            json["messages"][0],
+            json!({"role": "system", "content": "system"})
+        );
+    }
+}
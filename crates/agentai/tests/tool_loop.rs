@@ -0,0 +1,170 @@
+//! End-to-end tests of the [`Agent::run`] tool loop against a local mock of the
+//! OpenAI-compatible chat completions API.
+
+use agentai::tool::{toolbox, Tool, ToolBox, ToolError, ToolResult};
+use agentai::Agent;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+/// Returns canned chat completions in order, repeating the last one
+struct Sequence {
+    responses: Vec<Value>,
+    next: AtomicUsize,
+}
+
+impl Respond for Sequence {
+    fn respond(&self, _request: &Request) -> ResponseTemplate {
+        let index = self.next.fetch_add(1, Ordering::SeqCst);
+        let response = &self.responses[index.min(self.responses.len() - 1)];
+        ResponseTemplate::new(200).set_body_json(response)
+    }
+}
+
+/// Starts a server answering chat completion requests with `responses`
+async fn chat_server(responses: Vec<Value>) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .respond_with(Sequence {
+            responses,
+            next: AtomicUsize::new(0),
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+fn agent(server: &MockServer) -> Agent {
+    Agent::new_with_url(&format!("{}/", server.uri()), "key", "You are a calculator")
+}
+
+/// Chat completion with a text answer
+fn answer(content: &str) -> Value {
+    json!({"choices": [{"message": {"role": "assistant", "content": content}}]})
+}
+
+/// Chat completion with calls of tools, given as `(call id, tool name, JSON arguments)`
+fn tool_calls(calls: &[(&str, &str, &str)]) -> Value {
+    let calls: Vec<Value> = calls
+        .iter()
+        .map(|(id, name, arguments)| {
+            json!({"id": id, "type": "function", "function": {"name": name, "arguments": arguments}})
+        })
+        .collect();
+    json!({"choices": [{"message": {"role": "assistant", "content": null, "tool_calls": calls}}]})
+}
+
+/// Bodies of chat completion requests received by the server
+async fn requests(server: &MockServer) -> Vec<Value> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| request.body_json().unwrap())
+        .collect()
+}
+
+struct CalculatorToolBox;
+
+#[toolbox]
+impl CalculatorToolBox {
+    /// Adds two numbers
+    #[tool]
+    fn add(&self, a: i64, b: i64) -> ToolResult {
+        Ok((a + b).to_string())
+    }
+}
+
+#[tokio::test]
+async fn test_tool_loop() {
+    let server = chat_server(vec![
+        tool_calls(&[
+            ("call_1", "add", r#"{"a": 2, "b": 3}"#),
+            ("call_2", "add", r#"{"a": 10, "b": -4}"#),
+        ]),
+        answer("The sums are 5 and 6"),
+    ])
+    .await;
+    let mut agent = agent(&server);
+    let answer: String = agent
+        .run(
+            "model",
+            "Add 2 and 3, then 10 and -4",
+            Some(&CalculatorToolBox),
+        )
+        .await
+        .unwrap();
+    assert_eq!(answer, "The sums are 5 and 6");
+
+    let requests = requests(&server).await;
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0]["tools"][0]["function"]["name"], "add");
+    // Tool results are sent back in the next request, after the calls
+    let messages = requests[1]["messages"].as_array().unwrap();
+    let roles: Vec<&str> = messages
+        .iter()
+        .map(|message| message["role"].as_str().unwrap())
+        .collect();
+    assert_eq!(roles, ["system", "user", "assistant", "tool", "tool"]);
+    assert_eq!(messages[3]["tool_call_id"], "call_1");
+    assert_eq!(messages[3]["content"], "5");
+    assert_eq!(messages[4]["tool_call_id"], "call_2");
+    assert_eq!(messages[4]["content"], "6");
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct Sum {
+    result: i64,
+}
+
+#[tokio::test]
+async fn test_tool_loop_structured_output() {
+    let server = chat_server(vec![
+        tool_calls(&[("call_1", "add", r#"{"a": 20, "b": 22}"#)]),
+        answer(r#"{"result": 42}"#),
+    ])
+    .await;
+    let mut agent = agent(&server);
+    let sum: Sum = agent
+        .run("model", "Add 20 and 22", Some(&CalculatorToolBox))
+        .await
+        .unwrap();
+    assert_eq!(sum.result, 42);
+
+    let requests = requests(&server).await;
+    assert_eq!(requests[1]["messages"][3]["content"], "42");
+}
+
+#[tokio::test]
+async fn test_tool_loop_invalid_calls() {
+    let server = chat_server(vec![
+        tool_calls(&[
+            ("call_1", "add", "[1, 2]"),
+            ("call_2", "multiply", r#"{"a": 2, "b": 3}"#),
+        ]),
+        answer("I could not calculate it"),
+    ])
+    .await;
+    let mut agent = agent(&server);
+    let answer: String = agent
+        .run("model", "Calculate", Some(&CalculatorToolBox))
+        .await
+        .unwrap();
+    assert_eq!(answer, "I could not calculate it");
+
+    // Failed calls are reported to the model, every call has its response
+    let requests = requests(&server).await;
+    let messages = requests[1]["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 5);
+    assert_eq!(messages[3]["tool_call_id"], "call_1");
+    assert_eq!(messages[4]["tool_call_id"], "call_2");
+    assert!(messages[4]["content"]
+        .as_str()
+        .unwrap()
+        .contains("multiply"));
+}